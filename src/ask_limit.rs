@@ -0,0 +1,74 @@
+//! Limit on the asks the web API keeps waiting for the swarm at once
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Weight of the newest ask in the running average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Slots for pending asks, with the running average of how long asks take
+#[derive(Debug)]
+pub struct AskLimit {
+    slots: Arc<Semaphore>,
+    max: usize,
+    /// Average latency in milliseconds, as `f64` bits; 0 until an ask finishes
+    average_ms: AtomicU64,
+}
+
+impl AskLimit {
+    /// Allow up to `max` asks to be pending at once
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            slots: Arc::new(Semaphore::new(max)),
+            max,
+            average_ms: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    /// Take a slot for an ask, held until the permit is dropped, or `None`
+    /// if every slot is taken
+    pub fn try_start(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    /// Record how long a finished ask took
+    pub fn finished(&self, elapsed: Duration) {
+        let sample = elapsed.as_secs_f64() * 1000.0;
+        let _ = self
+            .average_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let average = f64::from_bits(bits);
+                let next = if average == 0.0 {
+                    sample
+                } else {
+                    average + LATENCY_WEIGHT * (sample - average)
+                };
+                Some(next.to_bits())
+            });
+    }
+
+    /// Number of asks pending now
+    pub fn pending(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+
+    /// Most asks allowed to be pending at once
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Seconds a refused client should wait: the time for the pending asks to
+    /// clear at the average latency, spread over the slots, and at least 1
+    pub fn retry_after_secs(&self) -> u64 {
+        let average = f64::from_bits(self.average_ms.load(Ordering::Relaxed)) / 1000.0;
+        let wait = average * self.pending() as f64 / self.max as f64;
+        wait.ceil().max(1.0) as u64
+    }
+}
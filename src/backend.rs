@@ -0,0 +1,529 @@
+//! The model server a Leader runs requests on
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ollama::{
+        ChatReply, Generation, GenerationChunk, ModelDetails, ModelInfo, OllamaClient,
+        OllamaOptions, OutputFormat, PromptFormat, PullProgress, RunningModel,
+    },
+    protocol::{ChatMessage, ChatRole},
+};
+
+/// Operations a Leader needs from its inference server
+///
+/// Errors are classified for Subordinates with [`crate::ollama::error_code`],
+/// so implementations should report failures with the error types it knows.
+/// Generation and chat calls stop with [`crate::ollama::Cancelled`] once
+/// their `cancel` token fires.
+#[async_trait]
+pub trait InferenceBackend: Send + Sync {
+    /// Where the backend is reached, for logs
+    fn endpoint(&self) -> &str;
+
+    /// Check that the backend is answering, returning its name and version
+    async fn health(&self) -> Result<String>;
+
+    /// List the models available locally
+    async fn list_models(&self) -> Result<Vec<ModelInfo>>;
+
+    /// List the models currently loaded in memory
+    async fn running_models(&self) -> Result<Vec<RunningModel>>;
+
+    /// Look up the metadata of `model`
+    async fn show_model(&self, model: String) -> Result<ModelDetails>;
+
+    /// Load `model` into memory without generating anything
+    async fn load_model(&self, model: String) -> Result<()>;
+
+    /// Download `model`, calling `on_progress` for each update
+    async fn pull_model(
+        &self,
+        model: String,
+        on_progress: Box<dyn for<'p> FnMut(&'p PullProgress) + Send>,
+    ) -> Result<()>;
+
+    /// Generate a completion of `prompt`
+    async fn generate(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<Generation>;
+
+    /// Generate a completion of `prompt`, yielding tokens as they are produced
+    async fn generate_stream(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>>;
+
+    /// Get the assistant's reply to a conversation, in the `output` format if set
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<ChatReply>;
+
+    /// Like [`chat`](Self::chat), but yield the reply's tokens as they are produced
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>>;
+
+    /// Compute the embedding vector for a single input
+    async fn embed(&self, input: String, model: String) -> Result<Vec<f32>>;
+
+    /// Count the tokens `content` takes for `model`
+    async fn tokenize(&self, content: String, model: String) -> Result<u64>;
+}
+
+#[async_trait]
+impl InferenceBackend for OllamaClient {
+    fn endpoint(&self) -> &str {
+        self.base_url()
+    }
+
+    async fn health(&self) -> Result<String> {
+        let version = OllamaClient::health(self).await?;
+        Ok(format!("Ollama {}", version))
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        OllamaClient::list_models(self).await
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        OllamaClient::ps(self).await
+    }
+
+    async fn show_model(&self, model: String) -> Result<ModelDetails> {
+        OllamaClient::show_model(self, model).await
+    }
+
+    async fn load_model(&self, model: String) -> Result<()> {
+        OllamaClient::load_model(self, model).await
+    }
+
+    async fn pull_model(
+        &self,
+        model: String,
+        on_progress: Box<dyn for<'p> FnMut(&'p PullProgress) + Send>,
+    ) -> Result<()> {
+        OllamaClient::pull_model(self, model, on_progress).await
+    }
+
+    async fn generate(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<Generation> {
+        OllamaClient::generate(self, prompt, model, images, options, format, cancel).await
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        OllamaClient::generate_stream(self, prompt, model, images, options, format, cancel).await
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<ChatReply> {
+        OllamaClient::chat(self, messages, model, options, output, cancel).await
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        OllamaClient::chat_stream(self, messages, model, options, output, cancel).await
+    }
+
+    async fn embed(&self, input: String, model: String) -> Result<Vec<f32>> {
+        OllamaClient::embed(self, input, model).await
+    }
+
+    async fn tokenize(&self, content: String, model: String) -> Result<u64> {
+        OllamaClient::tokenize(self, content, model).await
+    }
+}
+
+/// Backend for testing the network without a model: echoes prompts back
+///
+/// Only `models` are listed as available, like a Leader serving those models.
+pub struct DryRunBackend {
+    models: Vec<String>,
+}
+
+impl DryRunBackend {
+    pub fn new(models: Vec<String>) -> Self {
+        Self { models }
+    }
+
+    /// The canned answer to `prompt` from `model`
+    fn echo(model: &str, prompt: &str) -> String {
+        format!("[dry-run] {} received: {}", model, prompt)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for DryRunBackend {
+    fn endpoint(&self) -> &str {
+        "dry-run"
+    }
+
+    async fn health(&self) -> Result<String> {
+        Ok("Dry-run backend".to_string())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .models
+            .iter()
+            .map(|name| ModelInfo {
+                name: name.clone(),
+                size: 0,
+                modified_at: String::new(),
+            })
+            .collect())
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        Ok(Vec::new())
+    }
+
+    async fn show_model(&self, _model: String) -> Result<ModelDetails> {
+        Ok(ModelDetails::default())
+    }
+
+    async fn load_model(&self, _model: String) -> Result<()> {
+        Ok(())
+    }
+
+    async fn pull_model(
+        &self,
+        _model: String,
+        _on_progress: Box<dyn for<'p> FnMut(&'p PullProgress) + Send>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn generate(
+        &self,
+        prompt: String,
+        model: String,
+        _images: Vec<String>,
+        _options: Option<OllamaOptions>,
+        _format: PromptFormat,
+        _cancel: &CancellationToken,
+    ) -> Result<Generation> {
+        Ok(Generation {
+            response: Self::echo(&model, &prompt),
+            stats: None,
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: String,
+        model: String,
+        _images: Vec<String>,
+        _options: Option<OllamaOptions>,
+        _format: PromptFormat,
+        _cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Ok(GenerationChunk::Token(Self::echo(&model, &prompt))))
+            .await?;
+        tx.send(Ok(GenerationChunk::Done { stats: None })).await?;
+        Ok(rx)
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        _options: Option<OllamaOptions>,
+        _output: Option<OutputFormat>,
+        _cancel: &CancellationToken,
+    ) -> Result<ChatReply> {
+        let prompt = messages.last().map_or("", |message| &message.content);
+        Ok(ChatReply {
+            message: ChatMessage {
+                role: ChatRole::Assistant,
+                content: Self::echo(&model, prompt),
+            },
+            stats: None,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        _options: Option<OllamaOptions>,
+        _output: Option<OutputFormat>,
+        _cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        let prompt = messages.last().map_or("", |message| &message.content);
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Ok(GenerationChunk::Token(Self::echo(&model, prompt))))
+            .await?;
+        tx.send(Ok(GenerationChunk::Done { stats: None })).await?;
+        Ok(rx)
+    }
+
+    async fn embed(&self, _input: String, _model: String) -> Result<Vec<f32>> {
+        Ok(Vec::new())
+    }
+
+    async fn tokenize(&self, _content: String, _model: String) -> Result<u64> {
+        anyhow::bail!("Dry run can't tokenize")
+    }
+}
+
+/// Backend for tests: answers every prompt with a canned reply, and fails
+/// the next calls with errors queued by [`fail_next`](Self::fail_next)
+#[cfg(test)]
+pub struct MockBackend {
+    models: Vec<String>,
+    reply: String,
+    context_length: Option<u64>,
+    /// How long each reply takes after its first token
+    latency: std::time::Duration,
+    failures: std::sync::Mutex<std::collections::VecDeque<crate::ollama::BackendError>>,
+    prompts: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl MockBackend {
+    /// Serve `models`, answering every prompt with `reply`
+    pub fn new(models: &[&str], reply: &str) -> Self {
+        Self {
+            models: models.iter().map(|model| model.to_string()).collect(),
+            reply: reply.to_string(),
+            context_length: None,
+            latency: std::time::Duration::ZERO,
+            failures: Default::default(),
+            prompts: Default::default(),
+        }
+    }
+
+    /// Report a context window of `tokens` for every model
+    pub fn with_context_length(mut self, tokens: u64) -> Self {
+        self.context_length = Some(tokens);
+        self
+    }
+
+    /// Take `latency` to finish each generation or chat reply
+    pub fn with_latency(mut self, latency: std::time::Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Fail the next generation, chat or embedding call with `code`
+    pub fn fail_next(&self, code: crate::protocol::ErrorCode, message: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .push_back(crate::ollama::BackendError {
+                code,
+                message: message.to_string(),
+            });
+    }
+
+    /// The prompts and embedding inputs the backend was handed, oldest first
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+
+    /// Record a call for `prompt`, failing it if a failure is queued
+    fn call(&self, prompt: &str) -> Result<()> {
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        match self.failures.lock().unwrap().pop_front() {
+            Some(failure) => Err(failure.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait out the latency, failing with [`crate::ollama::Cancelled`] if
+    /// `cancel` fires first
+    async fn finish(&self, cancel: &CancellationToken) -> Result<String> {
+        let latency = self.latency;
+        crate::ollama::cancellable(cancel, async move {
+            tokio::time::sleep(latency).await;
+            Ok(())
+        })
+        .await?;
+        Ok(self.reply.clone())
+    }
+
+    /// Stream the reply as one token, then finish once the latency is over
+    fn stream(&self, cancel: &CancellationToken) -> mpsc::Receiver<Result<GenerationChunk>> {
+        let (tx, rx) = mpsc::channel(2);
+        tx.try_send(Ok(GenerationChunk::Token(self.reply.clone())))
+            .unwrap();
+        let (latency, cancel) = (self.latency, cancel.clone());
+        tokio::spawn(async move {
+            let finished = crate::ollama::cancellable(&cancel, async move {
+                tokio::time::sleep(latency).await;
+                Ok(GenerationChunk::Done { stats: None })
+            })
+            .await;
+            let _ = tx.send(finished).await;
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl InferenceBackend for MockBackend {
+    fn endpoint(&self) -> &str {
+        "mock"
+    }
+
+    async fn health(&self) -> Result<String> {
+        Ok("Mock backend".to_string())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(self
+            .models
+            .iter()
+            .map(|name| ModelInfo {
+                name: name.clone(),
+                size: 0,
+                modified_at: String::new(),
+            })
+            .collect())
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        Ok(Vec::new())
+    }
+
+    async fn show_model(&self, _model: String) -> Result<ModelDetails> {
+        Ok(ModelDetails {
+            context_length: self.context_length,
+            ..ModelDetails::default()
+        })
+    }
+
+    async fn load_model(&self, _model: String) -> Result<()> {
+        Ok(())
+    }
+
+    async fn pull_model(
+        &self,
+        _model: String,
+        _on_progress: Box<dyn for<'p> FnMut(&'p PullProgress) + Send>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn generate(
+        &self,
+        prompt: String,
+        _model: String,
+        _images: Vec<String>,
+        _options: Option<OllamaOptions>,
+        _format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<Generation> {
+        self.call(&prompt)?;
+        Ok(Generation {
+            response: self.finish(cancel).await?,
+            stats: None,
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: String,
+        _model: String,
+        _images: Vec<String>,
+        _options: Option<OllamaOptions>,
+        _format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        self.call(&prompt)?;
+        Ok(self.stream(cancel))
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model: String,
+        _options: Option<OllamaOptions>,
+        _output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<ChatReply> {
+        self.call(messages.last().map_or("", |message| &message.content))?;
+        Ok(ChatReply {
+            message: ChatMessage {
+                role: ChatRole::Assistant,
+                content: self.finish(cancel).await?,
+            },
+            stats: None,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model: String,
+        _options: Option<OllamaOptions>,
+        _output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        self.call(messages.last().map_or("", |message| &message.content))?;
+        Ok(self.stream(cancel))
+    }
+
+    async fn embed(&self, input: String, _model: String) -> Result<Vec<f32>> {
+        self.call(&input)?;
+        Ok(vec![input.chars().count() as f32])
+    }
+
+    async fn tokenize(&self, content: String, _model: String) -> Result<u64> {
+        Ok(content.split_whitespace().count() as u64)
+    }
+}
@@ -0,0 +1,111 @@
+//! Latency and throughput measurements for the `bench` subcommand
+
+use libp2p::PeerId;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+/// Outcome of one benchmark request
+#[derive(Debug)]
+pub struct Sample {
+    pub leader: PeerId,
+    pub latency: Duration,
+    pub success: bool,
+}
+
+/// Summary of a benchmark run
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub requests: usize,
+    pub concurrency: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub elapsed_secs: f64,
+    /// Completed requests per second, successful or not
+    pub throughput: f64,
+    /// Latency percentiles of successful requests
+    pub latency_ms: Percentiles,
+    /// Requests answered by each Leader, keyed by PeerId
+    pub per_leader: BTreeMap<String, usize>,
+}
+
+/// Latency distribution in milliseconds
+#[derive(Debug, Default, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl BenchReport {
+    /// Summarise the samples of a run that took `elapsed`
+    pub fn new(samples: &[Sample], concurrency: usize, elapsed: Duration) -> Self {
+        let mut latencies: Vec<f64> = samples
+            .iter()
+            .filter(|sample| sample.success)
+            .map(|sample| sample.latency.as_secs_f64() * 1000.0)
+            .collect();
+        latencies.sort_by(f64::total_cmp);
+
+        let mut per_leader = BTreeMap::new();
+        for sample in samples {
+            *per_leader.entry(sample.leader.to_string()).or_insert(0) += 1;
+        }
+
+        let elapsed_secs = elapsed.as_secs_f64();
+        let throughput = if elapsed_secs > 0.0 {
+            samples.len() as f64 / elapsed_secs
+        } else {
+            0.0
+        };
+
+        Self {
+            requests: samples.len(),
+            concurrency,
+            succeeded: latencies.len(),
+            failed: samples.len() - latencies.len(),
+            elapsed_secs,
+            throughput,
+            latency_ms: Percentiles {
+                p50: percentile(&latencies, 0.50),
+                p95: percentile(&latencies, 0.95),
+                p99: percentile(&latencies, 0.99),
+                max: latencies.last().copied().unwrap_or(0.0),
+            },
+            per_leader,
+        }
+    }
+}
+
+/// Nearest-rank percentile of an ascending list, 0 when empty
+fn percentile(sorted: &[f64], quantile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "📈 {} request(s) at concurrency {} in {:.2}s ({:.2} req/s)",
+            self.requests, self.concurrency, self.elapsed_secs, self.throughput
+        )?;
+        writeln!(
+            f,
+            "✅ {} succeeded, ❌ {} failed",
+            self.succeeded, self.failed
+        )?;
+        writeln!(
+            f,
+            "⏱️ Latency p50 {:.0}ms, p95 {:.0}ms, p99 {:.0}ms, max {:.0}ms",
+            self.latency_ms.p50, self.latency_ms.p95, self.latency_ms.p99, self.latency_ms.max
+        )?;
+        for (leader, count) in &self.per_leader {
+            writeln!(f, "🖥️ {}: {} request(s)", leader, count)?;
+        }
+        Ok(())
+    }
+}
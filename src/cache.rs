@@ -0,0 +1,199 @@
+//! Answers a Leader remembers for repeated inference requests
+
+use lru::LruCache;
+use std::{
+    hash::{DefaultHasher, Hasher},
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::protocol::{AxonRequest, InferenceResponse};
+
+/// Successful inference responses, keyed by a hash of everything that shapes
+/// the answer
+///
+/// Each entry keeps the request shape it was stored under, so two requests
+/// whose hashes collide never get each other's answer.
+#[derive(Debug)]
+pub struct ResponseCache {
+    entries: Mutex<LruCache<u64, Entry>>,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    shape: Vec<u8>,
+    stored: Instant,
+    response: InferenceResponse,
+}
+
+/// What a request is cached under: the serialized fields that shape its
+/// answer, and their hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    hash: u64,
+    shape: Vec<u8>,
+}
+
+impl ResponseCache {
+    /// Keep up to `size` answers, each for at most `ttl`, evicting the least
+    /// recently used first
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        let size = NonZeroUsize::new(size).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(size)),
+            ttl,
+        }
+    }
+
+    /// The cache key for `request`, or `None` if its answer can't be reused
+    ///
+    /// Only inference requests outside a session qualify, since a session's
+    /// history changes with every turn. The key covers the model, with
+    /// `default_model` standing in for none, the prompt, images, conversation,
+    /// options and prompt format; the id, priority and deadline don't count.
+    pub fn key(request: &AxonRequest, default_model: &str) -> Option<CacheKey> {
+        let AxonRequest::Inference(request) = request else {
+            return None;
+        };
+        if request.session_id.is_some() {
+            return None;
+        }
+        let model = request.model.as_deref().unwrap_or(default_model);
+        let shape = serde_json::to_vec(&(
+            model,
+            &request.prompt,
+            &request.images,
+            &request.messages,
+            &request.options,
+            &request.format,
+        ))
+        .ok()?;
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&shape);
+        Some(CacheKey {
+            hash: hasher.finish(),
+            shape,
+        })
+    }
+
+    /// The answer stored under `key`, if there is one younger than the TTL
+    pub fn get(&self, key: &CacheKey) -> Option<InferenceResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key.hash)?;
+        if entry.stored.elapsed() >= self.ttl {
+            entries.pop(&key.hash);
+            return None;
+        }
+        // Another request with the same hash
+        if entry.shape != key.shape {
+            return None;
+        }
+        Some(entry.response.clone())
+    }
+
+    /// Remember a successful answer under `key`
+    pub fn insert(&self, key: &CacheKey, response: &InferenceResponse) {
+        if response.success {
+            self.entries.lock().unwrap().put(
+                key.hash,
+                Entry {
+                    shape: key.shape.clone(),
+                    stored: Instant::now(),
+                    response: response.clone(),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ollama::PromptFormat, protocol::InferenceRequest};
+
+    fn request(prompt: &str) -> AxonRequest {
+        AxonRequest::Inference(InferenceRequest {
+            prompt: prompt.to_string(),
+            model: None,
+            images: Vec::new(),
+            messages: None,
+            priority: None,
+            session_id: None,
+            options: None,
+            format: PromptFormat::default(),
+            request_id: None,
+            timeout_secs: None,
+        })
+    }
+
+    fn answer(text: &str) -> InferenceResponse {
+        InferenceResponse {
+            response: text.to_string(),
+            success: true,
+            error: None,
+            error_code: None,
+            stats: None,
+            session_id: None,
+            request_id: None,
+        }
+    }
+
+    fn key(prompt: &str) -> CacheKey {
+        ResponseCache::key(&request(prompt), "llama3").unwrap()
+    }
+
+    #[test]
+    fn identical_request_gets_the_stored_answer() {
+        let cache = ResponseCache::new(8, Duration::from_secs(60));
+        cache.insert(&key("Hello"), &answer("Hi there"));
+
+        assert_eq!(cache.get(&key("Hello")).unwrap().response, "Hi there");
+        assert!(cache.get(&key("Goodbye")).is_none());
+    }
+
+    #[test]
+    fn colliding_hash_does_not_return_another_prompts_answer() {
+        let cache = ResponseCache::new(8, Duration::from_secs(60));
+        let stored = key("Hello");
+        cache.insert(&stored, &answer("Hi there"));
+
+        let colliding = CacheKey {
+            hash: stored.hash,
+            ..key("Goodbye")
+        };
+
+        assert!(cache.get(&colliding).is_none());
+        assert!(cache.get(&stored).is_some());
+    }
+
+    #[test]
+    fn answer_expires_after_the_ttl() {
+        let cache = ResponseCache::new(8, Duration::from_millis(20));
+        cache.insert(&key("Hello"), &answer("Hi there"));
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert!(cache.get(&key("Hello")).is_none());
+    }
+
+    #[test]
+    fn failures_and_sessions_are_not_cached() {
+        let cache = ResponseCache::new(8, Duration::from_secs(60));
+        let failed = InferenceResponse {
+            success: false,
+            ..answer("")
+        };
+        cache.insert(&key("Hello"), &failed);
+
+        let AxonRequest::Inference(mut in_session) = request("Hello") else {
+            unreachable!()
+        };
+        in_session.session_id = Some("chat".to_string());
+
+        assert!(cache.get(&key("Hello")).is_none());
+        assert!(ResponseCache::key(&AxonRequest::Inference(in_session), "llama3").is_none());
+    }
+}
@@ -2,51 +2,711 @@
 
 use anyhow::Result;
 use clap::Parser;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, path::PathBuf};
+
+use crate::protocol::DEFAULT_MAX_MESSAGE_SIZE;
 
 #[derive(Debug, Parser)]
 #[command(name = "axon_cluster")]
 #[command(about = "Axon-Cluster: Private P2P AI Inference Network", long_about = None)]
 pub struct Args {
+    /// TOML file of settings; flags and environment variables override it
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Path to this node's identity keypair (created if missing)
+    #[arg(long, global = true, default_value = "./node.key")]
+    pub identity_path: PathBuf,
+
+    /// Log output format (filter with RUST_LOG)
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// More log output, overriding RUST_LOG: -v debug, -vv trace, -vvv trace
+    /// from libraries too (default: info, or RUST_LOG if set; there is no warn
+    /// step, use RUST_LOG=warn for that)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only log errors, overriding RUST_LOG
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
     #[command(subcommand)]
     pub mode: Mode,
 }
 
+/// Peer connectivity settings shared by every mode
+#[derive(Debug, Clone, clap::Args, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkArgs {
+    /// Address of a peer to connect to without mDNS, e.g. across subnets (repeatable)
+    #[arg(long = "peer")]
+    pub peers: Vec<Multiaddr>,
+
+    /// Address to listen on for peers (repeatable); the default is every
+    /// IPv4 and IPv6 interface on a random port, for each --transport
+    #[arg(long)]
+    pub listen: Vec<Multiaddr>,
+
+    /// Transports to connect to peers over; QUIC connections are not limited
+    /// to holders of swarm.key, see the README before enabling it
+    #[arg(long, value_enum, default_value_t = TransportKind::Tcp)]
+    pub transport: TransportKind,
+
+    /// Seconds between keep-alive pings to connected peers
+    #[arg(long, default_value_t = 15)]
+    pub ping_interval_secs: u64,
+
+    /// Consecutive failed pings after which a peer is forgotten
+    #[arg(long, default_value_t = 3)]
+    pub max_ping_failures: u32,
+
+    /// Seconds a connection may sit unused before it is closed
+    #[arg(long = "idle-timeout", default_value_t = 60)]
+    pub idle_timeout_secs: u64,
+
+    /// Seconds to wait for a peer to answer a request, including the whole generation
+    #[arg(long = "request-timeout", default_value_t = 120)]
+    pub request_timeout_secs: u64,
+
+    /// Largest message accepted from a peer, in bytes (default: 16 MiB)
+    #[arg(long, default_value_t = DEFAULT_MAX_MESSAGE_SIZE)]
+    pub max_message_size: usize,
+}
+
+/// Transports a node connects to peers over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransportKind {
+    /// TCP, admitting only holders of swarm.key
+    Tcp,
+    /// QUIC over UDP, with its own encryption and no swarm.key check
+    Quic,
+    /// TCP and QUIC
+    Both,
+}
+
+impl TransportKind {
+    pub fn tcp(self) -> bool {
+        self != TransportKind::Quic
+    }
+
+    pub fn quic(self) -> bool {
+        self != TransportKind::Tcp
+    }
+}
+
+/// How log events are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
 #[derive(Debug, Parser)]
 pub enum Mode {
     /// Leader mode: Listen for inference requests and process them with Ollama
     #[command(name = "serve")]
     Serve {
-        /// Ollama API endpoint (default: http://127.0.0.1:11434)
-        #[arg(long, default_value = "http://127.0.0.1:11434")]
-        ollama_url: String,
-
-        /// Model name to use (default: qwen:0.5b)
-        #[arg(long, default_value = "qwen:0.5b")]
-        model: String,
+        #[command(flatten)]
+        leader: LeaderArgs,
     },
 
     /// Web mode: Start Leader with HTTP API for web interface
     #[command(name = "web")]
     Web {
-        /// Ollama API endpoint (default: http://127.0.0.1:11434)
-        #[arg(long, default_value = "http://127.0.0.1:11434")]
-        ollama_url: String,
-
-        /// Model name to use (default: qwen:0.5b)
-        #[arg(long, default_value = "qwen:0.5b")]
-        model: String,
+        #[command(flatten)]
+        leader: LeaderArgs,
     },
 
     /// Subordinate mode: Send an inference request to the Leader
     #[command(name = "ask")]
     Ask {
         /// The prompt to send for inference
-        prompt: String,
+        #[arg(required_unless_present = "batch_file", conflicts_with = "batch_file")]
+        prompt: Option<String>,
+
+        /// File with one prompt per line to run as a batch, printing JSON lines
+        #[arg(long, conflicts_with = "images")]
+        batch_file: Option<PathBuf>,
+
+        /// Image file to attach for vision models (repeatable)
+        #[arg(long = "image")]
+        images: Vec<PathBuf>,
+
+        /// Scheduling priority on the Leader, higher is served first
+        #[arg(long)]
+        priority: Option<u8>,
+
+        /// Continue (or start) a conversation the Leader remembers
+        #[arg(long = "session", conflicts_with = "batch_file")]
+        session_id: Option<String>,
+
+        /// Only count the prompt's tokens and show the model's context window
+        #[arg(long, conflicts_with_all = ["batch_file", "images"])]
+        count_tokens: bool,
+
+        /// Ollama model option such as temperature=0.2 or stop='["\n"]' (repeatable)
+        #[arg(
+            long = "option",
+            value_name = "KEY=VALUE",
+            conflicts_with = "count_tokens"
+        )]
+        model_options: Vec<String>,
+
+        /// System prompt, replacing the Leader's default
+        #[arg(long, conflicts_with_all = ["batch_file", "count_tokens"])]
+        system: Option<String>,
+
+        /// Send the prompt to the model as-is, without its prompt template
+        #[arg(long, conflicts_with_all = ["batch_file", "count_tokens"])]
+        raw: bool,
+
+        /// Make the model answer in JSON: "json", or a file with a JSON schema to follow
+        #[arg(long, conflicts_with_all = ["batch_file", "count_tokens"])]
+        format: Option<String>,
+
+        /// Print only a JSON object with the answer, answering Leader and latency,
+        /// or the error; logs still go to stderr
+        #[arg(long, conflicts_with_all = ["batch_file", "count_tokens"])]
+        json: bool,
+
+        #[command(flatten)]
+        options: RequestOptions,
+    },
+
+    /// Subordinate mode: Ask the Leader for embeddings and print them as JSON
+    #[command(name = "embed")]
+    Embed {
+        /// One or more inputs to embed
+        #[arg(required = true)]
+        input: Vec<String>,
+
+        /// Model name to use (default: the Leader's model)
+        #[arg(long)]
+        model: Option<String>,
+
+        #[command(flatten)]
+        options: RequestOptions,
+    },
+
+    /// Subordinate mode: Interactive multi-turn chat with a Leader
+    #[command(name = "chat")]
+    Chat {
+        /// Model name to use (default: the Leader's model)
+        #[arg(long)]
+        model: Option<String>,
+
+        /// Optional system prompt that starts the conversation
+        #[arg(long)]
+        system: Option<String>,
+    },
+
+    /// Subordinate mode: Run every prompt in a file across the discovered Leaders,
+    /// printing results as JSON lines in file order
+    #[command(name = "ask-batch")]
+    AskBatch {
+        #[command(flatten)]
+        batch: AskBatchArgs,
+    },
+
+    /// Subordinate mode: Measure latency and throughput across the discovered Leaders
+    #[command(name = "bench")]
+    Bench {
+        #[command(flatten)]
+        bench: BenchArgs,
     },
+
+    /// Send an administration command to a Leader
+    #[command(name = "admin")]
+    Admin {
+        /// PeerId of the Leader to administer
+        #[arg(long)]
+        peer: PeerId,
+
+        /// Seconds to wait for the Leader to be discovered
+        #[arg(long, default_value_t = 30)]
+        discovery_timeout_secs: u64,
+
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+
+    /// Print this node's PeerId and listen addresses, one multiaddr per line
+    #[command(name = "peer-info")]
+    PeerInfo,
+
+    /// Generate a new pre-shared key for a private network
+    #[command(name = "keygen")]
+    Keygen {
+        /// File to write the key to
+        #[arg(long, default_value = "./swarm.key")]
+        output: PathBuf,
+
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the most recent requests recorded by a Leader's --history-db
+    #[command(name = "history")]
+    History {
+        /// SQLite database written by the Leader
+        #[arg(long)]
+        history_db: PathBuf,
+
+        /// Number of entries to print, newest first
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Print one JSON object per line instead of text
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Inspect the settings loaded from --config
+    #[command(name = "config")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// Configuration commands
+#[derive(Debug, clap::Subcommand)]
+pub enum ConfigCommand {
+    /// Print a Leader's effective settings as TOML, after applying flags,
+    /// environment variables and --config
+    Print {
+        #[command(flatten)]
+        leader: LeaderArgs,
+    },
+}
+
+/// Administration commands understood by Leaders
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+pub enum AdminCommand {
+    /// Print the Leader's activity as JSON
+    Stats,
+    /// Make the Leader stop accepting new requests
+    Drain,
+}
+
+/// Settings for an `ask-batch` run
+#[derive(Debug, Clone, clap::Args)]
+pub struct AskBatchArgs {
+    /// File with one prompt per line, or a JSON array of prompts
+    pub file: PathBuf,
+
+    /// Number of prompts kept in flight at once, spread across Leaders
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// Model name to use (default: the Leader's model)
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Seconds to wait for the first Leader before giving up
+    #[arg(long, default_value_t = 30)]
+    pub discovery_timeout_secs: u64,
+}
+
+/// Settings for a benchmark run
+#[derive(Debug, Clone, clap::Args)]
+pub struct BenchArgs {
+    /// The prompt sent with every request
+    pub prompt: String,
+
+    /// Total number of inference requests to send
+    #[arg(long, default_value_t = 100)]
+    pub requests: usize,
+
+    /// Number of requests kept in flight at once
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// Model name to use (default: the Leader's model)
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Seconds to wait for the first Leader before giving up
+    #[arg(long, default_value_t = 30)]
+    pub discovery_timeout_secs: u64,
+
+    /// Seconds to keep discovering Leaders after the first one is found
+    #[arg(long, default_value_t = 3)]
+    pub warmup_secs: u64,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Settings shared by the Leader modes
+#[derive(Debug, Clone, clap::Args, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderArgs {
+    /// Kind of inference server to send requests to
+    #[arg(long, value_enum, default_value_t = BackendKind::Ollama)]
+    pub backend: BackendKind,
+
+    /// Root of an OpenAI-compatible API, including its version (default: http://127.0.0.1:8080/v1)
+    #[arg(long, default_value = "http://127.0.0.1:8080/v1")]
+    pub backend_url: String,
+
+    /// Bearer token sent to the OpenAI-compatible API (default: $OPENAI_API_KEY,
+    /// if set); the web API's own key is --http-api-key
+    #[arg(long)]
+    pub backend_api_key: Option<String>,
+
+    /// Deprecated name of --backend-api-key, from before the web API had keys
+    #[arg(long, hide = true, conflicts_with = "backend_api_key")]
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+
+    /// Ollama API endpoint (default: http://127.0.0.1:11434)
+    #[arg(long, default_value = "http://127.0.0.1:11434")]
+    pub ollama_url: String,
+
+    /// Model name to use (default: qwen:0.5b)
+    #[arg(long, default_value = "qwen:0.5b")]
+    pub model: String,
+
+    /// Serve only these models, comma-separated; the first is the default in
+    /// place of --model (default: any model Ollama has)
+    #[arg(long, value_delimiter = ',', conflicts_with = "model")]
+    pub models: Vec<String>,
+
+    /// Bearer token sent to Ollama, e.g. behind an authenticating proxy
+    /// (default: $OLLAMA_API_KEY, if set)
+    #[arg(long)]
+    pub ollama_api_key: Option<String>,
+
+    /// Extra HTTP header sent with every Ollama call, as NAME=VALUE (repeatable)
+    #[arg(long = "ollama-header", value_name = "NAME=VALUE")]
+    pub ollama_headers: Vec<String>,
+
+    /// Seconds to wait for a connection to Ollama
+    #[arg(long, default_value_t = 5)]
+    pub ollama_connect_timeout_secs: u64,
+
+    /// Seconds a non-streaming Ollama call may take in total
+    #[arg(long, default_value_t = 110)]
+    pub ollama_timeout_secs: u64,
+
+    /// Seconds to wait for the next part of an Ollama response
+    #[arg(long, default_value_t = 60)]
+    pub ollama_read_timeout_secs: u64,
+
+    /// Tries per Ollama call before giving up on connection errors and 5xx responses
+    #[arg(long, default_value_t = 3)]
+    pub ollama_max_attempts: u32,
+
+    /// Milliseconds before the first Ollama retry, doubled for each further one
+    #[arg(long, default_value_t = 250)]
+    pub ollama_retry_backoff_ms: u64,
+
+    /// Pull --model through Ollama at startup if it isn't available yet
+    #[arg(long)]
+    pub pull_missing: bool,
+
+    /// Pull any model a request asks for that Ollama doesn't have, then serve it
+    #[arg(long)]
+    pub auto_pull: bool,
+
+    /// Seconds an automatic pull may take before its requests fail
+    #[arg(long, default_value_t = 1800)]
+    pub auto_pull_timeout_secs: u64,
+
+    /// System prompt for requests that don't set their own
+    #[arg(long)]
+    pub system: Option<String>,
+
+    /// How long Ollama keeps --model loaded after a request, e.g. "30m" or -1 for
+    /// forever; use MODEL=DURATION for another model (repeatable)
+    #[arg(
+        long = "keep-alive",
+        value_name = "[MODEL=]DURATION",
+        allow_hyphen_values = true
+    )]
+    pub keep_alive: Vec<String>,
+
+    /// SQLite database to record served inference requests in (default: none)
+    #[arg(long)]
+    pub history_db: Option<PathBuf>,
+
+    /// Address to serve Prometheus metrics on at /metrics, e.g. 127.0.0.1:9100
+    /// (the web API always serves them)
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Answer every request by echoing its prompt instead of calling Ollama,
+    /// for testing discovery and routing without a model
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Start even if Ollama isn't reachable yet, e.g. when it is started later
+    #[arg(long)]
+    pub no_preflight: bool,
+
+    /// Skip loading the served models into memory at startup, which makes the
+    /// first request to each model slow
+    #[arg(long)]
+    pub no_warmup: bool,
+
+    /// JSON file of default Ollama options per model, e.g. {"llama2": {"num_ctx": 4096}}
+    #[arg(long)]
+    pub model_options: Option<PathBuf>,
+
+    /// Longest accepted prompt, in characters; a conversation counts as a
+    /// whole (default: 500000)
+    #[arg(long, default_value_t = 500_000)]
+    pub max_prompt_chars: usize,
+
+    /// Largest accepted image attachment, in bytes (default: 8 MiB)
+    #[arg(long, default_value_t = 8 * 1024 * 1024)]
+    pub max_image_bytes: usize,
+
+    /// File of base58 PeerIds allowed to send requests, one per line (default: any peer)
+    #[arg(long)]
+    pub peer_allowlist: Option<PathBuf>,
+
+    /// File of base58 PeerIds allowed to send admin commands (default: none)
+    #[arg(long)]
+    pub admin_allowlist: Option<PathBuf>,
+
+    /// Highest request priority honoured as-is
+    #[arg(long, default_value_t = u8::MAX)]
+    pub max_priority: u8,
+
+    /// What to do with requests above --max-priority
+    #[arg(long, value_enum, default_value_t = PriorityPolicy::Clamp)]
+    pub over_max_priority: PriorityPolicy,
+
+    /// Seconds an idle conversation session is kept
+    #[arg(long, default_value_t = 30 * 60)]
+    pub session_ttl_secs: u64,
+
+    /// Maximum number of conversation sessions kept, least recently used evicted first
+    #[arg(long, default_value_t = 1000)]
+    pub max_sessions: usize,
+
+    /// Answer repeated inference requests from a cache of earlier answers
+    /// instead of running the model again. Hits are counted by
+    /// axon_cache_hits_total and kept in --history-db, but skipped by the
+    /// request count and inference duration metrics
+    #[arg(long)]
+    pub enable_cache: bool,
+
+    /// With --enable-cache, answers kept, least recently used evicted first
+    #[arg(long, default_value_t = 256)]
+    pub cache_size: usize,
+
+    /// With --enable-cache, seconds an answer is reused for
+    #[arg(long = "cache-ttl", default_value_t = 60 * 60)]
+    pub cache_ttl_secs: u64,
+
+    /// In web mode, most turns kept per /api/chat session, oldest dropped first
+    #[arg(long, default_value_t = 100)]
+    pub max_session_messages: usize,
+
+    /// Maximum number of requests sent to Ollama at the same time
+    #[arg(long, default_value_t = 4)]
+    pub max_concurrency: usize,
+
+    /// In web mode, fail asks when no remote Leader is known instead of answering with the local Ollama
+    #[arg(long)]
+    pub no_local_fallback: bool,
+
+    /// In web mode, answer asks for a model this node serves with the local
+    /// Ollama even when remote Leaders are connected
+    #[arg(long, conflicts_with = "no_local_fallback")]
+    pub prefer_local: bool,
+
+    /// In web mode, require `Authorization: Bearer <key>` on every /api route
+    /// but /api/health (default: $AXON_API_KEY or $AXON_API_TOKEN, if set;
+    /// otherwise no auth)
+    #[arg(long, visible_alias = "api-token")]
+    pub http_api_key: Option<String>,
+
+    /// In web mode, requests a minute each client may make to /api/ask (default: unlimited)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub rate_limit: Option<u32>,
+
+    /// In web mode, requests a client may make at once before --rate-limit applies
+    #[arg(long, default_value_t = 10)]
+    pub rate_burst: u32,
+
+    /// In web mode, asks to /api/ask kept waiting at once; more are refused
+    /// with 429 until one finishes
+    #[arg(long, default_value_t = 64)]
+    pub max_pending_asks: usize,
+
+    /// In web mode, seconds an ask waits for its answer when it doesn't give
+    /// `timeout_seconds` (default: --request-timeout)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub ask_timeout_secs: Option<u64>,
+
+    /// In web mode, the most seconds an ask may give as `timeout_seconds`;
+    /// this node's --request-timeout is raised to match
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_ask_timeout_secs: u64,
+
+    /// In web mode, tell clients apart by the last X-Forwarded-For address,
+    /// as set by a reverse proxy, rather than the connecting address
+    #[arg(long)]
+    pub trust_proxy: bool,
+
+    /// In web mode, a browser origin allowed to call the HTTP API, e.g.
+    /// https://chat.example.com (repeatable; default: the comma-separated
+    /// $AXON_CORS_ORIGINS if set, otherwise pages served from localhost)
+    #[arg(long = "cors-origin")]
+    pub cors_origins: Vec<String>,
+
+    /// In web mode, the HTTP methods browsers may use on the API, comma-separated
+    #[arg(long, value_delimiter = ',', default_value = "GET,POST,DELETE")]
+    pub cors_methods: Vec<String>,
+
+    /// In web mode, the request headers browsers may send to the API, comma-separated
+    #[arg(long, value_delimiter = ',', default_value = "content-type")]
+    pub cors_headers: Vec<String>,
+
+    /// In web mode, address and port to serve the API (and UI) on; use
+    /// 0.0.0.0 to accept connections from other machines
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    pub http_addr: SocketAddr,
+
+    /// In web mode, PEM certificate chain to serve HTTPS with; needs --tls-key
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// In web mode, PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// In web mode, directory of the built web UI to serve at / (default:
+    /// web-app/dist, if it exists)
+    #[arg(long)]
+    pub ui_dir: Option<PathBuf>,
+
+    /// Seconds to let pending requests finish after SIGINT/SIGTERM
+    #[arg(long, default_value_t = 30)]
+    pub shutdown_grace_secs: u64,
+}
+
+/// Inference servers a Leader can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    /// Ollama's native API at --ollama-url
+    Ollama,
+    /// A server speaking the OpenAI chat completions API at --backend-url,
+    /// such as llama.cpp, vLLM or LM Studio
+    #[value(name = "openai-compat")]
+    OpenaiCompat,
+}
+
+/// Treatment of requests asking for more than the allowed priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PriorityPolicy {
+    /// Lower the priority to the maximum
+    Clamp,
+    /// Refuse the request
+    Reject,
+}
+
+/// Discovery and retry behaviour for Subordinate requests
+#[derive(Debug, Clone, clap::Args)]
+pub struct RequestOptions {
+    /// Seconds to wait for a suitable Leader before giving up
+    #[arg(long, default_value_t = 30)]
+    pub discovery_timeout_secs: u64,
+
+    /// Number of times to retry a failed request before giving up
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Base delay between retries in milliseconds, doubled on each attempt
+    #[arg(long, default_value_t = 500)]
+    pub retry_delay_ms: u64,
 }
 
 impl Args {
     pub async fn run(self) -> Result<()> {
         Ok(())
     }
+
+    /// The log filter chosen with --verbose or --quiet, if either was given
+    ///
+    /// Counting starts from the default of info rather than warn, so a single
+    /// -v already shows debug output.
+    pub fn log_filter(&self) -> Option<&'static str> {
+        if self.quiet {
+            return Some("error");
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some("axon_cluster=debug,info"),
+            2 => Some("axon_cluster=trace,info"),
+            _ => Some("trace"),
+        }
+    }
+}
+
+impl NetworkArgs {
+    /// The addresses to listen on: those given with --listen, or every
+    /// interface for each transport in use
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+        let mut addrs = Vec::new();
+        if self.transport.tcp() {
+            addrs.extend(["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/0"]);
+        }
+        if self.transport.quic() {
+            addrs.extend(["/ip4/0.0.0.0/udp/0/quic-v1", "/ip6/::/udp/0/quic-v1"]);
+        }
+        addrs
+            .into_iter()
+            .map(|addr| addr.parse().expect("valid multiaddr"))
+            .collect()
+    }
+}
+
+impl LeaderArgs {
+    /// Check the bounds the flags enforce, for settings that came from
+    /// elsewhere, like the `--config` file
+    pub fn validate(&self) -> Result<()> {
+        let at_least_one = [
+            ("rate_limit", self.rate_limit.map(u64::from)),
+            ("ask_timeout_secs", self.ask_timeout_secs),
+            ("max_ask_timeout_secs", Some(self.max_ask_timeout_secs)),
+        ];
+        for (name, value) in at_least_one {
+            if value == Some(0) {
+                anyhow::bail!("{} must be at least 1", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// The models this Leader advertises, its default first
+    pub fn served_models(&self) -> Vec<String> {
+        if self.models.is_empty() {
+            vec![self.model.clone()]
+        } else {
+            self.models.clone()
+        }
+    }
 }
@@ -0,0 +1,281 @@
+//! Settings loaded from the TOML file given with `--config`
+//!
+//! Each setting comes from the first of: the command line, an environment
+//! variable, the file, and the built-in default.
+
+use anyhow::{Context, Result};
+use clap::{ArgMatches, parser::ValueSource};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use crate::cli::{Args, ConfigCommand, LeaderArgs, LogFormat, Mode, NetworkArgs};
+
+/// Layout of a configuration file; `network` and `leader` take the same keys
+/// as [`NetworkArgs`] and [`LeaderArgs`]
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    identity_path: Option<PathBuf>,
+    log_format: Option<LogFormat>,
+    #[serde(default)]
+    network: toml::Table,
+    #[serde(default)]
+    leader: toml::Table,
+}
+
+/// The settings a Leader runs with, as printed by `config print`
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig<'a> {
+    pub identity_path: &'a Path,
+    pub log_format: LogFormat,
+    pub network: &'a NetworkArgs,
+    pub leader: &'a LeaderArgs,
+}
+
+/// Fill in the settings not given on the command line from the environment
+/// and the `--config` file
+pub fn resolve(mut args: Args, matches: &ArgMatches) -> Result<Args> {
+    let file = match &args.config {
+        Some(path) => load(path)?,
+        None => ConfigFile::default(),
+    };
+
+    if let Some(identity_path) = file.identity_path
+        && !from_cli(matches, "identity_path")
+    {
+        args.identity_path = identity_path;
+    }
+    if let Some(log_format) = file.log_format
+        && !from_cli(matches, "log_format")
+    {
+        args.log_format = log_format;
+    }
+
+    args.network =
+        overlay(&args.network, file.network, matches).context("Invalid [network] settings")?;
+
+    if let Some((leader, leader_matches)) = leader_args(&mut args.mode, matches) {
+        // Not carried through the overlay, which only keeps current names
+        let deprecated_key = leader.api_key.clone();
+        *leader =
+            overlay(leader, file.leader, leader_matches).context("Invalid [leader] settings")?;
+        leader.validate().context("Invalid [leader] settings")?;
+        apply_env(leader, leader_matches);
+
+        // --api-key wins like any flag; the file's api_key only fills a gap
+        match (&deprecated_key, &leader.api_key) {
+            (Some(key), _) => leader.backend_api_key = Some(key.clone()),
+            (None, Some(key)) => {
+                leader.backend_api_key.get_or_insert_with(|| key.clone());
+            }
+            (None, None) => {}
+        }
+        leader.api_key = deprecated_key.or(leader.api_key.take());
+    }
+
+    Ok(args)
+}
+
+/// Warn about settings given under a deprecated name
+pub fn warn_deprecated(args: &Args) {
+    let (Mode::Serve { leader }
+    | Mode::Web { leader }
+    | Mode::Config {
+        command: ConfigCommand::Print { leader },
+    }) = &args.mode
+    else {
+        return;
+    };
+    if leader.api_key.is_some() {
+        warn!(
+            "⚠️ --api-key (api_key in the config file) is deprecated, use --backend-api-key \
+             (backend_api_key) instead; the web API's own key is --http-api-key"
+        );
+    }
+}
+
+fn load(path: &Path) -> Result<ConfigFile> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("Invalid config file '{}'", path.display()))
+}
+
+/// The Leader settings of `mode`, with the matches their flags were parsed into
+fn leader_args<'a>(
+    mode: &'a mut Mode,
+    matches: &'a ArgMatches,
+) -> Option<(&'a mut LeaderArgs, &'a ArgMatches)> {
+    match mode {
+        Mode::Serve { leader } => Some((leader, matches.subcommand_matches("serve")?)),
+        Mode::Web { leader } => Some((leader, matches.subcommand_matches("web")?)),
+        Mode::Config {
+            command: ConfigCommand::Print { leader },
+        } => Some((
+            leader,
+            matches
+                .subcommand_matches("config")?
+                .subcommand_matches("print")?,
+        )),
+        _ => None,
+    }
+}
+
+/// Replace the settings in `current` that weren't given on the command line
+/// with those in `file`
+fn overlay<T: Serialize + DeserializeOwned>(
+    current: &T,
+    file: toml::Table,
+    matches: &ArgMatches,
+) -> Result<T> {
+    let mut merged = toml::Table::try_from(current)?;
+    for (key, value) in file {
+        if !from_cli(matches, &key) {
+            merged.insert(key, value);
+        }
+    }
+    Ok(toml::Value::Table(merged).try_into()?)
+}
+
+/// Environment variables, which win over the file but not the command line
+fn apply_env(leader: &mut LeaderArgs, matches: &ArgMatches) {
+    if !from_cli(matches, "ollama_url")
+        && let Ok(url) = std::env::var("OLLAMA_LOCALHOST")
+    {
+        leader.ollama_url = url;
+    }
+    if !from_cli(matches, "ollama_api_key")
+        && let Ok(key) = std::env::var("OLLAMA_API_KEY")
+    {
+        leader.ollama_api_key = Some(key);
+    }
+    if !from_cli(matches, "backend_api_key")
+        && let Ok(key) = std::env::var("OPENAI_API_KEY")
+    {
+        leader.backend_api_key = Some(key);
+    }
+    if !from_cli(matches, "http_api_key")
+        && let Ok(key) = std::env::var("AXON_API_KEY").or_else(|_| std::env::var("AXON_API_TOKEN"))
+    {
+        leader.http_api_key = Some(key);
+    }
+    if !from_cli(matches, "cors_origins")
+        && let Ok(origins) = std::env::var("AXON_CORS_ORIGINS")
+    {
+        leader.cors_origins = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+}
+
+/// Whether the setting `id` was given as a flag
+fn from_cli(matches: &ArgMatches, id: &str) -> bool {
+    matches.ids().any(|known| known.as_str() == id)
+        && matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    /// Resolve `config print` with `flags` and a config file holding `contents`
+    fn print_with(name: &str, contents: &str, flags: &[&str]) -> Result<Args> {
+        let path = std::env::temp_dir().join(format!(
+            "axon-{}-{}-{:x}.toml",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        fs::write(&path, contents).unwrap();
+        let command = [
+            "axon_cluster",
+            "--config",
+            path.to_str().unwrap(),
+            "config",
+            "print",
+        ];
+        let matches = Args::command()
+            .try_get_matches_from(command.iter().chain(flags))
+            .unwrap();
+        let args = Args::from_arg_matches(&matches).unwrap();
+        let resolved = resolve(args, &matches);
+        fs::remove_file(path).unwrap();
+        resolved
+    }
+
+    #[test]
+    fn file_settings_are_applied() {
+        let args = print_with("config-ok", "[leader]\nrate_limit = 30\n", &[]).unwrap();
+
+        assert_eq!(leader_of(args).rate_limit, Some(30));
+    }
+
+    fn leader_of(args: Args) -> LeaderArgs {
+        let Mode::Config {
+            command: ConfigCommand::Print { leader },
+        } = args.mode
+        else {
+            unreachable!()
+        };
+        leader
+    }
+
+    #[test]
+    fn deprecated_api_key_sets_the_backend_key() {
+        let from_flag = leader_of(print_with("config-flag", "", &["--api-key", "old"]).unwrap());
+        let from_file =
+            leader_of(print_with("config-file", "[leader]\napi_key = \"older\"\n", &[]).unwrap());
+        let both = leader_of(
+            print_with(
+                "config-both",
+                "[leader]\napi_key = \"older\"\nbackend_api_key = \"new\"\n",
+                &[],
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(from_flag.backend_api_key.as_deref(), Some("old"));
+        assert!(from_flag.api_key.is_some());
+        assert_eq!(from_file.backend_api_key.as_deref(), Some("older"));
+        assert_eq!(both.backend_api_key.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn http_addr_comes_from_the_file_unless_given_as_a_flag() {
+        let contents = "[leader]\nhttp_addr = \"0.0.0.0:8080\"\n";
+        let from_file = leader_of(print_with("config-addr", contents, &[]).unwrap());
+        let from_flag = leader_of(
+            print_with(
+                "config-addr-flag",
+                contents,
+                &["--http-addr", "127.0.0.1:3001"],
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(from_file.http_addr.to_string(), "0.0.0.0:8080");
+        assert_eq!(from_flag.http_addr.to_string(), "127.0.0.1:3001");
+    }
+
+    #[test]
+    fn file_settings_out_of_range_are_refused() {
+        for contents in [
+            "[leader]\nrate_limit = 0\n",
+            "[leader]\nask_timeout_secs = 0\n",
+            "[leader]\nmax_ask_timeout_secs = 0\n",
+        ] {
+            let error = format!(
+                "{:#}",
+                print_with("config-zero", contents, &[]).unwrap_err()
+            );
+            assert!(error.contains("must be at least 1"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Cluster administration messages, carried on their own protocol
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{BackendLoad, ErrorCode};
+
+/// Protocol name and version for administration requests
+pub const CONTROL_PROTOCOL_NAME: &str = "/axon/control/1.0.0";
+
+/// Administration command sent to a Leader
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Report what the Leader is doing
+    Stats,
+    /// Stop accepting new requests, finishing the ones already accepted
+    Drain,
+    /// A command this node does not know, e.g. from a newer peer
+    #[serde(other)]
+    Unsupported,
+}
+
+/// A Leader's answer to a [`ControlRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Stats(LeaderStats),
+    /// Draining started; `pending` requests are still being finished
+    Draining {
+        pending: usize,
+    },
+    /// The command was refused, e.g. because the sender is not an admin
+    Rejected {
+        code: ErrorCode,
+        message: String,
+    },
+    /// The Leader did not recognise the command
+    Unsupported {
+        protocol: String,
+    },
+}
+
+/// Snapshot of a Leader's activity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderStats {
+    pub models: Vec<String>,
+    /// Requests queued for or being processed by the backend
+    pub in_flight: usize,
+    /// Requests answered by the backend since startup
+    pub completed: usize,
+    /// Conversation sessions currently kept
+    pub sessions: usize,
+    /// Whether the Leader has stopped accepting new requests
+    pub draining: bool,
+    /// What the backend has loaded, if it can tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_load: Option<BackendLoad>,
+}
@@ -0,0 +1,122 @@
+//! Optional SQLite log of the inference requests a Leader has served
+
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use std::{path::Path, sync::mpsc, thread};
+use tracing::warn;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY,
+    timestamp_ms INTEGER NOT NULL,
+    peer TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    response_len INTEGER NOT NULL,
+    success INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL
+)";
+
+/// One served inference request
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// Completion time, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// PeerId of the node that asked
+    pub peer: String,
+    pub model: String,
+    pub prompt: String,
+    /// Length of the generated text in bytes
+    pub response_len: usize,
+    pub success: bool,
+    pub latency_ms: u64,
+}
+
+/// A stored entry, as read back for the `history` subcommand
+#[derive(Debug, Serialize)]
+pub struct HistoryRecord {
+    /// Completion time in UTC, e.g. "2024-05-01 12:34:56"
+    pub served_at: String,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+}
+
+/// Writes history entries from a dedicated thread, so inference never waits on disk
+pub struct HistoryLog {
+    tx: mpsc::Sender<HistoryEntry>,
+}
+
+impl HistoryLog {
+    /// Open (or create) the database at `path` and start the writer thread
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = open_db(path)?;
+        let (tx, rx) = mpsc::channel::<HistoryEntry>();
+
+        thread::spawn(move || {
+            for entry in rx {
+                if let Err(e) = insert(&conn, &entry) {
+                    warn!("⚠️ Failed to record request history: {}", e);
+                }
+            }
+        });
+
+        Ok(Self { tx })
+    }
+
+    /// Queue `entry` for writing
+    pub fn record(&self, entry: HistoryEntry) {
+        let _ = self.tx.send(entry);
+    }
+}
+
+fn open_db(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+fn insert(conn: &Connection, entry: &HistoryEntry) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO history (timestamp_ms, peer, model, prompt, response_len, success, latency_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            entry.timestamp_ms as i64,
+            entry.peer,
+            entry.model,
+            entry.prompt,
+            entry.response_len as i64,
+            entry.success,
+            entry.latency_ms as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Read the `limit` most recent entries, newest first
+pub fn recent(path: &Path, limit: usize) -> Result<Vec<HistoryRecord>> {
+    let conn = open_db(path)?;
+    let mut statement = conn.prepare(
+        "SELECT datetime(timestamp_ms / 1000, 'unixepoch'), timestamp_ms, peer, model, prompt,
+                response_len, success, latency_ms
+         FROM history ORDER BY timestamp_ms DESC, id DESC LIMIT ?1",
+    )?;
+
+    let records = statement
+        .query_map([limit as i64], |row| {
+            Ok(HistoryRecord {
+                served_at: row.get(0)?,
+                entry: HistoryEntry {
+                    timestamp_ms: row.get::<_, i64>(1)? as u64,
+                    peer: row.get(2)?,
+                    model: row.get(3)?,
+                    prompt: row.get(4)?,
+                    response_len: row.get::<_, i64>(5)? as usize,
+                    success: row.get(6)?,
+                    latency_ms: row.get::<_, i64>(7)? as u64,
+                },
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(records)
+}
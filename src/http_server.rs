@@ -1,75 +1,1261 @@
 // ! HTTP API server for Web UI
 
+use anyhow::Context;
 use axum::{
     Router,
-    extract::State,
-    http::{Method, StatusCode, header},
-    response::Json,
-    routing::{get, post},
+    extract::{
+        ConnectInfo, FromRef, Path as UrlPath, Request, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{any, get, post},
+};
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use futures::{
+    SinkExt, StreamExt,
+    stream::{self, BoxStream, SelectAll},
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot};
-use tower_http::cors::{Any, CorsLayer};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    services::{ServeDir, ServeFile},
+};
+
+use crate::{
+    ask_limit::AskLimit,
+    backend::InferenceBackend,
+    metrics::{self, Metrics},
+    ollama::{OllamaOptions, OutputFormat, STREAM_BUFFER, same_model},
+    protocol::{ChatMessage, ChatRole, ErrorCode, InferenceStats},
+    rate_limit::RateLimiter,
+    sessions::ChatSessions,
+};
 
 /// Commands sent from HTTP handlers to the P2P swarm
 #[derive(Debug)]
 pub enum SwarmCommand {
     Ask {
         prompt: String,
-        responder: oneshot::Sender<Result<String, String>>,
+        /// Model to use, or `None` for the Leader's default
+        model: Option<String>,
+        /// System prompt, or `None` for the Leader's default
+        system: Option<String>,
+        /// Model parameters such as temperature
+        options: Option<OllamaOptions>,
+        priority: Option<u8>,
+        /// Required structure of the answer, if any
+        format: Option<OutputFormat>,
+        /// How long the client waits, after which the answering Leader gives up
+        timeout: Duration,
+        responder: oneshot::Sender<Result<AskResponse, AskError>>,
+    },
+    /// Fetch the models available on this Leader and its connected peers
+    ListModels {
+        responder: oneshot::Sender<ModelsResponse>,
+    },
+    /// Describe this node and the cluster as it sees it
+    Status {
+        responder: oneshot::Sender<NodeStatus>,
+    },
+    /// Describe the peers the swarm knows about
+    ListPeers {
+        responder: oneshot::Sender<Vec<PeerInfo>>,
     },
+    /// Reply to the last message of a conversation, streaming the answer
+    Chat {
+        messages: Vec<ChatMessage>,
+        /// Model to use, or `None` for the Leader's default
+        model: Option<String>,
+        events: mpsc::Sender<ChatEvent>,
+    },
+}
+
+/// Progress of a reply to a [`SwarmCommand::Chat`]
+#[derive(Debug)]
+pub enum ChatEvent {
+    Token(String),
+    Done(Option<InferenceStats>),
+    Failed(AskError),
+}
+
+/// Message a client sends on /api/ws
+#[derive(Debug, Deserialize)]
+struct SocketPrompt {
+    prompt: String,
+    /// Model to use; omitted or empty means the Leader's default
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// Message sent to a client on /api/ws
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SocketEvent {
+    /// Text of the reply produced since the previous token
+    Token {
+        text: String,
+    },
+    /// The reply is complete
+    Done {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stats: Option<InferenceStats>,
+    },
+    Error {
+        error: String,
+        code: ErrorCode,
+    },
+}
+
+/// Message a client sends on /api/chat/ws
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatSocketRequest {
+    /// Answer a single prompt, tagged with an id chosen by the client
+    Ask {
+        id: String,
+        prompt: String,
+        /// Model to use; omitted or empty means the Leader's default
+        #[serde(default)]
+        model: Option<String>,
+    },
+}
+
+/// Message sent to a client on /api/chat/ws, tagged with the ask it belongs to
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatSocketEvent {
+    /// Text of the answer produced since the previous token
+    Token { id: String, text: String },
+    /// The answer is complete
+    Result {
+        id: String,
+        answer: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stats: Option<InferenceStats>,
+    },
+    /// The ask failed, or a message couldn't be read and `id` is omitted
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        error: String,
+        code: ErrorCode,
+    },
+}
+
+/// HTTP response payload for /api/models
+#[derive(Debug, Serialize)]
+pub struct ModelsResponse {
+    pub models: Vec<ModelEntry>,
+    /// Why this node's own models are missing, e.g. its backend is down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// A model and where it can be run
+#[derive(Debug, Serialize)]
+pub struct ModelEntry {
+    pub name: String,
+    /// Whether this node's own backend has it
+    pub local: bool,
+    /// Connected peers advertising it
+    pub peers: Vec<String>,
+}
+
+impl ModelsResponse {
+    /// Combine this node's `local` models with those each of the `remote`
+    /// peers advertised, sorted by name
+    pub fn combine(
+        local: Vec<String>,
+        remote: Vec<(String, Vec<String>)>,
+        warning: Option<String>,
+    ) -> Self {
+        let mut models: Vec<ModelEntry> = local
+            .into_iter()
+            .map(|name| ModelEntry {
+                name,
+                local: true,
+                peers: Vec::new(),
+            })
+            .collect();
+
+        for (peer, names) in remote {
+            for name in names {
+                match models.iter_mut().find(|m| same_model(&m.name, &name)) {
+                    Some(entry) => entry.peers.push(peer.clone()),
+                    None => models.push(ModelEntry {
+                        name,
+                        local: false,
+                        peers: vec![peer.clone()],
+                    }),
+                }
+            }
+        }
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self { models, warning }
+    }
+}
+
+/// HTTP response entry for /api/peers
+#[derive(Debug, Serialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    pub connected: bool,
+    /// When discovery first reported the peer, in milliseconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovered_at_ms: Option<u64>,
+    /// Models the peer advertised, if it answered the capabilities query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<Vec<String>>,
 }
 
 /// HTTP request payload for /api/ask
 #[derive(Debug, Deserialize)]
 pub struct AskRequest {
     pub prompt: String,
+    /// Model to use; omitted or empty means the Leader's default
+    #[serde(default)]
+    pub model: Option<String>,
+    /// System prompt; omitted means the Leader's default
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Model parameters such as `temperature`, over the Leader's defaults
+    #[serde(default)]
+    pub options: Option<OllamaOptions>,
+    /// Scheduling priority on the Leader, higher is served first
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// `"json"` or a JSON schema the answer must follow
+    #[serde(default)]
+    pub format: Option<OutputFormat>,
+    /// Seconds to wait for the answer, up to the server's maximum
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
 }
 
 /// HTTP response payload for /api/ask
 #[derive(Debug, Serialize)]
 pub struct AskResponse {
     pub answer: String,
+    /// The model that produced the answer
+    pub model: String,
+    /// `"local"` if this node answered, otherwise the answering Leader's PeerId
+    pub served_by: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<InferenceStats>,
+}
+
+/// Failure reported back to an /api/ask handler
+#[derive(Debug)]
+pub struct AskError {
+    pub code: ErrorCode,
+    pub message: String,
 }
 
 /// HTTP response for errors
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+/// HTTP status for a failed request of the given category
+///
+/// | Code                  | Status | Typical cause                        |
+/// |-----------------------|--------|--------------------------------------|
+/// | `model_not_found`     | 404    | Leader's Ollama lacks the model      |
+/// | `backend_unavailable` | 503    | No Leader reachable, or Ollama down  |
+/// | `timeout`             | 504    | Leader or Ollama took too long       |
+/// | `rate_limited`        | 429    | Ollama is overloaded                 |
+/// | `invalid_request`     | 400    | Bad prompt, image, priority or model |
+/// | `unauthorized`        | 403    | Not on the Leader's peer allowlist   |
+/// | `prompt_too_long`     | 413    | Prompt over --max-prompt-chars       |
+/// | `cancelled`           | 499    | The client went away first           |
+/// | `internal`            | 500    | Anything else                        |
+fn status_for(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::ModelNotFound => StatusCode::NOT_FOUND,
+        ErrorCode::BackendUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized => StatusCode::FORBIDDEN,
+        ErrorCode::InvalidOutput => StatusCode::BAD_GATEWAY,
+        ErrorCode::PromptTooLong => StatusCode::PAYLOAD_TOO_LARGE,
+        // nginx's "client closed request"; the client rarely sees it
+        ErrorCode::Cancelled => StatusCode::from_u16(499).unwrap(),
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// HTTP request payload for /api/chat
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    /// Session to continue; omitted starts a new one
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub message: String,
+    /// Model to use; omitted or empty means the Leader's default
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// HTTP response payload for /api/chat
+#[derive(Debug, Serialize)]
+pub struct ChatReply {
+    pub session_id: String,
+    pub answer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<InferenceStats>,
+}
+
+/// HTTP response payload for GET /api/chat/{session_id}
+#[derive(Debug, Serialize)]
+pub struct ChatHistory {
+    pub session_id: String,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// HTTP response for an ask refused because too many are pending
+#[derive(Debug, Serialize)]
+pub struct BusyResponse {
+    pub error: String,
+    pub code: ErrorCode,
+    /// Suggested wait before trying again, also sent as `Retry-After`
+    pub retry_after_seconds: u64,
+}
+
+/// What the swarm reports about this node for /api/status
+#[derive(Debug, Serialize)]
+pub struct NodeStatus {
+    pub peer_id: String,
+    pub listen_addrs: Vec<String>,
+    pub uptime_secs: u64,
+    /// Model used when a request doesn't name one
+    pub model: String,
+    /// Whether this node's own backend answered its last health probe
+    pub ollama_reachable: bool,
+    /// Peers found by mDNS or --peer
+    pub discovered_peers: usize,
+    pub connected_peers: usize,
+    /// Requests sent to other Leaders and not yet answered
+    pub pending_p2p_requests: usize,
+    /// Requests this node's backend is working on or has queued
+    pub in_flight_requests: usize,
+    /// Requests this node's backend has answered
+    pub completed_requests: usize,
+}
+
+/// HTTP response for /api/status
+#[derive(Debug, Serialize)]
+pub struct StatusResponse {
+    #[serde(flatten)]
+    pub node: NodeStatus,
+    /// Asks waiting for an answer
+    pub pending_asks: usize,
+    /// Asks allowed to wait at once before new ones get `429`
+    pub max_pending_asks: usize,
+}
+
+/// HTTP response for /api/health
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub ollama: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 /// Shared state for HTTP handlers
 #[derive(Clone)]
 pub struct AppState {
     pub command_tx: mpsc::Sender<SwarmCommand>,
+    pub backend: Arc<dyn InferenceBackend>,
+    /// How long an ask waits for the swarm's answer unless it says otherwise
+    pub ask_timeout: Duration,
+    /// Longest wait an ask may choose
+    pub max_ask_timeout: Duration,
+    /// Longest prompt /api/ask accepts, in characters
+    pub max_prompt_chars: usize,
+    pub metrics: Arc<Metrics>,
+    pub origins: Arc<OriginPolicy>,
+    pub ask_limit: Arc<AskLimit>,
+    /// Conversations held for /api/chat
+    pub chats: Arc<ChatSessions>,
 }
 
-/// Start the HTTP API server
-pub async fn start_server(command_tx: mpsc::Sender<SwarmCommand>) -> anyhow::Result<()> {
-    let state = AppState { command_tx };
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([header::CONTENT_TYPE]);
+/// Who may call the web API, and how often
+pub struct ApiAccess {
+    /// Browser origins allowed to use the API, also checked for WebSockets
+    pub origins: Arc<OriginPolicy>,
+    pub cors: CorsLayer,
+    /// Bearer token required on every /api route but /api/health
+    pub api_key: Option<String>,
+    /// Limit on /api/ask requests per client
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Limit on /api/ask requests pending at once, across clients
+    pub ask_limit: Arc<AskLimit>,
+}
 
-    let app = Router::new()
-        .route("/api/health", get(health_check))
+/// Start the HTTP API server, running until `shutdown` resolves
+///
+/// When `ui_dir` is given, the web UI's files are served from it at `/`, with
+/// `index.html` answering any other path outside `/api` so client-side routes
+/// load the app. The API listens on `addr`, over HTTPS with `tls`. Once shutting down
+/// no new connections are accepted, but requests already being handled are
+/// allowed to finish.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_server(
+    command_tx: mpsc::Sender<SwarmCommand>,
+    backend: Arc<dyn InferenceBackend>,
+    ask_timeout: Duration,
+    max_ask_timeout: Duration,
+    max_prompt_chars: usize,
+    metrics: Arc<Metrics>,
+    access: ApiAccess,
+    chats: Arc<ChatSessions>,
+    ui_dir: Option<PathBuf>,
+    addr: SocketAddr,
+    tls: Option<RustlsConfig>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let state = AppState {
+        command_tx,
+        backend,
+        ask_timeout,
+        max_ask_timeout,
+        max_prompt_chars,
+        metrics,
+        origins: access.origins.clone(),
+        ask_limit: access.ask_limit.clone(),
+        chats,
+    };
+    let app = router(state, access, ui_dir);
+
+    // Rate limiting tells clients apart by their address
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    let Some(tls) = tls else {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        tracing::info!("🌐 HTTP API listening on http://{}", addr);
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        return Ok(());
+    };
+
+    let handle = Handle::new();
+    let stopping = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        stopping.graceful_shutdown(None);
+    });
+
+    let server = axum_server::bind_rustls(addr, tls).handle(handle);
+    tracing::info!("🔒 HTTP API listening on https://{}", addr);
+    server.serve(app).await?;
+    Ok(())
+}
+
+/// The web API's routes, behind the limits and checks `access` asks for
+fn router(state: AppState, access: ApiAccess, ui_dir: Option<PathBuf>) -> Router {
+    let mut ask = Router::new()
         .route("/api/ask", post(handle_ask))
-        .layer(cors)
-        .with_state(state);
+        .route_layer(middleware::from_fn_with_state(
+            access.ask_limit,
+            limit_pending,
+        ));
+    if let Some(limiter) = access.rate_limiter {
+        ask = ask.route_layer(middleware::from_fn_with_state(limiter, limit_rate));
+    }
+
+    // Health and metrics stay open to monitoring
+    let mut api = ask
+        .route("/api/status", get(status))
+        .route("/api/chat", post(handle_chat))
+        .route("/api/chat/:session_id", get(chat_history).delete(end_chat))
+        .route("/api/models", get(list_models))
+        .route("/api/peers", get(list_peers))
+        .route("/api/ws", get(chat_socket))
+        .route("/api/chat/ws", get(ask_socket));
+    if let Some(key) = access.api_key {
+        api = api.route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(key),
+            require_api_key,
+        ));
+    }
+
+    let mut app = Router::new()
+        .route("/api/health", get(health_check))
+        .route("/metrics", get(serve_metrics))
+        .merge(api);
+    if let Some(dir) = ui_dir {
+        tracing::info!("🖥️ Serving web UI from {}", dir.display());
+        // Unknown API paths are errors, not pages of the app
+        let index = ServeFile::new(dir.join("index.html"));
+        app = app
+            .route("/api/*path", any(api_not_found))
+            .fallback_service(ServeDir::new(dir).fallback(index));
+    }
+    app.layer(access.cors).with_state(state)
+}
+
+/// Load the certificate chain and private key to serve HTTPS with
+///
+/// Fails if either file is missing or unreadable, or they don't form a usable pair.
+pub async fn load_tls(cert: &Path, key: &Path) -> anyhow::Result<RustlsConfig> {
+    // rustls needs a process-wide crypto provider; an error means one is set already
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    for (path, flag) in [(cert, "--tls-cert"), (key, "--tls-key")] {
+        anyhow::ensure!(
+            path.is_file(),
+            "{} '{}' does not exist",
+            flag,
+            path.display()
+        );
+    }
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .with_context(|| {
+            format!(
+                "Invalid TLS certificate '{}' or key '{}'",
+                cert.display(),
+                key.display()
+            )
+        })
+}
+
+/// Answer `404 Not Found` for an /api path no route handles
+async fn api_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "No such API endpoint".to_string(),
+            code: Some(ErrorCode::InvalidRequest),
+        }),
+    )
+}
+
+/// Browser origins allowed to use the web API
+#[derive(Debug)]
+pub struct OriginPolicy {
+    origins: Vec<HeaderValue>,
+}
+
+impl OriginPolicy {
+    /// Allow exactly `origins`, or pages served from localhost if there are none
+    pub fn new(origins: &[String]) -> anyhow::Result<Self> {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .with_context(|| format!("Invalid --cors-origin '{}'", origin))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { origins })
+    }
+
+    /// Whether a page from `origin` may call the API
+    pub fn allows(&self, origin: &HeaderValue) -> bool {
+        if self.origins.is_empty() {
+            is_local_origin(origin)
+        } else {
+            self.origins.contains(origin)
+        }
+    }
+}
+
+/// CORS policy for the web API, letting pages `origins` allows call it
+pub fn cors_layer(
+    origins: Arc<OriginPolicy>,
+    methods: &[String],
+    headers: &[String],
+) -> anyhow::Result<CorsLayer> {
+    let allow_origin = AllowOrigin::predicate(move |origin, _| origins.allows(origin));
+    let methods = methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+                .with_context(|| format!("Invalid --cors-methods entry '{}'", method))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = headers
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .with_context(|| format!("Invalid --cors-headers entry '{}'", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(headers))
+}
+
+/// Let a request through only if it carries `Authorization: Bearer <key>`
+async fn require_api_key(State(key): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
-    println!("🌐 HTTP API listening on http://127.0.0.1:3000");
+    // Compare in constant time so the key can't be guessed byte by byte
+    if presented.is_some_and(|presented| bool::from(presented.as_bytes().ct_eq(key.as_bytes()))) {
+        return next.run(request).await;
+    }
 
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(ErrorResponse {
+            error: "Missing or invalid API key".to_string(),
+            code: Some(ErrorCode::Unauthorized),
+        }),
+    )
+        .into_response()
+}
+
+/// Answer `429 Too Many Requests` to clients that have used up their requests
+async fn limit_rate(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = limiter.client_ip(peer, request.headers());
+    let Err(wait) = limiter.check(client) else {
+        return next.run(request).await;
+    };
+
+    tracing::info!("🚦 Rate limited {}", client);
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(ErrorResponse {
+            error: format!("Too many requests; retry in {}s", retry_after),
+            code: Some(ErrorCode::RateLimited),
+        }),
+    )
+        .into_response()
+}
+
+/// Answer `429 Too Many Requests` when the most asks allowed are already pending
+async fn limit_pending(
+    State(limit): State<Arc<AskLimit>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(_slot) = limit.try_start() else {
+        let retry_after = limit.retry_after_secs();
+        tracing::info!("🚦 {} asks pending, refusing another", limit.pending());
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(BusyResponse {
+                error: format!("Too many pending requests; retry in {}s", retry_after),
+                code: ErrorCode::RateLimited,
+                retry_after_seconds: retry_after,
+            }),
+        )
+            .into_response();
+    };
+
+    let started = std::time::Instant::now();
+    let response = next.run(request).await;
+    limit.finished(started.elapsed());
+    response
+}
+
+/// Whether `origin` is a page served from this machine, e.g. http://localhost:5173
+fn is_local_origin(origin: &HeaderValue) -> bool {
+    let Some(url) = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| reqwest::Url::parse(origin).ok())
+    else {
+        return false;
+    };
+    match url.host_str() {
+        Some(host) => {
+            host.eq_ignore_ascii_case("localhost")
+                || host
+                    .trim_matches(['[', ']'])
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        }
+        None => false,
+    }
+}
+
+/// Serve only `/metrics` on `listener`, for Leaders without the web API
+pub async fn start_metrics_server(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(serve_metrics))
+        .with_state(metrics);
     axum::serve(listener, app).await?;
     Ok(())
 }
 
-/// Health check endpoint
-async fn health_check() -> StatusCode {
-    StatusCode::OK
+/// Metrics endpoint, in the text format Prometheus scrapes
+async fn serve_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics.encode(),
+    )
+}
+
+/// Health check endpoint, reporting whether the Ollama backend is reachable
+async fn health_check(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    match state.backend.health().await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(HealthResponse {
+                ollama: "ok",
+                error: None,
+            }),
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthResponse {
+                ollama: "unreachable",
+                error: Some(e.to_string()),
+            }),
+        ),
+    }
+}
+
+/// Status endpoint, describing this node, its peers and how busy it is
+async fn status(
+    State(state): State<AppState>,
+) -> Result<Json<StatusResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    state
+        .command_tx
+        .send(SwarmCommand::Status { responder: resp_tx })
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to send command: {}", e),
+                    code: Some(ErrorCode::Internal),
+                }),
+            )
+        })?;
+
+    let node = resp_rx.await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Channel closed".to_string(),
+                code: Some(ErrorCode::Internal),
+            }),
+        )
+    })?;
+
+    Ok(Json(StatusResponse {
+        node,
+        pending_asks: state.ask_limit.pending(),
+        max_pending_asks: state.ask_limit.max(),
+    }))
+}
+
+/// List the names of the models the Leader's Ollama can serve
+async fn list_models(
+    State(state): State<AppState>,
+) -> Result<Json<ModelsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    state
+        .command_tx
+        .send(SwarmCommand::ListModels { responder: resp_tx })
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to send command: {}", e),
+                    code: Some(ErrorCode::Internal),
+                }),
+            )
+        })?;
+
+    let models = resp_rx.await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Channel closed".to_string(),
+                code: Some(ErrorCode::Internal),
+            }),
+        )
+    })?;
+
+    Ok(Json(models))
+}
+
+/// List the peers the swarm knows about
+async fn list_peers(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PeerInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    state
+        .command_tx
+        .send(SwarmCommand::ListPeers { responder: resp_tx })
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to send command: {}", e),
+                    code: Some(ErrorCode::Internal),
+                }),
+            )
+        })?;
+
+    let peers = resp_rx.await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Channel closed".to_string(),
+                code: Some(ErrorCode::Internal),
+            }),
+        )
+    })?;
+
+    Ok(Json(peers))
+}
+
+/// Upgrade /api/ws to a WebSocket chat session
+async fn chat_socket(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if let Some(refused) = refuse_socket_origin(&state, &headers) {
+        return refused;
+    }
+
+    ws.on_upgrade(move |socket| run_chat_socket(socket, state.command_tx))
+}
+
+/// Upgrade /api/chat/ws to a WebSocket carrying independent asks
+async fn ask_socket(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if let Some(refused) = refuse_socket_origin(&state, &headers) {
+        return refused;
+    }
+
+    ws.on_upgrade(move |socket| run_ask_socket(socket, state.command_tx))
+}
+
+/// The response refusing a WebSocket upgrade from a page whose origin isn't allowed
+///
+/// Browsers don't apply CORS to WebSockets, so the origin is checked here.
+fn refuse_socket_origin(state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    match headers.get(header::ORIGIN) {
+        Some(origin) if !state.origins.allows(origin) => Some(
+            (
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Origin not allowed".to_string(),
+                    code: Some(ErrorCode::Unauthorized),
+                }),
+            )
+                .into_response(),
+        ),
+        _ => None,
+    }
+}
+
+/// Serve one chat session until the client disconnects
+///
+/// Every prompt is sent with the conversation so far. A reply still being
+/// generated when the client goes away is cancelled.
+async fn run_chat_socket(mut socket: WebSocket, command_tx: mpsc::Sender<SwarmCommand>) {
+    let mut history: Vec<ChatMessage> = Vec::new();
+    // The reply being streamed, the prompt it answers and its text so far
+    let mut pending: Option<(mpsc::Receiver<ChatEvent>, ChatMessage, String)> = None;
+
+    loop {
+        let next_event = async {
+            match &mut pending {
+                Some((events, ..)) => events.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let reply = tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    // Pings are answered by the WebSocket library
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Binary(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                if pending.is_some() {
+                    SocketEvent::Error {
+                        error: "Wait for the current reply before sending another prompt".to_string(),
+                        code: ErrorCode::InvalidRequest,
+                    }
+                } else {
+                    match serde_json::from_str::<SocketPrompt>(&text) {
+                        Ok(prompt) => {
+                            let asked = ChatMessage {
+                                role: ChatRole::User,
+                                content: prompt.prompt,
+                            };
+                            let mut messages = history.clone();
+                            messages.push(asked.clone());
+
+                            let (events_tx, events_rx) = mpsc::channel(STREAM_BUFFER);
+                            let command = SwarmCommand::Chat {
+                                messages,
+                                model: prompt.model.filter(|model| !model.trim().is_empty()),
+                                events: events_tx,
+                            };
+                            if command_tx.send(command).await.is_err() {
+                                break;
+                            }
+                            pending = Some((events_rx, asked, String::new()));
+                            continue;
+                        }
+                        Err(e) => SocketEvent::Error {
+                            error: format!("Invalid message: {}", e),
+                            code: ErrorCode::InvalidRequest,
+                        },
+                    }
+                }
+            }
+            event = next_event => match event {
+                Some(ChatEvent::Token(text)) => {
+                    if let Some((_, _, answer)) = &mut pending {
+                        answer.push_str(&text);
+                    }
+                    SocketEvent::Token { text }
+                }
+                Some(ChatEvent::Done(stats)) => {
+                    if let Some((_, asked, answer)) = pending.take() {
+                        history.push(asked);
+                        history.push(ChatMessage {
+                            role: ChatRole::Assistant,
+                            content: answer,
+                        });
+                    }
+                    SocketEvent::Done { stats }
+                }
+                Some(ChatEvent::Failed(e)) => {
+                    pending = None;
+                    SocketEvent::Error {
+                        error: e.message,
+                        code: e.code,
+                    }
+                }
+                None => {
+                    pending = None;
+                    SocketEvent::Error {
+                        error: "Reply ended unexpectedly".to_string(),
+                        code: ErrorCode::Internal,
+                    }
+                }
+            },
+        };
+
+        let Ok(text) = serde_json::to_string(&reply) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+
+    // Send the reply to a client's close frame before dropping the connection
+    let _ = socket.flush().await;
+}
+
+/// Serve asks from one client until it disconnects
+///
+/// Asks are answered concurrently, each with its own stream of events. Any
+/// still being answered when the client goes away are cancelled.
+async fn run_ask_socket(mut socket: WebSocket, command_tx: mpsc::Sender<SwarmCommand>) {
+    let mut replies = SelectAll::new();
+    // Text of each unfinished answer so far, by ask id
+    let mut answers: HashMap<String, String> = HashMap::new();
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    // Pings are answered by the WebSocket library
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Binary(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                match serde_json::from_str::<ChatSocketRequest>(&text) {
+                    Ok(ChatSocketRequest::Ask { id, .. }) if answers.contains_key(&id) => {
+                        ChatSocketEvent::Error {
+                            error: format!("Ask '{}' is still being answered", id),
+                            id: Some(id),
+                            code: ErrorCode::InvalidRequest,
+                        }
+                    }
+                    Ok(ChatSocketRequest::Ask { id, prompt, model }) => {
+                        let (events_tx, events_rx) = mpsc::channel(STREAM_BUFFER);
+                        let command = SwarmCommand::Chat {
+                            messages: vec![ChatMessage {
+                                role: ChatRole::User,
+                                content: prompt,
+                            }],
+                            model: model.filter(|model| !model.trim().is_empty()),
+                            events: events_tx,
+                        };
+                        if command_tx.send(command).await.is_err() {
+                            break;
+                        }
+                        answers.insert(id.clone(), String::new());
+                        replies.push(tagged_events(id, events_rx));
+                        continue;
+                    }
+                    Err(e) => ChatSocketEvent::Error {
+                        id: None,
+                        error: format!("Invalid message: {}", e),
+                        code: ErrorCode::InvalidRequest,
+                    },
+                }
+            }
+            Some((id, event)) = replies.next(), if !replies.is_empty() => match event {
+                Some(ChatEvent::Token(text)) => {
+                    if let Some(answer) = answers.get_mut(&id) {
+                        answer.push_str(&text);
+                    }
+                    ChatSocketEvent::Token { id, text }
+                }
+                Some(ChatEvent::Done(stats)) => ChatSocketEvent::Result {
+                    answer: answers.remove(&id).unwrap_or_default(),
+                    id,
+                    stats,
+                },
+                Some(ChatEvent::Failed(e)) => {
+                    answers.remove(&id);
+                    ChatSocketEvent::Error {
+                        id: Some(id),
+                        error: e.message,
+                        code: e.code,
+                    }
+                }
+                None => {
+                    answers.remove(&id);
+                    ChatSocketEvent::Error {
+                        id: Some(id),
+                        error: "Reply ended unexpectedly".to_string(),
+                        code: ErrorCode::Internal,
+                    }
+                }
+            },
+        };
+
+        let Ok(text) = serde_json::to_string(&reply) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+
+    // Dropping the streams cancels the asks still being answered
+    drop(replies);
+    let _ = socket.flush().await;
+}
+
+/// Events of one ask, tagged with its id, ending after its outcome
+///
+/// `None` means the reply stopped without reporting an outcome.
+fn tagged_events(
+    id: String,
+    events: mpsc::Receiver<ChatEvent>,
+) -> BoxStream<'static, (String, Option<ChatEvent>)> {
+    stream::unfold(Some(events), move |events| {
+        let id = id.clone();
+        async move {
+            let mut events = events?;
+            let event = events.recv().await;
+            let more = matches!(event, Some(ChatEvent::Token(_))).then_some(events);
+            Some(((id, event), more))
+        }
+    })
+    .boxed()
+}
+
+/// An error response with the status matching `code`
+fn failure(code: ErrorCode, error: String) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        status_for(code),
+        Json(ErrorResponse {
+            error,
+            code: Some(code),
+        }),
+    )
+}
+
+/// Handle /api/chat, answering a message in the context of its session
+///
+/// Messages to the same session are answered one at a time; a message whose
+/// answer fails leaves the history unchanged.
+async fn handle_chat(
+    State(state): State<AppState>,
+    Json(payload): Json<ChatRequest>,
+) -> Result<Json<ChatReply>, (StatusCode, Json<ErrorResponse>)> {
+    let (session_id, session) = match payload.session_id {
+        Some(id) => match state.chats.get(&id) {
+            Some(session) => (id, session),
+            None => return Err(no_session(&id)),
+        },
+        None => state.chats.create(),
+    };
+    let mut history = session.lock().await;
+
+    let asked = ChatMessage {
+        role: ChatRole::User,
+        content: payload.message,
+    };
+    let mut messages = history.clone();
+    messages.push(asked.clone());
+
+    let chars: usize = messages.iter().map(|m| m.content.chars().count()).sum();
+    if chars > state.max_prompt_chars {
+        return Err(failure(
+            ErrorCode::PromptTooLong,
+            format!(
+                "conversation is {} characters, longer than the limit of {}",
+                chars, state.max_prompt_chars
+            ),
+        ));
+    }
+
+    let (events_tx, mut events_rx) = mpsc::channel(STREAM_BUFFER);
+    state
+        .command_tx
+        .send(SwarmCommand::Chat {
+            messages,
+            model: payload.model.filter(|model| !model.trim().is_empty()),
+            events: events_tx,
+        })
+        .await
+        .map_err(|e| {
+            failure(
+                ErrorCode::Internal,
+                format!("Failed to send command: {}", e),
+            )
+        })?;
+
+    let reply = async {
+        let mut answer = String::new();
+        loop {
+            match events_rx.recv().await {
+                Some(ChatEvent::Token(text)) => answer.push_str(&text),
+                Some(ChatEvent::Done(stats)) => return Ok((answer, stats)),
+                Some(ChatEvent::Failed(e)) => return Err(failure(e.code, e.message)),
+                None => {
+                    return Err(failure(
+                        ErrorCode::Internal,
+                        "Reply ended unexpectedly".to_string(),
+                    ));
+                }
+            }
+        }
+    };
+    let (answer, stats) = tokio::time::timeout(state.ask_timeout, reply)
+        .await
+        .map_err(|_| failure(ErrorCode::Timeout, "Request timeout".to_string()))??;
+
+    history.push(asked);
+    history.push(ChatMessage {
+        role: ChatRole::Assistant,
+        content: answer.clone(),
+    });
+    state.chats.trim(&mut history);
+
+    Ok(Json(ChatReply {
+        session_id,
+        answer,
+        stats,
+    }))
+}
+
+/// Return the turns of a chat session
+async fn chat_history(
+    State(state): State<AppState>,
+    UrlPath(session_id): UrlPath<String>,
+) -> Result<Json<ChatHistory>, (StatusCode, Json<ErrorResponse>)> {
+    let session = state
+        .chats
+        .get(&session_id)
+        .ok_or_else(|| no_session(&session_id))?;
+    let messages = session.lock().await.clone();
+    Ok(Json(ChatHistory {
+        session_id,
+        messages,
+    }))
+}
+
+/// Forget a chat session
+async fn end_chat(
+    State(state): State<AppState>,
+    UrlPath(session_id): UrlPath<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    if state.chats.remove(&session_id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(no_session(&session_id))
+    }
+}
+
+/// The `404 Not Found` response for an unknown or expired chat session
+fn no_session(session_id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!(
+                "Chat session '{}' does not exist or has expired",
+                session_id
+            ),
+            code: Some(ErrorCode::InvalidRequest),
+        }),
+    )
 }
 
 /// Handle /api/ask endpoint
@@ -77,6 +1263,34 @@ async fn handle_ask(
     State(state): State<AppState>,
     Json(payload): Json<AskRequest>,
 ) -> Result<Json<AskResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let chars = payload.prompt.chars().count();
+    if chars > state.max_prompt_chars {
+        return Err((
+            status_for(ErrorCode::PromptTooLong),
+            Json(ErrorResponse {
+                error: format!(
+                    "prompt is {} characters, longer than the limit of {}",
+                    chars, state.max_prompt_chars
+                ),
+                code: Some(ErrorCode::PromptTooLong),
+            }),
+        ));
+    }
+
+    let timeout = match payload.timeout_seconds {
+        Some(0) => {
+            return Err((
+                status_for(ErrorCode::InvalidRequest),
+                Json(ErrorResponse {
+                    error: "timeout_seconds must be at least 1".to_string(),
+                    code: Some(ErrorCode::InvalidRequest),
+                }),
+            ));
+        }
+        Some(secs) => Duration::from_secs(secs).min(state.max_ask_timeout),
+        None => state.ask_timeout,
+    };
+
     // Create a oneshot channel to receive the answer
     let (resp_tx, resp_rx) = oneshot::channel();
 
@@ -85,6 +1299,12 @@ async fn handle_ask(
         .command_tx
         .send(SwarmCommand::Ask {
             prompt: payload.prompt,
+            model: payload.model.filter(|model| !model.trim().is_empty()),
+            system: payload.system,
+            options: payload.options,
+            priority: payload.priority,
+            format: payload.format,
+            timeout,
             responder: resp_tx,
         })
         .await
@@ -93,18 +1313,20 @@ async fn handle_ask(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
                     error: format!("Failed to send command: {}", e),
+                    code: Some(ErrorCode::Internal),
                 }),
             )
         })?;
 
     // Wait for response from P2P swarm (with timeout)
-    let answer = tokio::time::timeout(std::time::Duration::from_secs(120), resp_rx)
+    let response = tokio::time::timeout(timeout, resp_rx)
         .await
         .map_err(|_| {
             (
-                StatusCode::REQUEST_TIMEOUT,
+                status_for(ErrorCode::Timeout),
                 Json(ErrorResponse {
                     error: "Request timeout".to_string(),
+                    code: Some(ErrorCode::Timeout),
                 }),
             )
         })?
@@ -113,15 +1335,443 @@ async fn handle_ask(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
                     error: "Channel closed".to_string(),
+                    code: Some(ErrorCode::Internal),
                 }),
             )
         })?
         .map_err(|e| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse { error: e }),
+                status_for(e.code),
+                Json(ErrorResponse {
+                    error: e.message,
+                    code: Some(e.code),
+                }),
             )
         })?;
 
-    Ok(Json(AskResponse { answer }))
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use axum::{body::Body, extract::connect_info::MockConnectInfo};
+    use tower::ServiceExt;
+
+    /// Who may call the API: pages from localhost, with no key or limits
+    fn open_access() -> ApiAccess {
+        let origins = Arc::new(OriginPolicy::new(&[]).unwrap());
+        ApiAccess {
+            cors: cors_layer(origins.clone(), &[], &[]).unwrap(),
+            origins,
+            api_key: None,
+            rate_limiter: None,
+            ask_limit: Arc::new(AskLimit::new(8)),
+        }
+    }
+
+    /// Stand in for the swarm, answering asks with their prompt and chats
+    /// with their last message
+    fn fake_swarm() -> mpsc::Sender<SwarmCommand> {
+        let (command_tx, mut command_rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            while let Some(command) = command_rx.recv().await {
+                match command {
+                    SwarmCommand::Ask {
+                        prompt, responder, ..
+                    } => {
+                        let _ = responder.send(Ok(AskResponse {
+                            answer: prompt,
+                            model: "llama3".to_string(),
+                            served_by: "local".to_string(),
+                            stats: None,
+                        }));
+                    }
+                    SwarmCommand::Chat {
+                        messages, events, ..
+                    } => {
+                        // Echo the last message with how many came before it,
+                        // after a pause so that chats can overlap
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            let earlier = messages.len() - 1;
+                            let last = &messages[earlier].content;
+                            let answer = format!("{} ({} earlier)", last, earlier);
+                            let _ = events.send(ChatEvent::Token(answer)).await;
+                            let _ = events.send(ChatEvent::Done(None)).await;
+                        });
+                    }
+                    SwarmCommand::ListModels { responder } => {
+                        let _ = responder.send(ModelsResponse {
+                            models: Vec::new(),
+                            warning: None,
+                        });
+                    }
+                    SwarmCommand::ListPeers { responder } => {
+                        let _ = responder.send(Vec::new());
+                    }
+                    _ => {}
+                }
+            }
+        });
+        command_tx
+    }
+
+    /// The API as a client at 127.0.0.1 sees it, with `access` applied
+    fn app(access: ApiAccess) -> Router {
+        let command_tx = fake_swarm();
+        let state = AppState {
+            command_tx,
+            backend: Arc::new(MockBackend::new(&["llama3"], "Hi there")),
+            ask_timeout: Duration::from_secs(5),
+            max_ask_timeout: Duration::from_secs(5),
+            max_prompt_chars: 100,
+            metrics: Arc::new(Metrics::new()),
+            origins: access.origins.clone(),
+            ask_limit: access.ask_limit.clone(),
+            chats: Arc::new(ChatSessions::new(Duration::from_secs(60), 8, 8)),
+        };
+        router(state, access, None)
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))))
+    }
+
+    /// A GET of `uri` carrying `headers`
+    fn get(uri: &str, headers: &[(HeaderName, &str)]) -> Request {
+        let mut request = Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    /// A POST to /api/ask of `prompt`
+    fn ask(prompt: &str) -> Request {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/api/ask")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "prompt": prompt }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn send(app: &Router, request: Request) -> Response {
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    /// A POST to /api/chat of `message`, continuing `session_id` if given
+    fn chat(session_id: Option<&str>, message: &str) -> Request {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/api/chat")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "session_id": session_id, "message": message }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn json_of(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn overlapping_chats_in_a_session_are_answered_in_turn() {
+        let app = app(open_access());
+        let first = json_of(send(&app, chat(None, "first")).await).await;
+        let id = first["session_id"].as_str().unwrap();
+
+        let (second, third) = tokio::join!(
+            send(&app, chat(Some(id), "second")),
+            send(&app, chat(Some(id), "third"))
+        );
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(third.status(), StatusCode::OK);
+
+        let history = json_of(send(&app, get(&format!("/api/chat/{}", id), &[])).await).await;
+        let turns: Vec<(&str, &str)> = history["messages"]
+            .as_array()
+            .unwrap()
+            .chunks(2)
+            .map(|turn| {
+                assert_eq!(turn[0]["role"], "user");
+                assert_eq!(turn[1]["role"], "assistant");
+                (
+                    turn[0]["content"].as_str().unwrap(),
+                    turn[1]["content"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        // Each answer saw every turn before it, whichever message went first
+        let (second_first, third_first) = (
+            vec![
+                ("first", "first (0 earlier)"),
+                ("second", "second (2 earlier)"),
+                ("third", "third (4 earlier)"),
+            ],
+            vec![
+                ("first", "first (0 earlier)"),
+                ("third", "third (2 earlier)"),
+                ("second", "second (4 earlier)"),
+            ],
+        );
+        assert!(turns == second_first || turns == third_first, "{:?}", turns);
+    }
+
+    #[tokio::test]
+    async fn ask_burst_is_honoured_then_limited_with_retry_after() {
+        let app = app(ApiAccess {
+            rate_limiter: Some(Arc::new(RateLimiter::new(1, 2, false))),
+            ..open_access()
+        });
+
+        for _ in 0..2 {
+            assert_eq!(send(&app, ask("Hello")).await.status(), StatusCode::OK);
+        }
+        let limited = send(&app, ask("Hello")).await;
+
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = limited.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after), "{}", retry_after);
+    }
+
+    #[tokio::test]
+    async fn asks_over_the_pending_limit_are_refused_with_retry_after() {
+        let ask_limit = Arc::new(AskLimit::new(1));
+        let app = app(ApiAccess {
+            ask_limit: ask_limit.clone(),
+            ..open_access()
+        });
+
+        let held = ask_limit.try_start().unwrap();
+        let refused = send(&app, ask("Hello")).await;
+        drop(held);
+        let accepted = send(&app, ask("Hello")).await;
+
+        assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(refused.headers()[header::RETRY_AFTER], "1");
+        assert_eq!(accepted.status(), StatusCode::OK);
+    }
+
+    fn locked() -> Router {
+        app(ApiAccess {
+            api_key: Some("s3cret".to_string()),
+            ..open_access()
+        })
+    }
+
+    #[tokio::test]
+    async fn missing_api_key_is_unauthorized() {
+        let response = send(&locked(), get("/api/peers", &[])).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn wrong_api_key_is_unauthorized() {
+        let app = locked();
+        for value in ["Bearer s3cre", "Bearer s3cret2", "Basic s3cret", "s3cret"] {
+            let response = send(&app, get("/api/peers", &[(header::AUTHORIZATION, value)])).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn correct_api_key_is_let_through() {
+        let app = locked();
+
+        let peers = send(
+            &app,
+            get("/api/peers", &[(header::AUTHORIZATION, "Bearer s3cret")]),
+        )
+        .await;
+        let mut asked = ask("Hello");
+        asked.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer s3cret"),
+        );
+        let asked = send(&app, asked).await;
+
+        assert_eq!(peers.status(), StatusCode::OK);
+        assert_eq!(asked.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health_and_metrics_stay_open_without_a_key() {
+        let app = locked();
+
+        assert_eq!(
+            send(&app, get("/api/health", &[])).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, get("/metrics", &[])).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn ask_over_the_prompt_limit_is_too_large() {
+        let response = send(&app(open_access()), ask(&"x".repeat(101))).await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    const UI_ORIGIN: &str = "https://chat.example.com";
+
+    /// Access restricted to pages from [`UI_ORIGIN`], with the default methods
+    /// and headers
+    fn restricted() -> ApiAccess {
+        let origins = Arc::new(OriginPolicy::new(&[UI_ORIGIN.to_string()]).unwrap());
+        ApiAccess {
+            cors: cors_layer(
+                origins.clone(),
+                &["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+                &["content-type".to_string(), "authorization".to_string()],
+            )
+            .unwrap(),
+            origins,
+            ..open_access()
+        }
+    }
+
+    /// A browser's CORS preflight for a POST to `uri` from `origin`
+    fn preflight(uri: &str, origin: &str) -> Request {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri(uri)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn allowed_origin(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|origin| origin.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn preflight_from_an_allowed_origin_is_answered() {
+        let app = app(restricted());
+
+        for uri in ["/api/ask", "/api/chat"] {
+            let response = send(&app, preflight(uri, UI_ORIGIN)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(allowed_origin(&response), Some(UI_ORIGIN), "{}", uri);
+            let methods = &response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS];
+            assert!(methods.to_str().unwrap().contains("POST"), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn preflight_from_another_origin_is_not_allowed() {
+        let app = app(restricted());
+
+        // Listing origins replaces the localhost default
+        for origin in ["https://evil.example.com", "http://localhost:5173"] {
+            let response = send(&app, preflight("/api/ask", origin)).await;
+            assert_eq!(allowed_origin(&response), None, "{}", origin);
+        }
+    }
+
+    #[tokio::test]
+    async fn preflight_needs_no_api_key() {
+        let app = app(ApiAccess {
+            api_key: Some("s3cret".to_string()),
+            ..restricted()
+        });
+
+        let response = send(&app, preflight("/api/ask", UI_ORIGIN)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(allowed_origin(&response), Some(UI_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn default_policy_allows_only_pages_from_localhost() {
+        let app = app(open_access());
+
+        let local = send(&app, preflight("/api/ask", "http://localhost:5173")).await;
+        let remote = send(&app, preflight("/api/ask", "https://evil.example.com")).await;
+
+        assert_eq!(allowed_origin(&local), Some("http://localhost:5173"));
+        assert_eq!(allowed_origin(&remote), None);
+    }
+
+    /// Serve `app` on a free local port
+    async fn serve(app: Router) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap();
+        });
+        addr
+    }
+
+    /// The status code a WebSocket upgrade of `path` from `origin` gets
+    async fn upgrade_status(addr: SocketAddr, path: &str, origin: Option<&str>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+            path, addr
+        );
+        if let Some(origin) = origin {
+            request.push_str(&format!("Origin: {}\r\n", origin));
+        }
+        request.push_str("\r\n");
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut status_line = [0u8; 12];
+        stream.read_exact(&mut status_line).await.unwrap();
+        String::from_utf8_lossy(&status_line[9..]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn websocket_upgrade_checks_the_origin() {
+        let addr = serve(app(restricted())).await;
+
+        for path in ["/api/ws", "/api/chat/ws"] {
+            assert_eq!(
+                upgrade_status(addr, path, Some(UI_ORIGIN)).await,
+                "101",
+                "{}",
+                path
+            );
+            assert_eq!(
+                upgrade_status(addr, path, Some("https://evil.example.com")).await,
+                "403",
+                "{}",
+                path
+            );
+            // Clients other than browsers send no origin
+            assert_eq!(upgrade_status(addr, path, None).await, "101", "{}", path);
+        }
+    }
 }
@@ -1,439 +1,5239 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{CommandFactory, FromArgMatches};
 use futures::StreamExt;
 use libp2p::{
-    PeerId, StreamProtocol, Swarm,
-    core::{Transport, upgrade},
-    identity, mdns, noise,
+    Multiaddr, PeerId, StreamProtocol, Swarm,
+    core::{Transport, muxing::StreamMuxerBox, upgrade},
+    identity, mdns, noise, ping,
     pnet::{PnetConfig, PreSharedKey},
-    request_response::{self, OutboundRequestId, ProtocolSupport},
+    quic,
+    request_response::{
+        self, InboundRequestId, OutboundRequestId, ProtocolSupport, ResponseChannel,
+    },
     swarm::{NetworkBehaviour, SwarmEvent},
     tcp, yamux,
 };
-use std::{collections::HashMap, fs, iter, path::Path, time::Duration};
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+pub mod ask_limit;
+pub mod backend;
+pub mod bench;
+pub mod cache;
 pub mod cli;
+pub mod config;
+pub mod control;
+pub mod history;
 pub mod http_server;
+pub mod metrics;
 pub mod ollama;
+pub mod openai;
+pub mod peers;
 pub mod protocol;
+pub mod queue;
+pub mod rate_limit;
+pub mod sessions;
 
-use cli::Mode;
-use http_server::SwarmCommand;
-use ollama::OllamaClient;
-use protocol::{InferenceCodec, InferenceRequest, InferenceResponse};
-use tokio::sync::{mpsc, oneshot};
+use ask_limit::AskLimit;
+use backend::{DryRunBackend, InferenceBackend};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bench::{BenchReport, Sample};
+use cache::ResponseCache;
+use cli::{
+    AdminCommand, AskBatchArgs, BackendKind, BenchArgs, ConfigCommand, LeaderArgs, LogFormat, Mode,
+    NetworkArgs, PriorityPolicy, RequestOptions, TransportKind,
+};
+use control::{CONTROL_PROTOCOL_NAME, ControlRequest, ControlResponse, LeaderStats};
+use history::{HistoryEntry, HistoryLog};
+use http_server::{
+    ApiAccess, AskError, AskResponse, ChatEvent, ModelsResponse, NodeStatus, OriginPolicy,
+    PeerInfo, SwarmCommand,
+};
+use metrics::Metrics;
+use ollama::{
+    BackendError, Cancelled, Generation, GenerationChunk, JsonOutput, KeepAlive, ModelDetails,
+    ModelInfo, OllamaApiError, OllamaClient, OllamaConfig, OllamaOptions, OutputFormat,
+    PromptFormat, PullProgress,
+};
+use openai::OpenAiCompatBackend;
+use peers::{
+    HEARTBEAT_INTERVAL, Heartbeats, LeaderPool, Liveness, PeerAllowlist, PeerModels, PeerTable,
+    PingFailures, STATIC_REDIAL_CHECK_INTERVAL, StaticPeers,
+};
+use protocol::{
+    AxonRequest, AxonResponse, BackendLoad, BatchRequest, BatchResponse, CapabilitiesResponse,
+    ChatMessage, ChatRequest, ChatResponse, ChatRole, CountTokensRequest, CountTokensResponse,
+    EmbeddingRequest, EmbeddingResponse, ErrorCode, InferenceCodec, InferenceRequest,
+    InferenceResponse, LEGACY_PROTOCOL_NAME, PROTOCOL_NAME, SUPPORTED_PROTOCOLS,
+};
+use queue::RequestQueue;
+use rate_limit::RateLimiter;
+use sessions::{ChatSessions, SessionStore};
+use tokio::{
+    sync::{OnceCell, OwnedSemaphorePermit, Semaphore, mpsc, oneshot, watch},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, error, info, info_span, warn};
 
-/// Network behavior combining mDNS and request-response
+/// Network behavior combining mDNS, keep-alive pings and request-response
 #[derive(NetworkBehaviour)]
 struct AxonBehaviour {
     mdns: mdns::tokio::Behaviour,
+    ping: ping::Behaviour,
     request_response: request_response::Behaviour<InferenceCodec>,
+    control: request_response::json::Behaviour<ControlRequest, ControlResponse>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Load .env file if it exists
-    dotenv::dotenv().ok();
+/// How long a fetched Ollama model list is trusted
+const MODEL_LIST_TTL: Duration = Duration::from_secs(30);
 
-    let args = cli::Args::parse();
+/// How long an Ollama health probe result is reused for heartbeats
+const HEALTH_PROBE_TTL: Duration = Duration::from_secs(5);
 
-    // Load the pre-shared key for private network
-    let psk_bytes = load_psk()?;
+/// How long a summary of the backend's loaded models is reused
+const LOAD_PROBE_TTL: Duration = Duration::from_secs(2);
 
-    match args.mode {
-        Mode::Serve { ollama_url, model } => {
-            // Use OLLAMA_LOCALHOST env var if ollama_url is the default
-            let final_url = if ollama_url == "http://localhost:11434"
-                || ollama_url == "http://127.0.0.1:11434"
-            {
-                std::env::var("OLLAMA_LOCALHOST").unwrap_or(ollama_url)
-            } else {
-                ollama_url
-            };
-            run_leader(psk_bytes, final_url, model, false).await?;
-        }
-        Mode::Web { ollama_url, model } => {
-            // Use OLLAMA_LOCALHOST env var if ollama_url is the default
-            let final_url = if ollama_url == "http://localhost:11434"
-                || ollama_url == "http://127.0.0.1:11434"
-            {
-                std::env::var("OLLAMA_LOCALHOST").unwrap_or(ollama_url)
-            } else {
-                ollama_url
-            };
-            run_leader(psk_bytes, final_url, model, true).await?;
-        }
-        Mode::Ask { prompt } => {
-            run_subordinate(psk_bytes, prompt).await?;
-        }
-    }
+/// How often a Leader re-checks Ollama to log it going away or coming back
+const BACKEND_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
-    Ok(())
-}
+/// How often models with a keep-alive are reloaded, inside Ollama's 5 minute default
+const KEEP_WARM_INTERVAL: Duration = Duration::from_secs(4 * 60);
 
-/// Load the pre-shared key from swarm.key file
-fn load_psk() -> Result<[u8; 32]> {
-    let key_path = Path::new("./swarm.key");
-    if !key_path.exists() {
-        anyhow::bail!(
-            "Error: 'swarm.key' not found!\n\
-            Generate it with:\n  \
-            echo -e \"/key/swarm/psk/1.0.0/\\n/base16/\" > swarm.key && openssl rand -hex 32 >> swarm.key"
-        );
-    }
+/// How long `peer-info` waits for the listen addresses to come up
+const LISTEN_ADDR_WAIT: Duration = Duration::from_secs(1);
 
-    let psk_string = fs::read_to_string(key_path)?;
-    let hex_key = psk_string
-        .trim()
-        .lines()
-        .last()
-        .ok_or_else(|| anyhow::anyhow!("Key file is empty"))?;
-    let decoded_key = hex::decode(hex_key)?;
+/// How often a shutting down Leader logs the requests it is still waiting for
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
-    if decoded_key.len() != 32 {
-        anyhow::bail!(
-            "Invalid key length: expected 32 bytes, got {}",
-            decoded_key.len()
-        );
-    }
+/// Where `npm run build` puts the web UI, served when --ui-dir isn't given
+const DEFAULT_UI_DIR: &str = "web-app/dist";
 
-    let mut psk_bytes = [0u8; 32];
-    psk_bytes.copy_from_slice(&decoded_key);
+/// First line of a swarm.key file, naming libp2p's pnet key format
+const PSK_HEADER: &str = "/key/swarm/psk/1.0.0/";
 
-    Ok(psk_bytes)
+/// Second line of a swarm.key file, the encoding of the key that follows
+const PSK_ENCODING: &str = "/base16/";
+
+/// An automatic pull of one model; the outcome is set once it finishes
+type SharedPull = Arc<OnceCell<Result<(), String>>>;
+
+/// State shared by every request a Leader handles
+struct LeaderContext {
+    backend: Arc<dyn InferenceBackend>,
+    model: String,
+    /// Models requests may ask for, default first; `None` allows any the backend has
+    served_models: Option<Vec<String>>,
+    /// System prompt for requests that don't bring their own
+    system: Option<String>,
+    max_prompt_chars: usize,
+    max_image_bytes: usize,
+    max_priority: u8,
+    over_max_priority: PriorityPolicy,
+    /// Peers allowed to send requests; `None` allows any
+    allowlist: Option<PeerAllowlist>,
+    /// Peers allowed to send admin commands; `None` allows none
+    admins: Option<PeerAllowlist>,
+    /// Conversation history for requests carrying a session id
+    sessions: Mutex<SessionStore>,
+    /// Limits how many requests the backend works on at once
+    backend_slots: Arc<Semaphore>,
+    /// Number of requests queued for or being processed by the backend
+    in_flight: AtomicUsize,
+    /// Number of requests answered by the backend
+    completed: AtomicUsize,
+    /// Set once shutdown starts; new work is refused while queued work drains
+    draining: AtomicBool,
+    /// Recently fetched Ollama model list, to avoid a lookup per request
+    available_models: Mutex<Option<(Instant, Vec<ModelInfo>)>>,
+    /// Metadata of models looked up so far
+    model_details: Mutex<HashMap<String, ModelDetails>>,
+    /// Result of the most recent Ollama health probe
+    last_health_probe: Mutex<Option<(Instant, bool)>>,
+    /// Most recent summary of the backend's loaded models, `None` if it can't tell
+    last_load_probe: Mutex<Option<(Instant, Option<BackendLoad>)>>,
+    /// Configured default options, by model
+    model_options: HashMap<String, OllamaOptions>,
+    /// Where served inference requests are recorded, if enabled
+    history: Option<HistoryLog>,
+    /// Earlier answers reused for identical requests, if enabled
+    cache: Option<ResponseCache>,
+    /// How long an automatic pull of a missing model may take; `None` disables them
+    auto_pull: Option<Duration>,
+    /// Automatic pulls in progress, by model, shared by every request waiting on one
+    pulls: Mutex<HashMap<String, SharedPull>>,
+    /// Requests from peers not answered yet, with the token that abandons each
+    cancellations: Mutex<HashMap<InboundRequestId, (PeerId, CancellationToken)>>,
+    metrics: Arc<Metrics>,
 }
 
-/// Create a libp2p swarm with private network support
-fn create_swarm(psk_bytes: [u8; 32]) -> Result<Swarm<AxonBehaviour>> {
-    let local_key = identity::Keypair::generate_ed25519();
-    let local_peer_id = PeerId::from(local_key.public());
+impl LeaderContext {
+    fn new(leader: &LeaderArgs, backend: Arc<dyn InferenceBackend>) -> Result<Self> {
+        let allowlist = match &leader.peer_allowlist {
+            Some(path) => {
+                let allowlist = PeerAllowlist::load(path)?;
+                info!(
+                    "🛡️ Peer allowlist: {} peer(s) from {}",
+                    allowlist.len(),
+                    path.display()
+                );
+                Some(allowlist)
+            }
+            None => None,
+        };
 
-    println!("🔑 Local PeerId: {}", local_peer_id);
-    println!("🔒 Private Network: Enabled");
+        let admins = match &leader.admin_allowlist {
+            Some(path) => {
+                let admins = PeerAllowlist::load(path)?;
+                info!(
+                    "🛡️ Admin allowlist: {} peer(s) from {}",
+                    admins.len(),
+                    path.display()
+                );
+                Some(admins)
+            }
+            None => None,
+        };
 
-    // Create transport with private network encryption
-    let psk = PreSharedKey::new(psk_bytes);
+        let model_options = match &leader.model_options {
+            Some(path) => {
+                let model_options = load_model_options(path)?;
+                info!(
+                    "🎛️ Default options for {} model(s) from {}",
+                    model_options.len(),
+                    path.display()
+                );
+                model_options
+            }
+            None => HashMap::new(),
+        };
 
-    let transport = tcp::tokio::Transport::new(tcp::Config::new().nodelay(true))
-        .and_then({
-            let psk = psk.clone();
-            move |socket, _| {
-                let pnet_config = PnetConfig::new(psk.clone());
-                pnet_config.handshake(socket)
+        let history = match &leader.history_db {
+            Some(path) => {
+                let history = HistoryLog::open(path)?;
+                info!("🗄️ Recording request history to {}", path.display());
+                Some(history)
             }
-        })
-        .upgrade(upgrade::Version::V1)
-        .authenticate(noise::Config::new(&local_key)?)
-        .multiplex(yamux::Config::default())
-        .boxed();
+            None => None,
+        };
 
-    // Create request-response behavior
-    let cfg = request_response::Config::default().with_request_timeout(Duration::from_secs(120));
+        let cache = leader.enable_cache.then(|| {
+            info!(
+                "💾 Caching up to {} answers for {}s",
+                leader.cache_size, leader.cache_ttl_secs
+            );
+            ResponseCache::new(
+                leader.cache_size,
+                Duration::from_secs(leader.cache_ttl_secs),
+            )
+        });
 
-    let protocol = StreamProtocol::new("/axon/inference/1.0.0");
-    let request_response = request_response::Behaviour::with_codec(
-        InferenceCodec,
-        iter::once((protocol, ProtocolSupport::Full)),
-        cfg,
-    );
+        Ok(Self {
+            backend,
+            model: leader.model.clone(),
+            served_models: (!leader.models.is_empty()).then(|| leader.models.clone()),
+            system: leader.system.clone(),
+            max_prompt_chars: leader.max_prompt_chars,
+            max_image_bytes: leader.max_image_bytes,
+            max_priority: leader.max_priority,
+            over_max_priority: leader.over_max_priority,
+            allowlist,
+            admins,
+            sessions: Mutex::new(SessionStore::new(
+                Duration::from_secs(leader.session_ttl_secs),
+                leader.max_sessions,
+            )),
+            backend_slots: Arc::new(Semaphore::new(leader.max_concurrency.max(1))),
+            in_flight: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            available_models: Mutex::new(None),
+            model_details: Mutex::new(HashMap::new()),
+            last_health_probe: Mutex::new(None),
+            last_load_probe: Mutex::new(None),
+            model_options,
+            history,
+            cache,
+            auto_pull: leader
+                .auto_pull
+                .then(|| Duration::from_secs(leader.auto_pull_timeout_secs)),
+            pulls: Mutex::new(HashMap::new()),
+            cancellations: Mutex::new(HashMap::new()),
+            metrics: Arc::new(Metrics::new()),
+        })
+    }
 
-    // Create mDNS for local network discovery
-    let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
+    /// The token that abandons request `id` from `peer`, created on first use
+    fn track(&self, id: InboundRequestId, peer: PeerId) -> CancellationToken {
+        self.cancellations
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| (peer, CancellationToken::new()))
+            .1
+            .clone()
+    }
 
-    let behaviour = AxonBehaviour {
-        mdns,
-        request_response,
-    };
+    /// Stop tracking request `id` once it has been answered
+    fn untrack(&self, id: InboundRequestId) {
+        self.cancellations.lock().unwrap().remove(&id);
+    }
 
-    let swarm = Swarm::new(
-        transport,
-        behaviour,
-        local_peer_id,
-        libp2p::swarm::Config::with_tokio_executor()
-            .with_idle_connection_timeout(Duration::from_secs(60)),
-    );
+    /// Abandon every request from `peer`, returning how many there were
+    ///
+    /// Running generations are dropped, which closes their backend connection
+    /// and stops Ollama working on them; queued ones finish as soon as they start.
+    fn cancel_requests_from(&self, peer: &PeerId) -> usize {
+        let cancellations = self.cancellations.lock().unwrap();
+        let mut cancelled = 0;
+        for (from, cancel) in cancellations.values() {
+            if from == peer && !cancel.is_cancelled() {
+                cancel.cancel();
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
 
-    Ok(swarm)
-}
+    /// Options for a call to `model`: its configured defaults with `requested` applied over them
+    fn options_for(&self, model: &str, requested: Option<OllamaOptions>) -> Option<OllamaOptions> {
+        let defaults = self
+            .model_options
+            .get(model)
+            .or_else(|| self.model_options.get(model.strip_suffix(":latest")?))
+            .cloned();
 
-/// Run in Leader mode (server)
-async fn run_leader(
-    psk_bytes: [u8; 32],
-    ollama_url: String,
-    model: String,
-    enable_http: bool,
-) -> Result<()> {
-    println!("🚀 Starting Leader Mode (Server)");
-    println!("📡 Ollama URL: {}", ollama_url);
-    println!("🤖 Model: {}", model);
+        match (defaults, requested) {
+            (Some(defaults), Some(requested)) => Some(defaults.merge(requested)),
+            (defaults, requested) => requested.or(defaults),
+        }
+    }
 
-    if enable_http {
-        println!("🌐 Web UI mode enabled");
+    /// `requested` with the default system prompt filled in, unless it is raw
+    fn prompt_format(&self, mut requested: PromptFormat) -> PromptFormat {
+        if requested.system.is_none() && !requested.raw {
+            requested.system = self.system.clone();
+        }
+        requested
     }
 
-    let mut swarm = create_swarm(psk_bytes)?;
+    /// Whether `peer_id` may send requests to this Leader
+    fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        self.allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.contains(peer_id))
+    }
 
-    // Listen on all interfaces
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    /// Whether Ollama answered a recent health probe, probing again if stale
+    ///
+    /// A draining Leader reports itself unhealthy so Subordinates move on.
+    async fn backend_healthy(&self) -> bool {
+        if self.draining.load(Ordering::Relaxed) {
+            return false;
+        }
 
-    let ollama_client = OllamaClient::new(ollama_url);
+        let cached = *self.last_health_probe.lock().unwrap();
+        if let Some((probed_at, healthy)) = cached
+            && probed_at.elapsed() < HEALTH_PROBE_TTL
+        {
+            return healthy;
+        }
 
-    // If HTTP mode is enabled, start the HTTP server and use command channel
-    if enable_http {
-        return run_leader_with_http(swarm, ollama_client, model).await;
+        let healthy = self.backend.health().await.is_ok();
+        *self.last_health_probe.lock().unwrap() = Some((Instant::now(), healthy));
+        healthy
     }
 
-    // Standard P2P-only mode
-    loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::NewListenAddr { address, .. } => {
-                println!("👂 Listening on: {}", address);
-            }
-            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
-                for (peer_id, _addr) in peers {
-                    println!("🔍 Discovered peer: {}", peer_id);
-                }
-            }
-            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
-                request_response::Event::Message {
-                    message:
-                        request_response::Message::Request {
-                            request, channel, ..
-                        },
-                    ..
-                },
-            )) => {
-                println!("📨 Received inference request: {:?}", request.prompt);
-
-                // Process the inference request with Ollama
-                let model_name = request.model.unwrap_or_else(|| model.clone());
-                let response = match ollama_client.generate(request.prompt, model_name).await {
-                    Ok(text) => InferenceResponse {
-                        response: text,
-                        success: true,
-                        error: None,
-                    },
-                    Err(e) => InferenceResponse {
-                        response: String::new(),
-                        success: false,
-                        error: Some(format!("{}", e)),
-                    },
-                };
+    /// What the backend has loaded in memory, cached for [`LOAD_PROBE_TTL`]
+    async fn backend_load(&self) -> Option<BackendLoad> {
+        let cached = self.last_load_probe.lock().unwrap().clone();
+        if let Some((probed_at, load)) = cached
+            && probed_at.elapsed() < LOAD_PROBE_TTL
+        {
+            return load;
+        }
 
-                println!("✅ Sending response back");
-                swarm
-                    .behaviour_mut()
-                    .request_response
-                    .send_response(channel, response)
-                    .ok();
-            }
-            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
-                for (peer_id, _addr) in peers {
-                    println!("❌ Peer expired: {}", peer_id);
-                }
+        let load = match self.backend.running_models().await {
+            Ok(running) => Some(BackendLoad {
+                memory_bytes: running.iter().map(|m| m.size).sum(),
+                vram_bytes: running.iter().map(|m| m.size_vram).sum(),
+                loaded_models: running.into_iter().map(|m| m.name).collect(),
+            }),
+            Err(e) => {
+                debug!("Could not list the backend's loaded models: {:#}", e);
+                None
             }
-            _ => {}
+        };
+        *self.last_load_probe.lock().unwrap() = Some((Instant::now(), load.clone()));
+        load
+    }
+
+    /// Models available in Ollama, cached for [`MODEL_LIST_TTL`]
+    async fn models(&self) -> Result<Vec<ModelInfo>> {
+        let cached = self
+            .available_models
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < MODEL_LIST_TTL)
+            .map(|(_, models)| models.clone());
+        if let Some(models) = cached {
+            return Ok(models);
         }
+
+        let models = self.backend.list_models().await?;
+        *self.available_models.lock().unwrap() = Some((Instant::now(), models.clone()));
+        Ok(models)
     }
-}
 
-/// Run Leader with HTTP API server (Web UI mode)
-async fn run_leader_with_http(
-    mut swarm: Swarm<AxonBehaviour>,
-    ollama_client: OllamaClient,
-    model: String,
-) -> Result<()> {
-    // Create command channel for HTTP -> Swarm communication
-    let (command_tx, mut command_rx) = mpsc::channel::<SwarmCommand>(32);
+    /// Check that Ollama has `model`, using a briefly cached model list
+    ///
+    /// If the list can't be fetched the request is let through, and Ollama
+    /// reports the problem itself. With auto-pull a missing model is pulled.
+    async fn check_model(&self, model: &str) -> Result<(), String> {
+        if !self.serves(model) {
+            return Err(format!(
+                "model '{}' is not served by this Leader, which serves: {:?}",
+                model,
+                self.advertised_models()
+            ));
+        }
 
-    // Store pending requests: RequestId -> oneshot::Sender
-    let mut pending_requests: HashMap<OutboundRequestId, oneshot::Sender<Result<String, String>>> =
-        HashMap::new();
+        let Ok(models) = self.models().await else {
+            return Ok(());
+        };
 
-    // Spawn HTTP server in background
-    let _http_handle = tokio::spawn(async move {
-        if let Err(e) = http_server::start_server(command_tx).await {
-            eprintln!("HTTP server error: {}", e);
+        if models.iter().any(|m| m.is(model)) {
+            Ok(())
+        } else if self.auto_pull.is_some() {
+            self.pull(model).await
+        } else {
+            let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+            Err(format!(
+                "model '{}' not available, have: {:?}",
+                model, names
+            ))
         }
-    });
+    }
 
-    // Main event loop with tokio::select!
-    loop {
-        tokio::select! {
-            // Handle HTTP commands from web UI
-            Some(cmd) = command_rx.recv() => {
-                match cmd {
-                    SwarmCommand::Ask { prompt, responder } => {
-                        println!("🌐 HTTP request: {}", prompt);
+    /// Whether requests may ask for `model`
+    fn serves(&self, model: &str) -> bool {
+        self.served_models.as_ref().is_none_or(|served| {
+            served
+                .iter()
+                .any(|served| ollama::same_model(served, model))
+        })
+    }
 
-                        // We need to discover a Leader peer first
-                        // For simplicity, we'll send to the first discovered peer
-                        // In a real implementation, you'd track discovered peers
+    /// The models announced to peers, default first
+    fn advertised_models(&self) -> Vec<String> {
+        self.served_models
+            .clone()
+            .unwrap_or_else(|| vec![self.model.clone()])
+    }
 
-                        // For now, send error if no peers discovered
-                        // This needs improvement - we should track peers from mDNS
-                        let _ = responder.send(Err(
-                            "Web UI mode currently requires P2P peers. Use 'ask' mode from another node.".to_string()
-                        ));
+    /// Pull `model` for a request that needs it, joining the pull already in
+    /// progress if there is one
+    async fn pull(&self, model: &str) -> Result<(), String> {
+        let Some(timeout) = self.auto_pull else {
+            return Err(format!("model '{}' not available", model));
+        };
 
-                        // TODO: Implement proper peer tracking and request forwarding
-                        // let request = InferenceRequest {
-                        //     prompt,
-                        //     model: Some(model.clone()),
-                        // };
-                        // let req_id = swarm.behaviour_mut()
-                        //     .request_response
-                        //     .send_request(&peer_id, request);
-                        // pending_requests.insert(req_id, responder);
+        let pull = self
+            .pulls
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .clone();
+        let result = pull
+            .get_or_init(|| async {
+                info!("⬇️ Auto-pulling model '{}'...", model);
+                let pulled = tokio::time::timeout(
+                    timeout,
+                    self.backend
+                        .pull_model(model.to_string(), Box::new(pull_progress_logger())),
+                )
+                .await;
+                let result = match pulled {
+                    Ok(Ok(())) => {
+                        info!("✅ Pulled model '{}'", model);
+                        *self.available_models.lock().unwrap() = None;
+                        Ok(())
                     }
+                    Ok(Err(e)) => Err(format!("{:#}", e)),
+                    Err(_) => Err(format!("pull timed out after {}s", timeout.as_secs())),
+                };
+                if let Err(reason) = &result {
+                    warn!("❌ Auto-pull of '{}' failed: {}", model, reason);
                 }
+                result
+            })
+            .await
+            .clone();
+
+        // Finished pulls are forgotten, so a later miss tries again
+        let mut pulls = self.pulls.lock().unwrap();
+        if pulls
+            .get(model)
+            .is_some_and(|current| Arc::ptr_eq(current, &pull))
+        {
+            pulls.remove(model);
+        }
+
+        result.map_err(|reason| {
+            format!(
+                "model '{}' not available; auto-pull was attempted and failed: {}",
+                model, reason
+            )
+        })
+    }
+
+    /// Run `call`, and if the backend turns out not to have `model`, pull it
+    /// and run `call` once more
+    async fn with_auto_pull<T, F>(&self, model: &str, mut call: impl FnMut() -> F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        match call().await {
+            Err(e)
+                if self.auto_pull.is_some()
+                    && ollama::error_code(&e) == ErrorCode::ModelNotFound =>
+            {
+                warn!("⚠️ Backend is missing model '{}': {:#}", model, e);
+                self.pull(model).await.map_err(|message| BackendError {
+                    code: ErrorCode::ModelNotFound,
+                    message,
+                })?;
+                call().await
             }
+            result => result,
+        }
+    }
 
-            // Handle P2P swarm events
-            event = swarm.select_next_some() => {
-                match event {
-                    SwarmEvent::NewListenAddr { address, .. } => {
-                        println!("👂 Listening on: {}", address);
-                    }
-                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
-                        for (peer_id, _addr) in peers {
-                            println!("🔍 Discovered peer: {}", peer_id);
-                        }
-                    }
-                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
-                        request_response::Event::Message {
-                            message:
-                                request_response::Message::Request {
-                                    request, channel, ..
-                                },
-                            ..
-                        },
-                    )) => {
-                        println!("📨 Received P2P inference request: {:?}", request.prompt);
+    /// Metadata of `model`, looked up once and then cached
+    async fn details(&self, model: &str) -> Result<ModelDetails> {
+        if let Some(details) = self.model_details.lock().unwrap().get(model) {
+            return Ok(details.clone());
+        }
 
-                        // Process the inference request with Ollama
-                        let model_name = request.model.unwrap_or_else(|| model.clone());
-                        let response = match ollama_client.generate(request.prompt, model_name).await {
-                            Ok(text) => InferenceResponse {
-                                response: text,
-                                success: true,
-                                error: None,
-                            },
-                            Err(e) => InferenceResponse {
-                                response: String::new(),
-                                success: false,
-                                error: Some(format!("{}", e)),
-                            },
-                        };
+        let details = self.backend.show_model(model.to_string()).await?;
+        self.model_details
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), details.clone());
+        Ok(details)
+    }
 
-                        println!("✅ Sending response back");
-                        swarm
-                            .behaviour_mut()
-                            .request_response
-                            .send_response(channel, response)
-                            .ok();
-                    }
-                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
-                        request_response::Event::Message {
-                            message: request_response::Message::Response { response, request_id, .. },
-                            ..
-                        },
-                    )) => {
-                        // Handle responses to our outbound requests (from HTTP)
-                        if let Some(responder) = pending_requests.remove(&request_id) {
-                            let result = if response.success {
-                                Ok(response.response)
-                            } else {
-                                Err(response.error.unwrap_or_else(|| "Unknown error".to_string()))
-                            };
-                            let _ = responder.send(result);
-                        }
-                    }
-                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
-                        for (peer_id, _addr) in peers {
-                            println!("❌ Peer expired: {}", peer_id);
-                        }
-                    }
-                    _ => {}
-                }
+    /// Refuse a prompt that can't fit in `model`'s context window
+    ///
+    /// `prompt` is all the text the model reads, including earlier turns.
+    /// Prompts are estimated first and only tokenized when they may not fit.
+    /// If the window can't be looked up the prompt is let through, unless
+    /// Ollama doesn't know the model at all.
+    async fn check_prompt_length(
+        &self,
+        model: &str,
+        prompt: &str,
+    ) -> Result<(), (ErrorCode, String)> {
+        let limit = match self.details(model).await {
+            Ok(details) => details.context_length,
+            Err(e) if matches!(ollama::error_code(&e), ErrorCode::ModelNotFound) => {
+                return Err((ErrorCode::ModelNotFound, format!("{}", e)));
             }
+            Err(_) => None,
+        };
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        if estimate_tokens(prompt) <= limit {
+            return Ok(());
         }
+
+        let tokens = self
+            .backend
+            .tokenize(prompt.to_string(), model.to_string())
+            .await
+            .unwrap_or_else(|_| estimate_tokens(prompt));
+        if tokens <= limit {
+            return Ok(());
+        }
+        Err((
+            ErrorCode::PromptTooLong,
+            format!(
+                "prompt is {} tokens, longer than the {} token context window of '{}'",
+                tokens, limit, model
+            ),
+        ))
     }
-}
 
-/// Run in Subordinate mode (client)
-async fn run_subordinate(psk_bytes: [u8; 32], prompt: String) -> Result<()> {
-    println!("🚀 Starting Subordinate Mode (Client)");
-    println!("💭 Prompt: {}", prompt);
+    /// Refuse a prompt of `chars` characters if it is over the configured limit
+    fn check_prompt_chars(&self, chars: usize) -> Result<(), String> {
+        if chars <= self.max_prompt_chars {
+            return Ok(());
+        }
+        Err(format!(
+            "prompt is {} characters, longer than this Leader's limit of {}",
+            chars, self.max_prompt_chars
+        ))
+    }
+
+    /// Whether `peer_id` may send admin commands to this Leader
+    fn is_admin(&self, peer_id: &PeerId) -> bool {
+        self.admins
+            .as_ref()
+            .is_some_and(|admins| admins.contains(peer_id))
+    }
 
-    let mut swarm = create_swarm(psk_bytes)?;
+    /// Apply the configured priority cap, or explain why the request is refused
+    fn admit_priority(&self, priority: u8) -> Result<u8, String> {
+        if priority <= self.max_priority {
+            return Ok(priority);
+        }
+        match self.over_max_priority {
+            PriorityPolicy::Clamp => Ok(self.max_priority),
+            PriorityPolicy::Reject => Err(format!(
+                "priority {} exceeds this Leader's maximum of {}",
+                priority, self.max_priority
+            )),
+        }
+    }
+
+    /// Describe this Leader for capability advertisement
+    async fn capabilities(&self) -> CapabilitiesResponse {
+        CapabilitiesResponse {
+            models: self.advertised_models(),
+            queue_depth: self.in_flight.load(Ordering::Relaxed),
+            protocol: PROTOCOL_NAME.to_string(),
+            max_prompt_chars: Some(self.max_prompt_chars),
+            backend_load: self.backend_load().await,
+        }
+    }
+}
 
-    // Listen on a random port for incoming connections
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+/// Exit status for failures worth retrying later (EX_TEMPFAIL)
+const EXIT_RETRIABLE: u8 = 75;
 
-    let mut pending_request: Option<OutboundRequestId> = None;
-    let mut discovered_leaders: HashMap<PeerId, bool> = HashMap::new();
+/// A request that failed, already reported to the user
+#[derive(Debug)]
+struct RequestError {
+    code: Option<ErrorCode>,
+    message: String,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} ({})", self.message, code),
+            None => f.write_str(&self.message),
+        }
+    }
+}
 
-    println!("🔍 Discovering Leader nodes...");
+impl std::error::Error for RequestError {}
 
-    loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::NewListenAddr { address, .. } => {
-                println!("👂 Listening on: {}", address);
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        // Request errors were printed when they happened; only pick the exit status
+        Err(e) => match e.downcast_ref::<RequestError>() {
+            Some(RequestError {
+                code: Some(code), ..
+            }) if code.is_retriable() => ExitCode::from(EXIT_RETRIABLE),
+            Some(_) => ExitCode::FAILURE,
+            None => {
+                error!("{:#}", e);
+                ExitCode::FAILURE
             }
-            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
-                for (peer_id, _addr) in peers {
-                    if !discovered_leaders.contains_key(&peer_id) {
-                        println!("🎯 Found Leader: {}", peer_id);
-                        discovered_leaders.insert(peer_id, false);
-
-                        // Send the inference request
-                        if pending_request.is_none() {
-                            println!("📤 Sending inference request to Leader...");
-                            let request = InferenceRequest {
-                                prompt: prompt.clone(),
-                                model: None,
-                            };
+        },
+    }
+}
 
-                            let req_id = swarm
-                                .behaviour_mut()
-                                .request_response
-                                .send_request(&peer_id, request);
-                            pending_request = Some(req_id);
-                        }
-                    }
+async fn run() -> Result<()> {
+    // Load .env file if it exists
+    dotenv::dotenv().ok();
+
+    let matches = cli::Args::command().get_matches();
+    let args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (log_format, log_filter) = (args.log_format, args.log_filter());
+    let args =
+        config::resolve(args, &matches).inspect_err(|_| init_logging(log_format, log_filter))?;
+    init_logging(args.log_format, log_filter);
+    config::warn_deprecated(&args);
+
+    // Reading the history needs neither the swarm key nor an identity
+    if let Mode::History {
+        history_db,
+        limit,
+        json,
+    } = &args.mode
+    {
+        return print_history(history_db, *limit, *json);
+    }
+
+    if let Mode::Config {
+        command: ConfigCommand::Print { leader },
+    } = &args.mode
+    {
+        return print_config(&args, leader);
+    }
+
+    if let Mode::Keygen { output, force } = &args.mode {
+        return write_psk(output, *force);
+    }
+
+    // Load the pre-shared key for private network
+    let psk_bytes = load_psk()?;
+
+    // Load (or create) this node's persistent identity
+    let local_key = load_identity(&args.identity_path)?;
+    let network = args.network;
+
+    match args.mode {
+        Mode::Serve { leader } => {
+            run_leader(psk_bytes, local_key, network, leader, false).await?;
+        }
+        Mode::Web { leader } => {
+            run_leader(psk_bytes, local_key, network, leader, true).await?;
+        }
+        Mode::Ask {
+            prompt,
+            batch_file,
+            images,
+            priority,
+            session_id,
+            count_tokens,
+            model_options,
+            system,
+            raw,
+            format,
+            json,
+            options,
+        } => {
+            let model_options = parse_model_options(&model_options)?;
+            let output = format.as_deref().map(load_output_format).transpose()?;
+            let request = match (prompt, batch_file) {
+                (_, Some(path)) => {
+                    let prompts = load_prompts(&path)?;
+                    info!(
+                        "📚 Batch of {} prompt(s) from {}",
+                        prompts.len(),
+                        path.display()
+                    );
+                    AxonRequest::Batch(BatchRequest {
+                        prompts,
+                        model: None,
+                        priority,
+                        options: model_options,
+                    })
+                }
+                (Some(prompt), None) if count_tokens => {
+                    AxonRequest::CountTokens(CountTokensRequest {
+                        prompt,
+                        model: None,
+                    })
+                }
+                (Some(prompt), None) => {
+                    info!("💭 Prompt: {}", prompt);
+                    let images = images
+                        .iter()
+                        .map(|path| load_image(path, network.max_message_size))
+                        .collect::<Result<Vec<_>>>()?;
+                    AxonRequest::Inference(InferenceRequest {
+                        prompt,
+                        model: None,
+                        images,
+                        messages: None,
+                        priority,
+                        session_id,
+                        options: model_options,
+                        format: PromptFormat {
+                            system,
+                            template: None,
+                            raw,
+                            output,
+                        },
+                        request_id: Some(new_request_id()),
+                        timeout_secs: None,
+                    })
                 }
+                (None, None) => unreachable!("clap requires a prompt or --batch-file"),
+            };
+            let result =
+                run_subordinate(psk_bytes, local_key, network, request, options, json).await;
+            if json && let Err(e) = &result {
+                let code = e.downcast_ref::<RequestError>().and_then(|e| e.code);
+                let output = AskOutput {
+                    success: false,
+                    response: None,
+                    peer: None,
+                    latency_ms: None,
+                    error: Some(match e.downcast_ref::<RequestError>() {
+                        Some(e) => e.message.clone(),
+                        None => format!("{:#}", e),
+                    }),
+                    code,
+                };
+                println!("{}", serde_json::to_string(&output)?);
             }
-            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
-                request_response::Event::Message {
-                    message: request_response::Message::Response { response, .. },
+            result?;
+        }
+        Mode::Embed {
+            input,
+            model,
+            options,
+        } => {
+            info!("🧮 Embedding {} input(s)", input.len());
+            let request = AxonRequest::Embedding(EmbeddingRequest { input, model });
+            run_subordinate(psk_bytes, local_key, network, request, options, false).await?;
+        }
+        Mode::Chat { model, system } => {
+            run_chat(psk_bytes, local_key, network, model, system).await?;
+        }
+        Mode::AskBatch { batch } => {
+            run_ask_batch(psk_bytes, local_key, network, batch).await?;
+        }
+        Mode::Bench { bench } => {
+            run_bench(psk_bytes, local_key, network, bench).await?;
+        }
+        Mode::Admin {
+            peer,
+            discovery_timeout_secs,
+            command,
+        } => {
+            let request = match command {
+                AdminCommand::Stats => ControlRequest::Stats,
+                AdminCommand::Drain => ControlRequest::Drain,
+            };
+            run_admin(
+                psk_bytes,
+                local_key,
+                network,
+                peer,
+                request,
+                discovery_timeout_secs,
+            )
+            .await?;
+        }
+        Mode::PeerInfo => {
+            run_peer_info(psk_bytes, local_key, network).await?;
+        }
+        Mode::History { .. } | Mode::Config { .. } | Mode::Keygen { .. } => {
+            unreachable!("handled before joining the network")
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the most recent entries of a Leader's request history, newest first
+fn print_history(path: &Path, limit: usize, json: bool) -> Result<()> {
+    let records = history::recent(path, limit)?;
+    for record in records {
+        if json {
+            println!("{}", serde_json::to_string(&record)?);
+            continue;
+        }
+        let entry = record.entry;
+        println!(
+            "{} {} {} {} {}ms, {} bytes: {:?}",
+            record.served_at,
+            if entry.success { "✅" } else { "❌" },
+            entry.peer,
+            entry.model,
+            entry.latency_ms,
+            entry.response_len,
+            entry.prompt
+        );
+    }
+    Ok(())
+}
+
+/// Install the global log subscriber, writing to stderr so stdout carries only results
+///
+/// `filter`, from --verbose or --quiet, wins over RUST_LOG.
+fn init_logging(format: LogFormat, filter: Option<&str>) {
+    let filter = match filter {
+        Some(filter) => tracing_subscriber::EnvFilter::new(filter),
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("axon_cluster=info,warn")),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Print a Leader's effective settings as TOML, hiding credentials
+fn print_config(args: &cli::Args, leader: &LeaderArgs) -> Result<()> {
+    print!("{}", config_toml(args, leader)?);
+    Ok(())
+}
+
+/// A Leader's effective settings as TOML, with credentials redacted
+fn config_toml(args: &cli::Args, leader: &LeaderArgs) -> Result<String> {
+    let mut leader = leader.clone();
+    for key in [
+        &mut leader.backend_api_key,
+        &mut leader.ollama_api_key,
+        &mut leader.http_api_key,
+    ] {
+        if key.is_some() {
+            *key = Some("<redacted>".to_string());
+        }
+    }
+    for header in &mut leader.ollama_headers {
+        if let Some((name, _)) = header.split_once('=') {
+            *header = format!("{}=<redacted>", name);
+        }
+    }
+
+    let config = config::EffectiveConfig {
+        identity_path: &args.identity_path,
+        log_format: args.log_format,
+        network: &args.network,
+        leader: &leader,
+    };
+    Ok(toml::to_string(&config)?)
+}
+
+/// Read a batch file, one prompt per non-empty line
+fn load_prompts(path: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read batch file '{}': {}", path.display(), e))?;
+    let prompts: Vec<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    if prompts.is_empty() {
+        anyhow::bail!("Batch file '{}' contains no prompts", path.display());
+    }
+    Ok(prompts)
+}
+
+/// Read a JSON object mapping model names to their default Ollama options
+fn load_model_options(path: &Path) -> Result<HashMap<String, OllamaOptions>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read model options '{}': {}", path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid model options '{}': {}", path.display(), e))
+}
+
+/// A random id for a request, so both ends can log it
+fn new_request_id() -> String {
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+/// Read an `ask-batch` file: a JSON array of prompts, or one prompt per line
+///
+/// Each prompt is paired with its array index or 0-based line number.
+fn load_indexed_prompts(path: &Path) -> Result<Vec<(usize, String)>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read batch file '{}': {}", path.display(), e))?;
+
+    let prompts: Vec<(usize, String)> = if contents.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<String>>(&contents)
+            .with_context(|| {
+                format!(
+                    "Batch file '{}' is not a JSON array of strings",
+                    path.display()
+                )
+            })?
+            .into_iter()
+            .enumerate()
+            .collect()
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.trim().to_string()))
+            .filter(|(_, line)| !line.is_empty())
+            .collect()
+    };
+
+    if prompts.is_empty() {
+        anyhow::bail!("Batch file '{}' contains no prompts", path.display());
+    }
+    Ok(prompts)
+}
+
+/// Rough token count of `text`, at about four characters per token
+fn estimate_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(4) as u64
+}
+
+/// Turn `[MODEL=]DURATION` arguments into keep-alives by model, `model` being the default
+fn parse_keep_alive(values: &[String], model: &str) -> Result<HashMap<String, KeepAlive>> {
+    values
+        .iter()
+        .map(|value| {
+            let (name, duration) = value.split_once('=').unwrap_or((model, value));
+            Ok((name.trim().to_string(), duration.parse()?))
+        })
+        .collect()
+}
+
+/// Turn `KEY=VALUE` arguments into Ollama options
+///
+/// Values are read as JSON where possible (numbers, lists), otherwise as strings.
+fn parse_model_options(pairs: &[String]) -> Result<Option<OllamaOptions>> {
+    if pairs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut object = serde_json::Map::new();
+    for pair in pairs {
+        let Some((key, value)) = pair.split_once('=') else {
+            anyhow::bail!("Option '{}' is not in KEY=VALUE form", pair);
+        };
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        object.insert(key.trim().to_string(), value);
+    }
+
+    let options = serde_json::from_value(serde_json::Value::Object(object))
+        .map_err(|e| anyhow::anyhow!("Invalid option: {}", e))?;
+    Ok(Some(options))
+}
+
+/// Parse `--format`: "json", or the path of a file holding a JSON schema
+fn load_output_format(value: &str) -> Result<OutputFormat> {
+    if value == "json" {
+        return Ok(OutputFormat::Json(JsonOutput::Json));
+    }
+    let contents = fs::read_to_string(value).with_context(|| {
+        format!(
+            "--format is neither \"json\" nor a readable file: {}",
+            value
+        )
+    })?;
+    let schema = serde_json::from_str(&contents)
+        .with_context(|| format!("Schema file '{}' is not a JSON object", value))?;
+    Ok(OutputFormat::Schema(schema))
+}
+
+/// Read an image attachment and base64-encode it for the wire, refusing one
+/// that wouldn't fit in a message of `max_message_size` bytes
+fn load_image(path: &Path, max_message_size: usize) -> Result<String> {
+    let bytes = fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read image '{}': {}", path.display(), e))?;
+
+    // Base64 inflates by 4/3; catch files the codec would refuse anyway
+    let encoded_len = bytes.len().div_ceil(3) * 4;
+    if encoded_len > max_message_size {
+        anyhow::bail!(
+            "Image '{}' is {} bytes, too large to send (limit is about {} bytes)",
+            path.display(),
+            bytes.len(),
+            max_message_size / 4 * 3
+        );
+    }
+
+    info!(
+        "🖼️ Attaching image: {} ({} bytes)",
+        path.display(),
+        bytes.len()
+    );
+    Ok(BASE64.encode(bytes))
+}
+
+/// Load the pre-shared key from swarm.key file
+fn load_psk() -> Result<[u8; 32]> {
+    let key_path = Path::new("./swarm.key");
+    if !key_path.exists() {
+        anyhow::bail!(
+            "Error: 'swarm.key' not found!\n\
+            Generate it with:\n  \
+            axon_cluster keygen"
+        );
+    }
+
+    let psk_string = fs::read_to_string(key_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read 'swarm.key': {}", e))?;
+    parse_psk(&psk_string).map_err(|e| {
+        anyhow::anyhow!(
+            "Invalid 'swarm.key': {}\n\
+            Copy the file again from another node, or generate a new one with `axon_cluster keygen`",
+            e
+        )
+    })
+}
+
+/// Read a pre-shared key in libp2p's pnet format: `/key/swarm/psk/1.0.0/`,
+/// `/base16/`, then 64 hex digits, each on its own line
+///
+/// Surrounding whitespace, CRLF line endings, blank lines, a byte order mark
+/// and the case of the hex digits don't matter.
+fn parse_psk(contents: &str) -> Result<[u8; 32]> {
+    let mut lines = contents
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    match lines.next() {
+        None => anyhow::bail!("the file is empty"),
+        Some((_, PSK_HEADER)) => {}
+        Some((number, line)) => anyhow::bail!(
+            "line {} should be '{}' but is '{}'",
+            number,
+            PSK_HEADER,
+            line
+        ),
+    }
+    match lines.next() {
+        None => anyhow::bail!("'{}' is missing after the header", PSK_ENCODING),
+        Some((_, PSK_ENCODING)) => {}
+        Some((number, line)) => anyhow::bail!(
+            "line {} should be '{}', the only supported encoding, but is '{}'",
+            number,
+            PSK_ENCODING,
+            line
+        ),
+    }
+    let Some((number, hex_key)) = lines.next() else {
+        anyhow::bail!("the key is missing after '{}'", PSK_ENCODING);
+    };
+    if let Some((extra, _)) = lines.next() {
+        anyhow::bail!("unexpected text on line {}, after the key", extra);
+    }
+
+    if let Some(bad) = hex_key.chars().find(|c| !c.is_ascii_hexdigit()) {
+        anyhow::bail!(
+            "the key on line {} contains '{}', which is not a hex digit",
+            number,
+            bad
+        );
+    }
+    if hex_key.len() != 64 {
+        anyhow::bail!(
+            "the key on line {} is {} hex digits long, expected 64 (32 bytes)",
+            number,
+            hex_key.len()
+        );
+    }
+
+    let mut psk_bytes = [0u8; 32];
+    hex::decode_to_slice(hex_key, &mut psk_bytes)?;
+    Ok(psk_bytes)
+}
+
+/// Write a new random pre-shared key to `path` in the format libp2p's pnet
+/// reads, refusing to replace an existing file unless `force` is set
+fn write_psk(path: &Path, force: bool) -> Result<()> {
+    let key: [u8; 32] = rand::random();
+    let contents = format!("{}\n{}\n{}\n", PSK_HEADER, PSK_ENCODING, hex::encode(key));
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    // Anyone holding the key can join the network
+    let mut file = create_private_file(path, force).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => anyhow::anyhow!(
+            "'{}' already exists; pass --force to replace it",
+            path.display()
+        ),
+        _ => anyhow::anyhow!("Failed to create '{}': {}", path.display(), e),
+    })?;
+    file.write_all(contents.as_bytes())?;
+
+    info!("🔑 Wrote a new swarm key to {}", path.display());
+    info!("Copy it to every node that should join the network");
+    Ok(())
+}
+
+/// Load the node identity keypair from `path`, generating and saving one if missing
+fn load_identity(path: &Path) -> Result<identity::Keypair> {
+    if path.exists() {
+        let bytes = fs::read(path)?;
+        let keypair = identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| anyhow::anyhow!("Invalid identity file '{}': {}", path.display(), e))?;
+        info!("🪪 Loaded node identity from {}", path.display());
+        return Ok(keypair);
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    save_identity(path, &keypair)?;
+    info!("🆕 Generated new node identity at {}", path.display());
+
+    Ok(keypair)
+}
+
+/// Persist a keypair so the node keeps the same PeerId across restarts
+fn save_identity(path: &Path, keypair: &identity::Keypair) -> Result<()> {
+    let bytes = keypair.to_protobuf_encoding()?;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    create_private_file(path, false)?.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Open `path` for writing a secret, such as a private key, creating it
+/// readable only by its owner so it is never briefly exposed
+///
+/// An existing file is an error unless `replace` is set, in which case it is
+/// removed first so the new one gets the same restricted permissions.
+fn create_private_file(path: &Path, replace: bool) -> io::Result<fs::File> {
+    if replace {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Start listening on each of `addrs`. One that can't be bound, such as IPv6
+/// on a host without it, is only logged, unless none of them can
+fn listen(swarm: &mut Swarm<AxonBehaviour>, addrs: &[Multiaddr]) -> Result<()> {
+    let mut listening = false;
+    for addr in addrs {
+        match swarm.listen_on(addr.clone()) {
+            Ok(_) => listening = true,
+            // The transport error's own message is empty; the cause says what failed
+            Err(e) => warn!(
+                "⚠️ Cannot listen on {}: {}",
+                addr,
+                anyhow::Error::from(e).root_cause()
+            ),
+        }
+    }
+    if !listening {
+        anyhow::bail!("Could not listen on any --listen address");
+    }
+    Ok(())
+}
+
+/// Create a libp2p swarm with private network support
+fn create_swarm(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: &NetworkArgs,
+) -> Result<Swarm<AxonBehaviour>> {
+    let local_peer_id = PeerId::from(local_key.public());
+
+    info!("🔑 Local PeerId: {}", local_peer_id);
+    if network.transport.quic() {
+        warn!("⚠️ QUIC enabled: peers connecting over QUIC are not checked against swarm.key");
+    } else {
+        info!("🔒 Private Network: Enabled");
+    }
+
+    // Create transport with private network encryption
+    let psk = PreSharedKey::new(psk_bytes);
+
+    let tcp = tcp::tokio::Transport::new(tcp::Config::new().nodelay(true))
+        .and_then(move |socket, _| {
+            let pnet_config = PnetConfig::new(psk);
+            pnet_config.handshake(socket)
+        })
+        .upgrade(upgrade::Version::V1)
+        .authenticate(noise::Config::new(&local_key)?)
+        .multiplex(yamux::Config::default())
+        .boxed();
+
+    // QUIC brings its own TLS encryption and multiplexing, and has no pnet layer
+    let quic = || {
+        quic::tokio::Transport::new(quic::Config::new(&local_key))
+            .map(|(peer, connection), _| (peer, StreamMuxerBox::new(connection)))
+            .boxed()
+    };
+
+    let transport = match network.transport {
+        TransportKind::Tcp => tcp,
+        TransportKind::Quic => quic(),
+        TransportKind::Both => tcp
+            .or_transport(quic())
+            .map(|either, _| either.into_inner())
+            .boxed(),
+    };
+
+    // Create request-response behavior
+    let cfg = request_response::Config::default()
+        .with_request_timeout(Duration::from_secs(network.request_timeout_secs));
+
+    // Offer every version, newest first, so older peers negotiate down
+    let protocols = SUPPORTED_PROTOCOLS
+        .iter()
+        .map(|name| (StreamProtocol::new(name), ProtocolSupport::Full));
+    let request_response = request_response::Behaviour::with_codec(
+        InferenceCodec::new(network.max_message_size),
+        protocols,
+        cfg,
+    );
+
+    // Administration commands travel on their own protocol
+    let control = request_response::json::Behaviour::new(
+        iter::once((
+            StreamProtocol::new(CONTROL_PROTOCOL_NAME),
+            ProtocolSupport::Full,
+        )),
+        request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+    );
+
+    // Create mDNS for local network discovery
+    let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
+
+    // Keep-alive pings notice dead peers sooner than mDNS expiry
+    let ping = ping::Behaviour::new(
+        ping::Config::new().with_interval(Duration::from_secs(network.ping_interval_secs)),
+    );
+
+    let behaviour = AxonBehaviour {
+        mdns,
+        ping,
+        request_response,
+        control,
+    };
+
+    let swarm = Swarm::new(
+        transport,
+        behaviour,
+        local_peer_id,
+        libp2p::swarm::Config::with_tokio_executor()
+            .with_idle_connection_timeout(Duration::from_secs(network.idle_timeout_secs)),
+    );
+
+    Ok(swarm)
+}
+
+/// Build the Leader's Ollama client from its settings
+fn build_ollama_client(leader: &LeaderArgs) -> Result<OllamaClient> {
+    let keep_alive = parse_keep_alive(&leader.keep_alive, &leader.model)?;
+    for (model, duration) in &keep_alive {
+        info!("📌 Keep-alive for '{}': {}", model, duration);
+    }
+
+    let headers = ollama_headers(leader)?;
+    Ok(
+        OllamaClient::new(leader.ollama_url.clone(), backend_config(leader), headers)?
+            .with_keep_alive(keep_alive),
+    )
+}
+
+/// Headers sent with every Ollama call: each --ollama-header and the bearer token
+fn ollama_headers(leader: &LeaderArgs) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for pair in &leader.ollama_headers {
+        let (name, value) = pair
+            .split_once('=')
+            .with_context(|| format!("Invalid --ollama-header '{}', expected NAME=VALUE", pair))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("Invalid header name in --ollama-header '{}'", pair))?;
+        let mut value = HeaderValue::from_str(value.trim())
+            .with_context(|| format!("Invalid header value in --ollama-header '{}'", pair))?;
+        value.set_sensitive(true);
+        headers.append(name, value);
+    }
+
+    if let Some(key) = &leader.ollama_api_key {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", key))
+            .context("Invalid --ollama-api-key")?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    Ok(headers)
+}
+
+/// Timeouts and retries for calls to the Leader's backend
+fn backend_config(leader: &LeaderArgs) -> OllamaConfig {
+    OllamaConfig {
+        connect_timeout: Duration::from_secs(leader.ollama_connect_timeout_secs),
+        request_timeout: Duration::from_secs(leader.ollama_timeout_secs),
+        read_timeout: Duration::from_secs(leader.ollama_read_timeout_secs),
+        max_attempts: leader.ollama_max_attempts,
+        retry_backoff: Duration::from_millis(leader.ollama_retry_backoff_ms),
+    }
+}
+
+/// Build the Leader's OpenAI-compatible backend, checking it like Ollama at startup
+async fn build_openai_backend(leader: &LeaderArgs) -> Result<OpenAiCompatBackend> {
+    if leader.pull_missing || leader.auto_pull || !leader.keep_alive.is_empty() {
+        warn!(
+            "⚠️ --pull-missing, --auto-pull and --keep-alive only apply to Ollama and are ignored"
+        );
+    }
+
+    let backend = OpenAiCompatBackend::new(
+        leader.backend_url.clone(),
+        leader.backend_api_key.clone(),
+        backend_config(leader),
+    )?;
+
+    if !leader.no_preflight {
+        backend.health().await.with_context(|| {
+            format!(
+                "OpenAI-compatible server is not reachable at {}; start it, fix --backend-url, or pass --no-preflight",
+                backend.endpoint()
+            )
+        })?;
+        info!("✅ OpenAI-compatible server is reachable");
+    }
+
+    match backend.list_models().await {
+        Ok(models) => {
+            for model in leader.served_models() {
+                if models.iter().any(|m| m.is(&model)) {
+                    info!("✅ Model '{}' is available", model);
+                } else {
+                    let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+                    warn!(
+                        "⚠️ Model '{}' is NOT served by the backend; requests using it will fail. Available: {:?}",
+                        model, names
+                    );
+                }
+            }
+        }
+        Err(e) => warn!("⚠️ Could not list the backend's models: {:#}", e),
+    }
+
+    Ok(backend)
+}
+
+/// Warn at startup if Ollama doesn't have the model the Leader will default to
+///
+/// With `pull_missing` the model is pulled instead, and failing to do so is fatal.
+async fn check_configured_model(
+    ollama_client: &OllamaClient,
+    model: &str,
+    pull_missing: bool,
+) -> Result<()> {
+    let models = match ollama_client.list_models().await {
+        Ok(models) => models,
+        Err(e) => {
+            warn!("⚠️ Could not list Ollama models: {:#}", e);
+            return Ok(());
+        }
+    };
+    if models.iter().any(|m| m.is(model)) {
+        info!("✅ Model '{}' is available in Ollama", model);
+        return Ok(());
+    }
+
+    if !pull_missing {
+        let names: Vec<&str> = models.iter().map(|m| m.name.as_str()).collect();
+        warn!(
+            "⚠️ Model '{}' is NOT available in Ollama; requests using it will fail. Available: {:?}. Run `ollama pull {}` or pass --pull-missing",
+            model, names, model
+        );
+        return Ok(());
+    }
+
+    info!("⬇️ Pulling model '{}'...", model);
+    ollama_client
+        .pull_model(model.to_string(), pull_progress_logger())
+        .await?;
+    info!("✅ Pulled model '{}'", model);
+    Ok(())
+}
+
+/// Load `model` before the Leader announces itself, so the first request
+/// doesn't wait for it; a failure is only logged since the model may be
+/// pulled later
+async fn warm_up_model(ollama_client: &OllamaClient, model: String) {
+    info!("🔥 Warming up model '{}'...", model);
+    let started = Instant::now();
+    match ollama_client.load_model(model.clone()).await {
+        Ok(()) => info!(
+            "✅ Model '{}' loaded in {:.1}s",
+            model,
+            started.elapsed().as_secs_f64()
+        ),
+        Err(e) => warn!("⚠️ Could not warm up model '{}': {:#}", model, e),
+    }
+}
+
+/// Progress callback for a pull that logs each status change, and every 10%
+/// of a layer's download
+fn pull_progress_logger() -> impl FnMut(&PullProgress) + Send {
+    let mut last: Option<(String, u64)> = None;
+    move |progress| {
+        let step = progress.percent().map_or(0, |p| p as u64 / 10);
+        let current = (progress.status.clone(), step);
+        if last.as_ref() != Some(&current) {
+            match progress.percent() {
+                Some(percent) => info!("⬇️ {}: {:.0}%", progress.status, percent),
+                None => info!("⬇️ {}", progress.status),
+            }
+            last = Some(current);
+        }
+    }
+}
+
+/// Run in Leader mode (server)
+async fn run_leader(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    mut network: NetworkArgs,
+    mut leader: LeaderArgs,
+    enable_http: bool,
+) -> Result<()> {
+    info!("🚀 Starting Leader Mode (Server)");
+    match leader.backend {
+        BackendKind::Ollama => info!("📡 Ollama URL: {}", leader.ollama_url),
+        BackendKind::OpenaiCompat => info!("📡 OpenAI-compatible API: {}", leader.backend_url),
+    }
+    if leader.models.iter().any(|model| model.trim().is_empty()) {
+        anyhow::bail!("--models contains an empty model name");
+    }
+    if let Some(first) = leader.models.first() {
+        leader.model = first.clone();
+        info!("🤖 Models: {}", leader.models.join(", "));
+    } else {
+        info!("🤖 Model: {}", leader.model);
+    }
+
+    if enable_http {
+        info!("🌐 Web UI mode enabled");
+    }
+    let ask_timeout = Duration::from_secs(
+        leader
+            .ask_timeout_secs
+            .unwrap_or(network.request_timeout_secs)
+            .min(leader.max_ask_timeout_secs),
+    );
+    if enable_http {
+        // Forwarded asks may be given up to the longest ask timeout
+        network.request_timeout_secs = network
+            .request_timeout_secs
+            .max(leader.max_ask_timeout_secs);
+    }
+
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let (backend, pinned): (Arc<dyn InferenceBackend>, Vec<String>) = if leader.dry_run {
+        info!("🧪 Dry run: echoing prompts instead of calling Ollama");
+        (
+            Arc::new(DryRunBackend::new(leader.served_models())),
+            Vec::new(),
+        )
+    } else if leader.backend == BackendKind::OpenaiCompat {
+        (Arc::new(build_openai_backend(&leader).await?), Vec::new())
+    } else {
+        let ollama_client = build_ollama_client(&leader)?;
+        if !leader.no_preflight {
+            let version = match ollama_client.health().await {
+                Ok(version) => version,
+                Err(e)
+                    if e.downcast_ref::<OllamaApiError>()
+                        .is_some_and(OllamaApiError::is_auth_failure) =>
+                {
+                    anyhow::bail!(
+                        "Ollama at {} refused our credentials ({}); check --ollama-api-key (or OLLAMA_API_KEY) and --ollama-header",
+                        ollama_client.base_url(),
+                        e
+                    );
+                }
+                Err(e) => {
+                    return Err(e.context(format!(
+                        "Ollama is not reachable at {}; start it, fix --ollama-url, or pass --no-preflight",
+                        ollama_client.base_url()
+                    )));
+                }
+            };
+            info!("✅ Ollama {} is reachable", version);
+        }
+        for model in leader.served_models() {
+            check_configured_model(&ollama_client, &model, leader.pull_missing).await?;
+        }
+        if !leader.no_warmup {
+            for model in leader.served_models() {
+                warm_up_model(&ollama_client, model).await;
+            }
+        }
+        let pinned = ollama_client.keep_alive_models().cloned().collect();
+        (Arc::new(ollama_client), pinned)
+    };
+
+    let ctx = Arc::new(LeaderContext::new(&leader, backend)?);
+    if let Some(addr) = leader.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen for metrics on {}", addr))?;
+        info!("📈 Metrics at http://{}/metrics", addr);
+        let metrics = ctx.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http_server::start_metrics_server(listener, metrics).await {
+                error!("Metrics server error: {}", e);
+            }
+        });
+    }
+    tokio::spawn(watch_backend(
+        ctx.clone(),
+        (!leader.no_preflight).then_some(true),
+    ));
+    if !pinned.is_empty() {
+        tokio::spawn(keep_models_warm(ctx.clone(), pinned));
+    }
+
+    // If HTTP mode is enabled, start the HTTP server and use command channel
+    if enable_http {
+        return run_leader_with_http(swarm, static_peers, ping_failures, ctx, leader, ask_timeout)
+            .await;
+    }
+
+    // Requests waiting for the backend, most urgent first
+    let mut queue = RequestQueue::new();
+
+    // Backend tasks hand finished responses back to this loop for sending
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut drain_deadline: Option<Instant> = None;
+
+    // Inbound requests whose response the swarm hasn't finished sending
+    let mut unanswered: HashSet<InboundRequestId> = HashSet::new();
+
+    // Dial static peers now, and again later if they aren't reachable yet
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    // Standard P2P-only mode
+    loop {
+        if drain_deadline.is_some()
+            && ctx.in_flight.load(Ordering::Relaxed) == 0
+            && unanswered.is_empty()
+        {
+            break;
+        }
+
+        let event = tokio::select! {
+            // Queued work is only started once no other event is ready
+            biased;
+
+            _ = &mut shutdown, if drain_deadline.is_none() => {
+                drain_deadline = Some(start_draining(&ctx, &leader));
+                continue;
+            }
+            _ = tokio::time::sleep_until(drain_deadline.unwrap_or_else(Instant::now)), if drain_deadline.is_some() => {
+                break;
+            }
+            Some(done) = done_rx.recv() => {
+                finish_request(&mut swarm, &ctx, done);
+                continue;
+            }
+            Some((channel, response)) = control_rx.recv() => {
+                swarm.behaviour_mut().control.send_response(channel, response).ok();
+                continue;
+            }
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+                continue;
+            }
+            event = swarm.select_next_some() => discovery_event(event, &mut static_peers, &mut ping_failures),
+            permit = ctx.backend_slots.clone().acquire_owned(), if !queue.is_empty() => {
+                if let Ok(permit) = permit {
+                    spawn_next(&ctx, &mut queue, permit, &done_tx);
+                }
+                continue;
+            }
+        };
+
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("👂 Listening on: {}", address);
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, _addr) in peers {
+                    info!("🔍 Discovered peer: {}", peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    message:
+                        request_response::Message::Request {
+                            request_id,
+                            request,
+                            channel,
+                        },
+                    peer,
+                },
+            )) => {
+                unanswered.insert(request_id);
+                let job = QueuedRequest {
+                    request_id,
+                    peer,
+                    request,
+                    channel,
+                    received_at: Instant::now(),
+                };
+                accept_request(&mut swarm, &ctx, &mut queue, job, &done_tx);
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::ResponseSent { request_id, .. }
+                | request_response::Event::InboundFailure { request_id, .. },
+            )) => {
+                unanswered.remove(&request_id);
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Control(
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                },
+            )) => {
+                spawn_control(&ctx, peer, request, channel, &control_tx);
+            }
+            SwarmEvent::ConnectionEstablished { .. } => {
+                ctx.metrics
+                    .set_peers_connected(swarm.connected_peers().count());
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                ctx.metrics
+                    .set_peers_connected(swarm.connected_peers().count());
+                let cancelled = ctx.cancel_requests_from(&peer_id);
+                if cancelled > 0 {
+                    info!(
+                        "🚫 {} disconnected, cancelling {} request(s)",
+                        peer_id, cancelled
+                    );
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer_id, _addr) in peers {
+                    info!("❌ Peer expired: {}", peer_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    log_shutdown_summary(&ctx, 0);
+    Ok(())
+}
+
+/// Resolve once SIGINT (Ctrl-C) or SIGTERM is received
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Answer an administration command on its own task, handing the response
+/// back through `control_tx` for the swarm to send
+fn spawn_control(
+    ctx: &Arc<LeaderContext>,
+    peer: PeerId,
+    request: ControlRequest,
+    channel: ResponseChannel<ControlResponse>,
+    control_tx: &mpsc::UnboundedSender<(ResponseChannel<ControlResponse>, ControlResponse)>,
+) {
+    let ctx = ctx.clone();
+    let control_tx = control_tx.clone();
+    tokio::spawn(async move {
+        let response = handle_control(&ctx, peer, request).await;
+        let _ = control_tx.send((channel, response));
+    });
+}
+
+/// Answer an administration command, refusing peers that are not admins
+async fn handle_control(
+    ctx: &LeaderContext,
+    peer: PeerId,
+    request: ControlRequest,
+) -> ControlResponse {
+    if !ctx.is_admin(&peer) {
+        warn!("🚫 Rejecting admin command from unauthorized peer {}", peer);
+        return ControlResponse::Rejected {
+            code: ErrorCode::Unauthorized,
+            message: "peer is not an admin of this Leader".to_string(),
+        };
+    }
+
+    match request {
+        ControlRequest::Stats => {
+            let backend_load = ctx.backend_load().await;
+            ControlResponse::Stats(LeaderStats {
+                models: ctx.advertised_models(),
+                in_flight: ctx.in_flight.load(Ordering::Relaxed),
+                completed: ctx.completed.load(Ordering::Relaxed),
+                sessions: ctx.sessions.lock().unwrap().len(),
+                draining: ctx.draining.load(Ordering::Relaxed),
+                backend_load,
+            })
+        }
+        ControlRequest::Drain => {
+            ctx.draining.store(true, Ordering::Relaxed);
+            let pending = ctx.in_flight.load(Ordering::Relaxed);
+            info!(
+                "🛑 Draining on request from {}: {} pending request(s)",
+                peer, pending
+            );
+            ControlResponse::Draining { pending }
+        }
+        ControlRequest::Unsupported => ControlResponse::Unsupported {
+            protocol: CONTROL_PROTOCOL_NAME.to_string(),
+        },
+    }
+}
+
+/// Stop accepting work and return the deadline for finishing what is pending
+///
+/// The Leader keeps running the swarm until every response has been written
+/// to its peer, or failed, so the last answers aren't dropped with it. Until
+/// the Leader exits, the pending count is logged every
+/// [`DRAIN_PROGRESS_INTERVAL`], and another SIGINT/SIGTERM exits at once.
+fn start_draining(ctx: &Arc<LeaderContext>, leader: &LeaderArgs) -> Instant {
+    ctx.draining.store(true, Ordering::Relaxed);
+    info!(
+        "🛑 Shutting down: finishing {} pending request(s) (up to {}s); signal again to force exit",
+        ctx.in_flight.load(Ordering::Relaxed),
+        leader.shutdown_grace_secs
+    );
+
+    tokio::spawn(async {
+        shutdown_signal().await;
+        warn!("⚠️ Forced exit, abandoning pending requests");
+        std::process::exit(130);
+    });
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(DRAIN_PROGRESS_INTERVAL);
+        timer.tick().await;
+        loop {
+            timer.tick().await;
+            let pending = ctx.in_flight.load(Ordering::Relaxed);
+            if pending == 0 {
+                break;
+            }
+            info!("⏳ Waiting for {} pending request(s) to finish", pending);
+        }
+    });
+
+    Instant::now() + Duration::from_secs(leader.shutdown_grace_secs)
+}
+
+/// Report how the Leader's work ended up at shutdown
+///
+/// `forwarded` counts requests relayed to other Leaders that never got an answer.
+fn log_shutdown_summary(ctx: &LeaderContext, forwarded: usize) {
+    info!(
+        "👋 Shutdown complete: {} request(s) completed, {} dropped",
+        ctx.completed.load(Ordering::Relaxed),
+        ctx.in_flight.load(Ordering::Relaxed) + forwarded
+    );
+}
+
+/// An inbound request waiting for the backend
+struct QueuedRequest {
+    request_id: InboundRequestId,
+    peer: PeerId,
+    request: AxonRequest,
+    channel: ResponseChannel<AxonResponse>,
+    /// When the request arrived, which its deadline counts from
+    received_at: Instant,
+}
+
+/// Queue an inbound request by priority
+///
+/// Requests refused by the allowlist or priority cap are answered straight
+/// away, and control messages start on their own task without queueing.
+/// Nothing here waits, so the swarm keeps making progress.
+fn accept_request(
+    swarm: &mut Swarm<AxonBehaviour>,
+    ctx: &Arc<LeaderContext>,
+    queue: &mut RequestQueue<QueuedRequest>,
+    job: QueuedRequest,
+    done_tx: &mpsc::UnboundedSender<FinishedRequest>,
+) {
+    let response = match job.request {
+        _ if !ctx.is_allowed(&job.peer) => {
+            warn!("🚫 Rejecting request from unauthorized peer {}", job.peer);
+            AxonResponse::failure(
+                &job.request,
+                ErrorCode::Unauthorized,
+                "unauthorized peer".to_string(),
+            )
+        }
+        AxonRequest::Capabilities | AxonRequest::Ping => {
+            spawn_request(ctx, job, None, done_tx);
+            return;
+        }
+        _ if ctx.draining.load(Ordering::Relaxed) => AxonResponse::failure(
+            &job.request,
+            ErrorCode::BackendUnavailable,
+            "Leader is shutting down".to_string(),
+        ),
+        _ => match ctx.admit_priority(job.request.priority()) {
+            Ok(priority) => {
+                ctx.in_flight.fetch_add(1, Ordering::Relaxed);
+                ctx.track(job.request_id, job.peer);
+                queue.push(priority, job);
+                return;
+            }
+            Err(message) => {
+                warn!("⚠️ Rejecting request from {}: {}", job.peer, message);
+                AxonResponse::failure(&job.request, ErrorCode::InvalidRequest, message)
+            }
+        },
+    };
+
+    swarm
+        .behaviour_mut()
+        .request_response
+        .send_response(job.channel, response)
+        .ok();
+}
+
+/// A response produced by a request task, waiting to be sent by the swarm
+struct FinishedRequest {
+    channel: ResponseChannel<AxonResponse>,
+    /// `None` if the request was cancelled and there is nobody to answer
+    response: Option<AxonResponse>,
+    /// Whether the request came through the queue and counts as in flight
+    queued: bool,
+}
+
+/// Start the most urgent queued request on its own task
+fn spawn_next(
+    ctx: &Arc<LeaderContext>,
+    queue: &mut RequestQueue<QueuedRequest>,
+    permit: OwnedSemaphorePermit,
+    done_tx: &mpsc::UnboundedSender<FinishedRequest>,
+) {
+    if let Some(job) = queue.pop() {
+        spawn_request(ctx, job, Some(permit), done_tx);
+    }
+}
+
+/// Handle a request on its own task, handing the response back through `done_tx`
+///
+/// Queued requests hold a backend `permit` until they are done, bounding how
+/// many run at once. The request is abandoned if its token is cancelled, and
+/// answered with a timeout once the deadline its requester gave has passed.
+fn spawn_request(
+    ctx: &Arc<LeaderContext>,
+    job: QueuedRequest,
+    permit: Option<OwnedSemaphorePermit>,
+    done_tx: &mpsc::UnboundedSender<FinishedRequest>,
+) {
+    let ctx = Arc::clone(ctx);
+    let done_tx = done_tx.clone();
+    // Prefer the Subordinate's own id, so its logs and ours can be matched up
+    let request_id = match &job.request {
+        AxonRequest::Inference(InferenceRequest {
+            request_id: Some(id),
+            ..
+        }) => id.clone(),
+        _ => job.request_id.to_string(),
+    };
+    let span = info_span!("request", request_id = %request_id, peer = %job.peer);
+    let cancel = ctx.track(job.request_id, job.peer);
+    let deadline = job
+        .request
+        .timeout()
+        .map(|timeout| job.received_at + timeout);
+    let expired = deadline.map(|_| {
+        AxonResponse::failure(
+            &job.request,
+            ErrorCode::Timeout,
+            "Requester stopped waiting before the answer was ready".to_string(),
+        )
+    });
+    tokio::spawn(
+        async move {
+            let response = tokio::select! {
+                biased;
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    info!("⏰ Requester's deadline passed, abandoned its request");
+                    expired
+                }
+                response = handle_request(&ctx, job.peer, job.request, &cancel) => Some(response),
+            };
+            ctx.untrack(job.request_id);
+            // Nobody is left to send the cancelled answer to
+            let response = response.filter(|_| !cancel.is_cancelled());
+            if response.is_none() {
+                info!("🚫 Requester went away, abandoned its request");
+            }
+            let queued = permit.is_some();
+            drop(permit);
+            done_tx
+                .send(FinishedRequest {
+                    channel: job.channel,
+                    response,
+                    queued,
+                })
+                .ok();
+        }
+        .instrument(span),
+    );
+}
+
+/// Send a finished request's response back to its Subordinate
+fn finish_request(swarm: &mut Swarm<AxonBehaviour>, ctx: &LeaderContext, done: FinishedRequest) {
+    if done.queued {
+        ctx.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+    let Some(response) = done.response else {
+        return;
+    };
+    if done.queued {
+        ctx.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    debug!("✅ Sending response back");
+    swarm
+        .behaviour_mut()
+        .request_response
+        .send_response(done.channel, response)
+        .ok();
+}
+
+/// Process a single request from a Subordinate
+///
+/// Backend work stops with [`ErrorCode::Cancelled`] once `cancel` fires.
+async fn handle_request(
+    ctx: &LeaderContext,
+    peer: PeerId,
+    request: AxonRequest,
+    cancel: &CancellationToken,
+) -> AxonResponse {
+    // Control messages are answered without counting as backend work
+    match request {
+        AxonRequest::Capabilities => {
+            return AxonResponse::Capabilities(ctx.capabilities().await);
+        }
+        AxonRequest::Ping => {
+            return AxonResponse::Pong {
+                backend_healthy: ctx.backend_healthy().await,
+            };
+        }
+        _ => {}
+    }
+
+    // Note what was asked before the request is consumed, if history is kept
+    let asked = ctx.history.as_ref().and_then(|_| match &request {
+        AxonRequest::Inference(request) => Some((request.model.clone(), request.prompt.clone())),
+        AxonRequest::Chat(request) => Some((
+            request.model.clone(),
+            request
+                .messages
+                .last()
+                .map(|message| message.content.clone())
+                .unwrap_or_default(),
+        )),
+        _ => None,
+    });
+
+    let started = Instant::now();
+    let cache_key = ctx
+        .cache
+        .as_ref()
+        .and_then(|_| ResponseCache::key(&request, &ctx.model));
+    let cached = ctx
+        .cache
+        .as_ref()
+        .zip(cache_key.as_ref())
+        .and_then(|(cache, key)| {
+            let cached = cache.get(key);
+            ctx.metrics.record_cache_lookup(cached.is_some());
+            cached
+        });
+
+    let response = match cached {
+        Some(mut response) => {
+            info!("💾 Answered from the response cache");
+            if let AxonRequest::Inference(request) = &request {
+                response.request_id = request.request_id.clone();
+            }
+            AxonResponse::Inference(response)
+        }
+        None => {
+            let response = dispatch_request(ctx, peer, request, cancel).await;
+            ctx.metrics
+                .record_request(response.failure_code(), started.elapsed());
+            if let Some(cache) = &ctx.cache
+                && let Some(key) = &cache_key
+                && let AxonResponse::Inference(response) = &response
+            {
+                cache.insert(key, response);
+            }
+            response
+        }
+    };
+
+    if let Some(history) = &ctx.history
+        && let Some((model, prompt)) = asked
+    {
+        let (success, response_len) = match &response {
+            AxonResponse::Inference(response) => (response.success, response.response.len()),
+            AxonResponse::Chat(response) => (
+                response.success,
+                response.message.as_ref().map_or(0, |m| m.content.len()),
+            ),
+            _ => (false, 0),
+        };
+        history.record(HistoryEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            peer: peer.to_string(),
+            model: model.unwrap_or_else(|| ctx.model.clone()),
+            prompt,
+            response_len,
+            success,
+            latency_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+
+    response
+}
+
+/// Serve a single request from a Subordinate with Ollama, unless `cancel`
+/// fires first
+async fn dispatch_request(
+    ctx: &LeaderContext,
+    peer: PeerId,
+    request: AxonRequest,
+    cancel: &CancellationToken,
+) -> AxonResponse {
+    let backend = ctx.backend.as_ref();
+    let default_model = ctx.model.as_str();
+
+    // e.g. the requester went away while this waited in the queue
+    if cancel.is_cancelled() {
+        return AxonResponse::failure(&request, ErrorCode::Cancelled, format!("{}", Cancelled));
+    }
+
+    if let Err(message) = ctx.check_prompt_chars(request.prompt_chars()) {
+        warn!("⚠️ Rejecting request: {}", message);
+        return AxonResponse::failure(&request, ErrorCode::PromptTooLong, message);
+    }
+
+    // Reject unknown models up front rather than relaying Ollama's opaque error
+    if matches!(
+        request,
+        AxonRequest::Inference(_)
+            | AxonRequest::Embedding(_)
+            | AxonRequest::Chat(_)
+            | AxonRequest::Batch(_)
+            | AxonRequest::CountTokens(_)
+    ) {
+        let model_name = request.model().unwrap_or(default_model);
+        if let Err(message) = ctx.check_model(model_name).await {
+            warn!("⚠️ Rejecting request: {}", message);
+            return AxonResponse::failure(&request, ErrorCode::ModelNotFound, message);
+        }
+    }
+
+    match request {
+        AxonRequest::Inference(request) => {
+            info!("📨 Received inference request: {:?}", request.prompt);
+
+            if let Err(e) = check_images(&request.images, ctx.max_image_bytes) {
+                return AxonResponse::Inference(InferenceResponse {
+                    response: String::new(),
+                    success: false,
+                    error: Some(e),
+                    error_code: Some(ErrorCode::InvalidRequest),
+                    stats: None,
+                    session_id: request.session_id,
+                    request_id: request.request_id,
+                });
+            }
+
+            let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+            let history = request
+                .session_id
+                .as_deref()
+                .map(|id| ctx.sessions.lock().unwrap().history(peer, id));
+
+            // Everything the model will read: earlier turns, the conversation and the prompt
+            let text = history
+                .iter()
+                .chain(&request.messages)
+                .flatten()
+                .map(|message| message.content.as_str())
+                .chain(iter::once(request.prompt.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Err((code, message)) = ctx.check_prompt_length(&model_name, &text).await {
+                warn!("⚠️ Rejecting request: {}", message);
+                return AxonResponse::Inference(InferenceResponse {
+                    response: String::new(),
+                    success: false,
+                    error: Some(message),
+                    error_code: Some(code),
+                    stats: None,
+                    session_id: request.session_id,
+                    request_id: request.request_id,
+                });
+            }
+
+            let options = ctx.options_for(&model_name, request.options);
+            let format = ctx.prompt_format(request.format);
+            let output = format.output.clone();
+            let session_id = request.session_id;
+            let request_id = request.request_id;
+
+            let result = match (request.messages, history) {
+                (None, None) => {
+                    let chunks = ctx
+                        .with_auto_pull(&model_name, || {
+                            backend.generate_stream(
+                                request.prompt.clone(),
+                                model_name.clone(),
+                                request.images.clone(),
+                                options.clone(),
+                                format.clone(),
+                                cancel,
+                            )
+                        })
+                        .await;
+                    match chunks {
+                        Ok(chunks) => collect_generation(chunks).await.and_then(|generation| {
+                            check_output(&generation.response, output.as_ref())?;
+                            Ok(generation)
+                        }),
+                        Err(e) => Err(e),
+                    }
+                }
+                // Conversations go through the chat endpoint
+                (messages, history) => {
+                    let mut turns = messages.unwrap_or_default();
+                    if !request.prompt.is_empty() {
+                        turns.push(ChatMessage {
+                            role: ChatRole::User,
+                            content: request.prompt,
+                        });
+                    }
+                    let mut conversation = history.unwrap_or_default();
+                    conversation.extend(turns.iter().cloned());
+                    if let Some(system) = format.system
+                        && conversation
+                            .first()
+                            .is_none_or(|message| message.role != ChatRole::System)
+                    {
+                        conversation.insert(
+                            0,
+                            ChatMessage {
+                                role: ChatRole::System,
+                                content: system,
+                            },
+                        );
+                    }
+
+                    let reply = ctx
+                        .with_auto_pull(&model_name, || {
+                            backend.chat(
+                                conversation.clone(),
+                                model_name.clone(),
+                                options.clone(),
+                                output.clone(),
+                                cancel,
+                            )
+                        })
+                        .await
+                        .and_then(|reply| {
+                            check_output(&reply.message.content, output.as_ref())?;
+                            Ok(reply)
+                        });
+                    if let (Ok(reply), Some(id)) = (&reply, &session_id) {
+                        turns.push(reply.message.clone());
+                        ctx.sessions.lock().unwrap().record(peer, id, turns);
+                    }
+                    reply.map(|reply| Generation {
+                        response: reply.message.content,
+                        stats: reply.stats,
+                    })
+                }
+            };
+            AxonResponse::Inference(InferenceResponse {
+                session_id,
+                request_id,
+                ..inference_response(result)
+            })
+        }
+        AxonRequest::Batch(request) => {
+            info!(
+                "📨 Received batch request: {} prompt(s)",
+                request.prompts.len()
+            );
+
+            if request.prompts.is_empty() {
+                return AxonResponse::Batch(BatchResponse {
+                    results: Vec::new(),
+                    success: false,
+                    error: Some("Batch request contains no prompts".to_string()),
+                    error_code: Some(ErrorCode::InvalidRequest),
+                });
+            }
+
+            // Prompts run one at a time; a failure only affects its own result
+            let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+            let options = ctx.options_for(&model_name, request.options);
+            let format = ctx.prompt_format(PromptFormat::default());
+            let mut results = Vec::with_capacity(request.prompts.len());
+            for prompt in request.prompts {
+                let result = match ctx.check_prompt_length(&model_name, &prompt).await {
+                    Err((code, message)) => Err(BackendError { code, message }.into()),
+                    Ok(()) => {
+                        ctx.with_auto_pull(&model_name, || {
+                            backend.generate(
+                                prompt.clone(),
+                                model_name.clone(),
+                                Vec::new(),
+                                options.clone(),
+                                format.clone(),
+                                cancel,
+                            )
+                        })
+                        .await
+                    }
+                };
+                results.push(inference_response(result));
+            }
+
+            AxonResponse::Batch(BatchResponse {
+                results,
+                success: true,
+                error: None,
+                error_code: None,
+            })
+        }
+        AxonRequest::Embedding(request) => {
+            info!(
+                "📨 Received embedding request: {} input(s)",
+                request.input.len()
+            );
+
+            if request.input.is_empty() {
+                return AxonResponse::Embedding(EmbeddingResponse {
+                    embeddings: Vec::new(),
+                    success: false,
+                    error: Some("Embedding request contains no input".to_string()),
+                    error_code: Some(ErrorCode::InvalidRequest),
+                });
+            }
+
+            let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+            let mut embeddings = Vec::with_capacity(request.input.len());
+            for input in request.input {
+                let embedding = ctx
+                    .with_auto_pull(&model_name, || {
+                        backend.embed(input.clone(), model_name.clone())
+                    })
+                    .await;
+                match embedding {
+                    Ok(embedding) => embeddings.push(embedding),
+                    Err(e) => {
+                        return AxonResponse::Embedding(EmbeddingResponse {
+                            embeddings: Vec::new(),
+                            success: false,
+                            error: Some(format!("{}", e)),
+                            error_code: Some(ollama::error_code(&e)),
+                        });
+                    }
+                }
+            }
+
+            AxonResponse::Embedding(EmbeddingResponse {
+                embeddings,
+                success: true,
+                error: None,
+                error_code: None,
+            })
+        }
+        AxonRequest::Chat(request) => {
+            info!(
+                "📨 Received chat request: {} message(s)",
+                request.messages.len()
+            );
+
+            if request.messages.is_empty() {
+                return AxonResponse::Chat(ChatResponse {
+                    message: None,
+                    success: false,
+                    error: Some("Chat request contains no messages".to_string()),
+                    error_code: Some(ErrorCode::InvalidRequest),
+                });
+            }
+
+            let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+            let text = conversation_text(&request.messages);
+            if let Err((code, message)) = ctx.check_prompt_length(&model_name, &text).await {
+                warn!("⚠️ Rejecting request: {}", message);
+                return AxonResponse::Chat(ChatResponse {
+                    message: None,
+                    success: false,
+                    error: Some(message),
+                    error_code: Some(code),
+                });
+            }
+
+            let options = ctx.options_for(&model_name, request.options);
+            let reply = ctx
+                .with_auto_pull(&model_name, || {
+                    backend.chat(
+                        request.messages.clone(),
+                        model_name.clone(),
+                        options.clone(),
+                        None,
+                        cancel,
+                    )
+                })
+                .await;
+            let response = match reply {
+                Ok(reply) => ChatResponse {
+                    message: Some(reply.message),
+                    success: true,
+                    error: None,
+                    error_code: None,
+                },
+                Err(e) => ChatResponse {
+                    message: None,
+                    success: false,
+                    error: Some(format!("{}", e)),
+                    error_code: Some(ollama::error_code(&e)),
+                },
+            };
+            AxonResponse::Chat(response)
+        }
+        AxonRequest::CountTokens(request) => {
+            info!("📨 Received token count request");
+
+            let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+            let context_length = match ctx.details(&model_name).await {
+                Ok(details) => details.context_length,
+                Err(e) => {
+                    return AxonResponse::CountTokens(CountTokensResponse {
+                        tokens: 0,
+                        estimated: false,
+                        context_length: None,
+                        success: false,
+                        error: Some(format!("{}", e)),
+                        error_code: Some(ollama::error_code(&e)),
+                    });
+                }
+            };
+
+            let (tokens, estimated) =
+                match backend.tokenize(request.prompt.clone(), model_name).await {
+                    Ok(tokens) => (tokens, false),
+                    Err(_) => (estimate_tokens(&request.prompt), true),
+                };
+
+            AxonResponse::CountTokens(CountTokensResponse {
+                tokens,
+                estimated,
+                context_length,
+                success: true,
+                error: None,
+                error_code: None,
+            })
+        }
+        AxonRequest::Capabilities => AxonResponse::Capabilities(ctx.capabilities().await),
+        AxonRequest::Ping => AxonResponse::Pong {
+            backend_healthy: ctx.backend_healthy().await,
+        },
+        AxonRequest::Unsupported => {
+            warn!("⚠️ Received unsupported request type");
+            AxonResponse::Unsupported {
+                protocol: PROTOCOL_NAME.to_string(),
+            }
+        }
+    }
+}
+
+/// All the text of a conversation, as the context-window check measures it
+fn conversation_text(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Turn the outcome of a generation into a response for the Subordinate
+fn inference_response(result: Result<Generation>) -> InferenceResponse {
+    match result {
+        Ok(generation) => InferenceResponse {
+            response: generation.response,
+            success: true,
+            error: None,
+            error_code: None,
+            stats: generation.stats,
+            session_id: None,
+            request_id: None,
+        },
+        Err(e) => InferenceResponse {
+            response: String::new(),
+            success: false,
+            error: Some(format!("{}", e)),
+            error_code: Some(ollama::error_code(&e)),
+            stats: None,
+            session_id: None,
+            request_id: None,
+        },
+    }
+}
+
+/// Refuse a model's answer that isn't JSON when `output` asked for it
+///
+/// Only the syntax is checked, not whether the answer follows a schema.
+fn check_output(text: &str, output: Option<&OutputFormat>) -> Result<()> {
+    if output.is_some()
+        && let Err(e) = serde_json::from_str::<serde_json::Value>(text)
+    {
+        return Err(BackendError {
+            code: ErrorCode::InvalidOutput,
+            message: format!("Model's answer is not valid JSON: {}", e),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Assemble a streamed generation into a single response
+async fn collect_generation(
+    mut chunks: mpsc::Receiver<Result<GenerationChunk>>,
+) -> Result<Generation> {
+    let started = Instant::now();
+    let mut response = String::new();
+
+    while let Some(chunk) = chunks.recv().await {
+        match chunk? {
+            GenerationChunk::Token(token) => {
+                if response.is_empty() && !token.is_empty() {
+                    debug!("⏱️ First token after {:?}", started.elapsed());
+                }
+                response.push_str(&token);
+            }
+            GenerationChunk::Done { stats } => return Ok(Generation { response, stats }),
+        }
+    }
+
+    anyhow::bail!("Generation stream closed before finishing")
+}
+
+/// Ensure every attached image is valid base64 and within the size limit
+fn check_images(images: &[String], max_image_bytes: usize) -> Result<(), String> {
+    for (index, image) in images.iter().enumerate() {
+        let bytes = BASE64
+            .decode(image)
+            .map_err(|e| format!("Image {} is not valid base64: {}", index + 1, e))?;
+        if bytes.len() > max_image_bytes {
+            return Err(format!(
+                "Image {} is {} bytes, exceeds the Leader's limit of {} bytes",
+                index + 1,
+                bytes.len(),
+                max_image_bytes
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Turn a Leader's answer to an HTTP ask into the result for the handler
+fn ask_result(
+    response: AxonResponse,
+    model: String,
+    served_by: String,
+) -> Result<AskResponse, AskError> {
+    match response {
+        AxonResponse::Inference(response) if response.success => Ok(AskResponse {
+            answer: response.response,
+            model,
+            served_by,
+            stats: response.stats,
+        }),
+        AxonResponse::Inference(response) => Err(AskError {
+            code: response.error_code.unwrap_or(ErrorCode::Internal),
+            message: response
+                .error
+                .unwrap_or_else(|| "Unknown error".to_string()),
+        }),
+        AxonResponse::Unsupported { protocol } => Err(AskError {
+            code: ErrorCode::Internal,
+            message: format!("Leader ({}) does not support this request type", protocol),
+        }),
+        AxonResponse::Embedding(_)
+        | AxonResponse::Chat(_)
+        | AxonResponse::Batch(_)
+        | AxonResponse::CountTokens(_)
+        | AxonResponse::Capabilities(_)
+        | AxonResponse::Pong { .. } => Err(AskError {
+            code: ErrorCode::Internal,
+            message: "Unexpected response type from Leader".to_string(),
+        }),
+    }
+}
+
+/// Answer an HTTP ask with this node's own Ollama, sharing the backend slots
+/// with requests from peers
+///
+/// If the HTTP client disconnects first, the HTTP handler drops its end of
+/// `responder` and the work is abandoned, which also stops Ollama generating.
+fn answer_locally(
+    ctx: &Arc<LeaderContext>,
+    local_peer_id: PeerId,
+    request: AxonRequest,
+    model: String,
+    mut responder: oneshot::Sender<Result<AskResponse, AskError>>,
+) {
+    ctx.in_flight.fetch_add(1, Ordering::Relaxed);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let cancel = CancellationToken::new();
+        let work = async {
+            let _permit = ctx.backend_slots.clone().acquire_owned().await;
+            handle_request(&ctx, local_peer_id, request, &cancel).await
+        };
+        let response = tokio::select! {
+            response = work => Some(response),
+            _ = responder.closed() => {
+                cancel.cancel();
+                None
+            }
+        };
+
+        ctx.in_flight.fetch_sub(1, Ordering::Relaxed);
+        match response {
+            Some(response) => {
+                ctx.completed.fetch_add(1, Ordering::Relaxed);
+                let _ = responder.send(ask_result(response, model, "local".to_string()));
+            }
+            None => info!("🚫 HTTP client disconnected, cancelled its local request"),
+        }
+    });
+}
+
+/// Pick a connected Leader for a request for `model`, skipping those known
+/// not to serve it
+///
+/// Discovery can still report a peer whose connection has gone, so only an
+/// open connection makes a Leader routable.
+fn pick_leader(
+    leaders: &mut LeaderPool,
+    peer_models: &PeerModels,
+    swarm: &Swarm<AxonBehaviour>,
+    model: &str,
+) -> Option<PeerId> {
+    leaders.next_where(|p| swarm.is_connected(p) && peer_models.may_serve(p, model))
+}
+
+/// Stream this node's own reply to a web chat, cancelling it if the client
+/// goes away
+fn stream_locally(
+    ctx: &Arc<LeaderContext>,
+    messages: Vec<ChatMessage>,
+    model: String,
+    events: mpsc::Sender<ChatEvent>,
+) {
+    ctx.in_flight.fetch_add(1, Ordering::Relaxed);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let cancel = CancellationToken::new();
+        let work = async {
+            let _permit = ctx.backend_slots.clone().acquire_owned().await;
+            stream_chat(&ctx, messages, model, &events, &cancel).await
+        };
+        let result = tokio::select! {
+            result = work => Some(result),
+            _ = events.closed() => {
+                cancel.cancel();
+                None
+            }
+        };
+
+        ctx.in_flight.fetch_sub(1, Ordering::Relaxed);
+        match result {
+            Some(Ok(())) => {
+                ctx.completed.fetch_add(1, Ordering::Relaxed);
+                ctx.metrics.record_request(None, started.elapsed());
+            }
+            Some(Err(e)) => {
+                let code = ollama::error_code(&e);
+                ctx.metrics.record_request(Some(code), started.elapsed());
+                warn!("❌ Chat failed: {:#}", e);
+                let _ = events
+                    .send(ChatEvent::Failed(AskError {
+                        code,
+                        message: format!("{:#}", e),
+                    }))
+                    .await;
+            }
+            None => info!("🚫 WebSocket client disconnected, cancelled its local chat"),
+        }
+    });
+}
+
+/// Run a chat on this node's backend, sending each token to `events` until
+/// `cancel` fires
+async fn stream_chat(
+    ctx: &LeaderContext,
+    mut messages: Vec<ChatMessage>,
+    model: String,
+    events: &mpsc::Sender<ChatEvent>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let chars = messages
+        .iter()
+        .map(|message| message.content.chars().count())
+        .sum();
+    ctx.check_prompt_chars(chars)
+        .map_err(|message| BackendError {
+            code: ErrorCode::PromptTooLong,
+            message,
+        })?;
+    ctx.check_model(&model)
+        .await
+        .map_err(|message| BackendError {
+            code: ErrorCode::ModelNotFound,
+            message,
+        })?;
+    ctx.check_prompt_length(&model, &conversation_text(&messages))
+        .await
+        .map_err(|(code, message)| BackendError { code, message })?;
+    if let Some(system) = &ctx.system
+        && messages
+            .first()
+            .is_none_or(|message| message.role != ChatRole::System)
+    {
+        messages.insert(
+            0,
+            ChatMessage {
+                role: ChatRole::System,
+                content: system.clone(),
+            },
+        );
+    }
+    let options = ctx.options_for(&model, None);
+
+    let mut chunks = ctx
+        .with_auto_pull(&model, || {
+            ctx.backend.chat_stream(
+                messages.clone(),
+                model.clone(),
+                options.clone(),
+                None,
+                cancel,
+            )
+        })
+        .await?;
+    while let Some(chunk) = chunks.recv().await {
+        let event = match chunk? {
+            GenerationChunk::Token(token) => ChatEvent::Token(token),
+            GenerationChunk::Done { stats } => ChatEvent::Done(stats),
+        };
+        let finished = matches!(event, ChatEvent::Done(_));
+        if events.send(event).await.is_err() || finished {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("Chat stream closed before finishing")
+}
+
+/// Pass a remote Leader's answer on to a web chat
+async fn relay_answer(
+    answer: oneshot::Receiver<Result<AskResponse, AskError>>,
+    events: mpsc::Sender<ChatEvent>,
+) {
+    let Ok(answer) = answer.await else {
+        return;
+    };
+    match answer {
+        Ok(answer) => {
+            if events.send(ChatEvent::Token(answer.answer)).await.is_ok() {
+                let _ = events.send(ChatEvent::Done(answer.stats)).await;
+            }
+        }
+        Err(e) => {
+            let _ = events.send(ChatEvent::Failed(e)).await;
+        }
+    }
+}
+
+/// Periodically probe the backend, logging when it goes away and comes back
+///
+/// `reachable` is what the startup check found, if it ran.
+async fn watch_backend(ctx: Arc<LeaderContext>, mut reachable: Option<bool>) {
+    let mut timer = tokio::time::interval(BACKEND_CHECK_INTERVAL);
+    loop {
+        timer.tick().await;
+        let result = ctx.backend.health().await;
+        let healthy = result.is_ok();
+        *ctx.last_health_probe.lock().unwrap() = Some((Instant::now(), healthy));
+        if reachable == Some(healthy) {
+            continue;
+        }
+        reachable = Some(healthy);
+
+        let url = ctx.backend.endpoint();
+        match result {
+            Ok(name) => info!("✅ {} is reachable at {}", name, url),
+            Err(e) => warn!("🔌 Backend is unreachable at {}: {:#}", url, e),
+        }
+    }
+}
+
+/// Load `models` now and every [`KEEP_WARM_INTERVAL`], so they stay resident
+/// even if Ollama restarts or their keep-alive is shorter than the interval
+async fn keep_models_warm(ctx: Arc<LeaderContext>, models: Vec<String>) {
+    let mut timer = tokio::time::interval(KEEP_WARM_INTERVAL);
+    loop {
+        timer.tick().await;
+        for model in &models {
+            match ctx.backend.load_model(model.clone()).await {
+                Ok(()) => debug!("📌 Model '{}' is loaded", model),
+                Err(e) => warn!("⚠️ Could not load model '{}': {:#}", model, e),
+            }
+        }
+    }
+}
+
+/// The directory of web UI files to serve, if any
+///
+/// A --ui-dir without an `index.html` is an error; without --ui-dir the UI is
+/// served from [`DEFAULT_UI_DIR`] once it has been built.
+fn web_ui_dir(leader: &LeaderArgs) -> Result<Option<PathBuf>> {
+    match &leader.ui_dir {
+        Some(dir) => {
+            anyhow::ensure!(
+                dir.join("index.html").is_file(),
+                "--ui-dir '{}' has no index.html",
+                dir.display()
+            );
+            Ok(Some(dir.clone()))
+        }
+        None => {
+            let dir = PathBuf::from(DEFAULT_UI_DIR);
+            if dir.join("index.html").is_file() {
+                Ok(Some(dir))
+            } else {
+                info!(
+                    "🖥️ No web UI at {}; run `npm run build` in web-app to serve it",
+                    DEFAULT_UI_DIR
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Run Leader with HTTP API server (Web UI mode)
+async fn run_leader_with_http(
+    mut swarm: Swarm<AxonBehaviour>,
+    mut static_peers: StaticPeers,
+    mut ping_failures: PingFailures,
+    ctx: Arc<LeaderContext>,
+    leader: LeaderArgs,
+    ask_timeout: Duration,
+) -> Result<()> {
+    let started = Instant::now();
+
+    // Create command channel for HTTP -> Swarm communication
+    let (command_tx, mut command_rx) = mpsc::channel::<SwarmCommand>(32);
+
+    // Store pending requests: RequestId -> (model asked for, oneshot::Sender)
+    let mut pending_requests: HashMap<
+        OutboundRequestId,
+        (String, oneshot::Sender<Result<AskResponse, AskError>>),
+    > = HashMap::new();
+
+    // Leaders discovered via mDNS, rotated through for HTTP requests
+    let mut leaders = LeaderPool::new();
+    let mut heartbeats = Heartbeats::new();
+    let mut heartbeat_timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    // The models each of those Leaders serves, so requests go to one that has it
+    let mut peer_models = PeerModels::new();
+
+    // Every peer discovered or connected, for /api/peers
+    let mut peer_table = PeerTable::new();
+
+    // Requests waiting for the backend, most urgent first
+    let mut queue = RequestQueue::new();
+
+    // Backend tasks hand finished responses back to this loop for sending
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+
+    // Tells the HTTP server to stop accepting connections
+    let (stop_http_tx, mut stop_http_rx) = watch::channel(false);
+
+    let api_key = leader.http_api_key.clone();
+    match api_key.as_deref() {
+        Some(key) if key.trim().is_empty() => anyhow::bail!("--http-api-key is empty"),
+        Some(_) => info!("🔒 Web API requires a bearer token"),
+        None => {}
+    }
+
+    // Browsers must be allowed to send the key
+    let mut cors_headers = leader.cors_headers.clone();
+    if api_key.is_some()
+        && !cors_headers
+            .iter()
+            .any(|name| name.trim().eq_ignore_ascii_case("authorization"))
+    {
+        cors_headers.push("authorization".to_string());
+    }
+    let ui_dir = web_ui_dir(&leader)?;
+    let chats = Arc::new(ChatSessions::new(
+        Duration::from_secs(leader.session_ttl_secs),
+        leader.max_sessions,
+        leader.max_session_messages,
+    ));
+    let tls = match (&leader.tls_cert, &leader.tls_key) {
+        (Some(cert), Some(key)) => Some(http_server::load_tls(cert, key).await?),
+        (None, None) => None,
+        _ => anyhow::bail!("--tls-cert and --tls-key must be given together"),
+    };
+
+    let origins = Arc::new(OriginPolicy::new(&leader.cors_origins)?);
+    if leader.cors_origins.is_empty() {
+        info!(
+            "🌍 Browsers may call the API from localhost pages only; allow others with --cors-origin"
+        );
+    } else {
+        info!(
+            "🌍 Browsers may call the API from {}",
+            leader.cors_origins.join(", ")
+        );
+    }
+    let access = ApiAccess {
+        cors: http_server::cors_layer(origins.clone(), &leader.cors_methods, &cors_headers)?,
+        origins,
+        api_key,
+        rate_limiter: leader.rate_limit.map(|per_minute| {
+            Arc::new(RateLimiter::new(
+                per_minute,
+                leader.rate_burst,
+                leader.trust_proxy,
+            ))
+        }),
+        ask_limit: Arc::new(AskLimit::new(leader.max_pending_asks)),
+    };
+
+    // Spawn HTTP server in background
+    let backend = ctx.backend.clone();
+    let max_prompt_chars = ctx.max_prompt_chars;
+    let max_ask_timeout = Duration::from_secs(leader.max_ask_timeout_secs);
+    let metrics = ctx.metrics.clone();
+    let _http_handle = tokio::spawn(async move {
+        let stop = async move {
+            stop_http_rx.wait_for(|stop| *stop).await.ok();
+        };
+        if let Err(e) = http_server::start_server(
+            command_tx,
+            backend,
+            ask_timeout,
+            max_ask_timeout,
+            max_prompt_chars,
+            metrics,
+            access,
+            chats,
+            ui_dir,
+            leader.http_addr,
+            tls,
+            stop,
+        )
+        .await
+        {
+            error!("HTTP server error: {}", e);
+        }
+    });
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut drain_deadline: Option<Instant> = None;
+
+    // Inbound requests whose response the swarm hasn't finished sending
+    let mut unanswered: HashSet<InboundRequestId> = HashSet::new();
+
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    // Main event loop with tokio::select!
+    loop {
+        if drain_deadline.is_some()
+            && ctx.in_flight.load(Ordering::Relaxed) == 0
+            && pending_requests.is_empty()
+            && unanswered.is_empty()
+        {
+            break;
+        }
+
+        tokio::select! {
+            // Queued work is only started once no other event is ready
+            biased;
+
+            _ = &mut shutdown, if drain_deadline.is_none() => {
+                stop_http_tx.send(true).ok();
+                drain_deadline = Some(start_draining(&ctx, &leader));
+            }
+            _ = tokio::time::sleep_until(drain_deadline.unwrap_or_else(Instant::now)), if drain_deadline.is_some() => {
+                break;
+            }
+
+            Some(done) = done_rx.recv() => {
+                finish_request(&mut swarm, &ctx, done);
+            }
+            Some((channel, response)) = control_rx.recv() => {
+                swarm.behaviour_mut().control.send_response(channel, response).ok();
+            }
+
+            // Check that known Leaders are still alive
+            _ = heartbeat_timer.tick() => {
+                send_heartbeats(&mut swarm, &leaders, &mut heartbeats);
+            }
+
+            // Handle HTTP commands from web UI
+            Some(cmd) = command_rx.recv() => {
+                match cmd {
+                    // The model list comes from this Leader's own Ollama, no peer needed
+                    SwarmCommand::ListModels { responder } => {
+                        let remote: Vec<(String, Vec<String>)> = leaders
+                            .iter()
+                            .filter(|peer_id| swarm.is_connected(peer_id))
+                            .filter_map(|peer_id| {
+                                Some((peer_id.to_string(), peer_models.advertised(peer_id)?.to_vec()))
+                            })
+                            .collect();
+                        let ctx = ctx.clone();
+                        tokio::spawn(async move {
+                            // Peers' models are still worth listing if our backend is down
+                            let (local, warning) = match ctx.models().await {
+                                Ok(models) => (
+                                    models
+                                        .into_iter()
+                                        .filter(|m| ctx.serves(&m.name))
+                                        .map(|m| m.name)
+                                        .collect(),
+                                    None,
+                                ),
+                                Err(e) => {
+                                    warn!("⚠️ Could not list local models: {:#}", e);
+                                    (Vec::new(), Some(format!("Could not list local models: {:#}", e)))
+                                }
+                            };
+                            let _ = responder.send(ModelsResponse::combine(local, remote, warning));
+                        });
+                    }
+                    SwarmCommand::Status { responder } => {
+                        let mut status = NodeStatus {
+                            peer_id: swarm.local_peer_id().to_string(),
+                            listen_addrs: swarm.listeners().map(Multiaddr::to_string).collect(),
+                            uptime_secs: started.elapsed().as_secs(),
+                            model: ctx.model.clone(),
+                            ollama_reachable: false,
+                            discovered_peers: peer_table
+                                .iter()
+                                .filter(|(_, seen)| seen.discovered_at.is_some())
+                                .count(),
+                            connected_peers: swarm.connected_peers().count(),
+                            pending_p2p_requests: pending_requests.len(),
+                            in_flight_requests: ctx.in_flight.load(Ordering::Relaxed),
+                            completed_requests: ctx.completed.load(Ordering::Relaxed),
+                        };
+                        // The probe may wait on the backend, so it runs off the loop
+                        let ctx = ctx.clone();
+                        tokio::spawn(async move {
+                            status.ollama_reachable = ctx.backend_healthy().await;
+                            let _ = responder.send(status);
+                        });
+                    }
+                    SwarmCommand::ListPeers { responder } => {
+                        let mut peers: Vec<PeerInfo> = peer_table
+                            .iter()
+                            .map(|(peer_id, seen)| PeerInfo {
+                                peer_id: peer_id.to_string(),
+                                addresses: seen.addresses.iter().map(Multiaddr::to_string).collect(),
+                                connected: swarm.is_connected(peer_id),
+                                discovered_at_ms: seen.discovered_at.map(|at| {
+                                    at.duration_since(UNIX_EPOCH)
+                                        .map_or(0, |d| d.as_millis() as u64)
+                                }),
+                                models: peer_models.advertised(peer_id).map(<[String]>::to_vec),
+                            })
+                            .collect();
+                        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+                        let _ = responder.send(peers);
+                    }
+                    SwarmCommand::Chat { messages, model, events } => {
+                        if drain_deadline.is_some() {
+                            let _ = events.try_send(ChatEvent::Failed(AskError {
+                                code: ErrorCode::BackendUnavailable,
+                                message: "Leader is shutting down".to_string(),
+                            }));
+                            continue;
+                        }
+
+                        let model = model.unwrap_or_else(|| ctx.model.clone());
+                        let Some(peer_id) = pick_leader(&mut leaders, &peer_models, &swarm, &model) else {
+                            if leader.no_local_fallback {
+                                let _ = events.try_send(ChatEvent::Failed(AskError {
+                                    code: ErrorCode::BackendUnavailable,
+                                    message: "No Leader peers connected yet. Start a Leader with 'serve' on the network.".to_string(),
+                                }));
+                            } else {
+                                info!("🏠 No remote Leader available, chatting locally");
+                                stream_locally(&ctx, messages, model, events);
+                            }
+                            continue;
+                        };
+
+                        // Leaders answer in one piece, so the reply arrives as a single token
+                        info!("📤 Forwarding chat to Leader: {}", peer_id);
+                        let request = AxonRequest::Inference(InferenceRequest {
+                            prompt: String::new(),
+                            model: Some(model.clone()),
+                            images: Vec::new(),
+                            messages: Some(messages),
+                            priority: None,
+                            session_id: None,
+                            options: None,
+                            format: PromptFormat::default(),
+                            request_id: None,
+                            timeout_secs: None,
+                        });
+                        let req_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, request);
+                        let (responder, answer) = oneshot::channel();
+                        pending_requests.insert(req_id, (model, responder));
+                        tokio::spawn(relay_answer(answer, events));
+                    }
+                    SwarmCommand::Ask { prompt, model, system, options, priority, format, timeout, responder } => {
+                        if drain_deadline.is_some() {
+                            let _ = responder.send(Err(AskError {
+                                code: ErrorCode::BackendUnavailable,
+                                message: "Leader is shutting down".to_string(),
+                            }));
+                            continue;
+                        }
+
+                        info!("🌐 HTTP request: {}", prompt);
+                        let named = model.is_some();
+                        let model = model.unwrap_or_else(|| ctx.model.clone());
+                        let local_first = leader.prefer_local && ctx.serves(&model);
+                        let remote = if local_first {
+                            None
+                        } else {
+                            pick_leader(&mut leaders, &peer_models, &swarm, &model)
+                        };
+
+                        // A model nobody serves is the client's mistake, not a backend failure
+                        if named && remote.is_none() && (leader.no_local_fallback || !ctx.serves(&model)) {
+                            let _ = responder.send(Err(AskError {
+                                code: ErrorCode::InvalidRequest,
+                                message: format!("Model '{}' is not available on this Leader or its peers", model),
+                            }));
+                            continue;
+                        }
+
+                        let request = AxonRequest::Inference(InferenceRequest {
+                            prompt,
+                            model: Some(model.clone()),
+                            images: Vec::new(),
+                            messages: None,
+                            priority,
+                            session_id: None,
+                            options,
+                            format: PromptFormat {
+                                system,
+                                output: format,
+                                ..PromptFormat::default()
+                            },
+                            request_id: None,
+                            timeout_secs: Some(timeout.as_secs()),
+                        });
+
+                        let Some(peer_id) = remote else {
+                            if leader.no_local_fallback {
+                                let _ = responder.send(Err(AskError {
+                                    code: ErrorCode::BackendUnavailable,
+                                    message: "No Leader peers connected yet. Start a Leader with 'serve' on the network.".to_string(),
+                                }));
+                            } else {
+                                if local_first {
+                                    info!("🏠 Answering locally (--prefer-local)");
+                                } else {
+                                    info!("🏠 No remote Leader available, answering locally");
+                                }
+                                answer_locally(&ctx, *swarm.local_peer_id(), request, model, responder);
+                            }
+                            continue;
+                        };
+
+                        info!("📤 Forwarding to Leader: {}", peer_id);
+                        let req_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, request);
+                        pending_requests.insert(req_id, (model, responder));
+                    }
+                }
+            }
+
+            // Handle P2P swarm events
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+            }
+
+            event = swarm.select_next_some() => {
+                match discovery_event(event, &mut static_peers, &mut ping_failures) {
+                    SwarmEvent::NewListenAddr { address, .. } => {
+                        info!("👂 Listening on: {}", address);
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                        for (peer_id, addr) in peers {
+                            peer_table.discovered(peer_id, addr);
+                            if leaders.insert(peer_id) {
+                                info!("🔍 Discovered peer: {}", peer_id);
+                                let query_id = swarm
+                                    .behaviour_mut()
+                                    .request_response
+                                    .send_request(&peer_id, AxonRequest::Capabilities);
+                                peer_models.sent(query_id, peer_id);
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message:
+                                request_response::Message::Request {
+                                    request_id,
+                                    request,
+                                    channel,
+                                },
+                            peer,
+                        },
+                    )) => {
+                        unanswered.insert(request_id);
+                        let job = QueuedRequest {
+                            request_id,
+                            peer,
+                            request,
+                            channel,
+                            received_at: Instant::now(),
+                        };
+                        accept_request(&mut swarm, &ctx, &mut queue, job, &done_tx);
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::ResponseSent { request_id, .. }
+                        | request_response::Event::InboundFailure { request_id, .. },
+                    )) => {
+                        unanswered.remove(&request_id);
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Response { response, request_id, .. },
+                            ..
+                        },
+                    )) if heartbeats.is_heartbeat(&request_id) => {
+                        let alive = matches!(response, AxonResponse::Pong { backend_healthy: true });
+                        match heartbeats.record(&request_id, alive) {
+                            Some(Liveness::Lost(peer_id)) => {
+                                leaders.remove(&peer_id);
+                                warn!("💔 Dropping unresponsive Leader: {}", peer_id);
+                            }
+                            Some(Liveness::Recovered(peer_id)) => {
+                                readmit_leader(&mut swarm, &mut leaders, &mut peer_models, peer_id);
+                            }
+                            None => {}
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Response { response, request_id },
+                        },
+                    )) if peer_models.is_query(&request_id) => {
+                        // Leaders that can't advertise are tried for any model
+                        let models = match response {
+                            AxonResponse::Capabilities(capabilities) => {
+                                info!("🎯 Leader {} serves models {:?}", peer, capabilities.models);
+                                Some(capabilities.models)
+                            }
+                            _ => None,
+                        };
+                        peer_models.record(&request_id, models);
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Response { response, request_id, .. },
+                        },
+                    )) => {
+                        // Handle responses to our outbound requests (from HTTP)
+                        if let Some((model, responder)) = pending_requests.remove(&request_id) {
+                            let _ = responder.send(ask_result(response, model, peer.to_string()));
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::OutboundFailure { request_id, error, .. },
+                    )) => {
+                        // A 1.0.0 Leader can't answer pings but did negotiate, so it is alive
+                        let alive = is_downgrade_refusal(&error);
+                        match heartbeats.record(&request_id, alive) {
+                            Some(Liveness::Lost(peer_id)) => {
+                                leaders.remove(&peer_id);
+                                warn!("💔 Dropping unresponsive Leader: {}", peer_id);
+                            }
+                            Some(Liveness::Recovered(peer_id)) => {
+                                readmit_leader(&mut swarm, &mut leaders, &mut peer_models, peer_id);
+                            }
+                            None => {}
+                        }
+                        // Peers that don't answer the capabilities query stay unknown
+                        if let Some(peer_id) = peer_models.record(&request_id, None) {
+                            debug!("No capability response from {}: {:?}", peer_id, error);
+                        }
+                        if let Some((_, responder)) = pending_requests.remove(&request_id) {
+                            let code = match error {
+                                request_response::OutboundFailure::Timeout => ErrorCode::Timeout,
+                                _ => ErrorCode::BackendUnavailable,
+                            };
+                            let _ = responder.send(Err(AskError {
+                                code,
+                                message: format!("Request failed: {:?}", error),
+                            }));
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::Control(
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Request { request, channel, .. },
+                        },
+                    )) => {
+                        spawn_control(&ctx, peer, request, channel, &control_tx);
+                    }
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        ctx.metrics.set_peers_connected(swarm.connected_peers().count());
+                        peer_table.connected(peer_id, endpoint.get_remote_address().clone());
+                    }
+                    SwarmEvent::ConnectionClosed {
+                        peer_id,
+                        num_established: 0,
+                        ..
+                    } => {
+                        ctx.metrics.set_peers_connected(swarm.connected_peers().count());
+                        peer_table.disconnected(&peer_id);
+                        let cancelled = ctx.cancel_requests_from(&peer_id);
+                        if cancelled > 0 {
+                            info!("🚫 {} disconnected, cancelling {} request(s)", peer_id, cancelled);
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                        for (peer_id, _addr) in peers {
+                            heartbeats.forget(&peer_id);
+                            peer_models.forget(&peer_id);
+                            peer_table.expired(&peer_id, swarm.is_connected(&peer_id));
+                            if leaders.remove(&peer_id) {
+                                info!("❌ Peer expired: {}", peer_id);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            permit = ctx.backend_slots.clone().acquire_owned(), if !queue.is_empty() => {
+                if let Ok(permit) = permit {
+                    spawn_next(&ctx, &mut queue, permit, &done_tx);
+                }
+            }
+        }
+    }
+
+    log_shutdown_summary(&ctx, pending_requests.len());
+    Ok(())
+}
+
+/// Run in Subordinate mode (client)
+async fn run_subordinate(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+    request: AxonRequest,
+    options: RequestOptions,
+    json: bool,
+) -> Result<()> {
+    info!("🚀 Starting Subordinate Mode (Client)");
+
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let wanted_model = request.model().map(str::to_string);
+    if let AxonRequest::Inference(InferenceRequest {
+        request_id: Some(id),
+        ..
+    }) = &request
+    {
+        info!("🏷️ Request id: {}", id);
+    }
+    let mut pending_request: Option<OutboundRequestId> = None;
+    // When the pending request was sent, for --json's latency
+    let mut sent_at = Instant::now();
+    let mut leaders = LeaderPool::new();
+
+    // Capability probes in flight, and peers we have already probed
+    let mut probes: HashMap<OutboundRequestId, PeerId> = HashMap::new();
+    let mut probed: HashSet<PeerId> = HashSet::new();
+
+    // Leaders already tried in the current round, so a retry goes elsewhere first
+    let mut tried: HashSet<PeerId> = HashSet::new();
+    let mut attempt: u32 = 0;
+    let mut retry_at: Option<Instant> = None;
+    let discovery_deadline = Instant::now() + Duration::from_secs(options.discovery_timeout_secs);
+
+    let mut heartbeats = Heartbeats::new();
+    let mut heartbeat_timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    info!("🔍 Discovering Leader nodes...");
+
+    // Dial static peers now, and again later if they aren't reachable yet
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    loop {
+        let idle = pending_request.is_none() && retry_at.is_none();
+        let event = tokio::select! {
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+                continue;
+            }
+            event = swarm.select_next_some() => discovery_event(event, &mut static_peers, &mut ping_failures),
+            _ = &mut shutdown => {
+                anyhow::bail!("Interrupted before a Leader responded");
+            }
+            _ = tokio::time::sleep_until(retry_at.unwrap_or_else(Instant::now)), if retry_at.is_some() => {
+                retry_at = None;
+                pending_request = send_to_next_leader(&mut swarm, &mut leaders, &mut tried, &request);
+                sent_at = Instant::now();
+                continue;
+            }
+            _ = heartbeat_timer.tick() => {
+                send_heartbeats(&mut swarm, &leaders, &mut heartbeats);
+                continue;
+            }
+            _ = tokio::time::sleep_until(discovery_deadline), if idle && leaders.is_empty() => {
+                anyhow::bail!(
+                    "No suitable Leader found within {}s",
+                    options.discovery_timeout_secs
+                );
+            }
+        };
+
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("👂 Listening on: {}", address);
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, _addr) in peers {
+                    // Ask each new peer what it serves before trusting it with the request
+                    if probed.insert(peer_id) {
+                        info!("🔍 Discovered peer: {}", peer_id);
+                        let probe_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, AxonRequest::Capabilities);
+                        probes.insert(probe_id, peer_id);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    message:
+                        request_response::Message::Response {
+                            response,
+                            request_id,
+                        },
+                    ..
+                },
+            )) if heartbeats.is_heartbeat(&request_id) => {
+                let alive = matches!(
+                    response,
+                    AxonResponse::Pong {
+                        backend_healthy: true
+                    }
+                );
+                match heartbeats.record(&request_id, alive) {
+                    Some(Liveness::Lost(peer_id)) => {
+                        leaders.remove(&peer_id);
+                        warn!("💔 Dropping unresponsive Leader: {}", peer_id);
+                    }
+                    // Check it still suits the request before trying it again
+                    Some(Liveness::Recovered(peer_id)) => {
+                        info!("💚 Leader {} is responding again", peer_id);
+                        let probe_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, AxonRequest::Capabilities);
+                        probes.insert(probe_id, peer_id);
+                    }
+                    None => {}
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Response {
+                            response,
+                            request_id,
+                        },
+                },
+            )) if probes.contains_key(&request_id) => {
+                probes.remove(&request_id);
+
+                let suitable = match &response {
+                    AxonResponse::Capabilities(capabilities) => {
+                        info!(
+                            "🎯 Found Leader {}: models {:?}, queue depth {}, protocol {}",
+                            peer,
+                            capabilities.models,
+                            capabilities.queue_depth,
+                            capabilities.protocol
+                        );
+                        advertises(&capabilities.models, wanted_model.as_deref())
+                    }
+                    // Older Leaders can't advertise, so try them anyway
+                    _ => {
+                        info!("🎯 Found Leader {} (no capability advertisement)", peer);
+                        true
+                    }
+                };
+
+                if !suitable {
+                    info!("⏭️ Skipping {}: does not serve the requested model", peer);
+                    continue;
+                }
+                if let AxonResponse::Capabilities(CapabilitiesResponse {
+                    max_prompt_chars: Some(limit),
                     ..
+                }) = response
+                    && request.prompt_chars() > limit
+                {
+                    info!(
+                        "⏭️ Skipping {}: prompt is longer than its limit of {} characters",
+                        peer, limit
+                    );
+                    continue;
+                }
+
+                leaders.insert(peer);
+                if pending_request.is_none() && retry_at.is_none() {
+                    pending_request =
+                        send_to_next_leader(&mut swarm, &mut leaders, &mut tried, &request);
+                    sent_at = Instant::now();
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Response {
+                            response,
+                            request_id,
+                        },
+                },
+            )) => {
+                let answered =
+                    take_answer(pending_request, request_id, peer, response, |response| {
+                        if json {
+                            print_json_response(response, peer, sent_at.elapsed())
+                        } else {
+                            print_response(response)
+                        }
+                    })?;
+                if answered {
+                    return Ok(());
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                // A 1.0.0 Leader can't answer pings but did negotiate, so it is alive
+                let downgraded = is_downgrade_refusal(&error);
+                match heartbeats.record(&request_id, downgraded) {
+                    Some(Liveness::Lost(peer_id)) => {
+                        leaders.remove(&peer_id);
+                        warn!("💔 Dropping unresponsive Leader: {}", peer_id);
+                        continue;
+                    }
+                    Some(Liveness::Recovered(peer_id)) => {
+                        info!("💚 Leader {} is responding again", peer_id);
+                        let probe_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, AxonRequest::Capabilities);
+                        probes.insert(probe_id, peer_id);
+                        continue;
+                    }
+                    None => {}
+                }
+                if let Some(peer_id) = probes.remove(&request_id) {
+                    // Leaders that can't advertise their models are tried anyway
+                    if probe_inconclusive(&error) {
+                        if downgraded {
+                            info!(
+                                "🎯 Found Leader {} (protocol {})",
+                                peer_id, LEGACY_PROTOCOL_NAME
+                            );
+                        } else {
+                            info!("🎯 Found Leader {} (capabilities unknown)", peer_id);
+                        }
+                        leaders.insert(peer_id);
+                        if pending_request.is_none() && retry_at.is_none() {
+                            pending_request =
+                                send_to_next_leader(&mut swarm, &mut leaders, &mut tried, &request);
+                            sent_at = Instant::now();
+                        }
+                    } else {
+                        // Otherwise the peer is not a Leader (or is unreachable)
+                        info!("⏭️ Skipping {}: no capability response", peer_id);
+                    }
+                    continue;
+                }
+                if pending_request != Some(request_id) {
+                    continue;
+                }
+                pending_request = None;
+                warn!("❌ Request failed: {:?}", error);
+
+                if attempt >= options.retries {
+                    let code = match error {
+                        request_response::OutboundFailure::Timeout => ErrorCode::Timeout,
+                        _ => ErrorCode::BackendUnavailable,
+                    };
+                    error!("❌ Giving up after {} attempt(s)", attempt + 1);
+                    return Err(RequestError {
+                        code: Some(code),
+                        message: format!("Request failed: {:?}", error),
+                    }
+                    .into());
+                }
+
+                attempt += 1;
+                let backoff = 1u64 << (attempt - 1).min(16);
+                let delay = Duration::from_millis(options.retry_delay_ms.saturating_mul(backoff));
+                info!(
+                    "🔁 Retrying in {:?} (attempt {}/{})",
+                    delay, attempt, options.retries
+                );
+                retry_at = Some(Instant::now() + delay);
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer_id, _addr) in peers {
+                    probed.remove(&peer_id);
+                    heartbeats.forget(&peer_id);
+                    if leaders.remove(&peer_id) {
+                        warn!("❌ Leader disconnected: {}", peer_id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Print `response` with `print` if it answers the `pending` request
+///
+/// Returns whether it did, which finishes the Subordinate. Any other
+/// response, e.g. the answer to an attempt that already timed out, is
+/// ignored.
+fn take_answer(
+    pending: Option<OutboundRequestId>,
+    request_id: OutboundRequestId,
+    peer: PeerId,
+    response: AxonResponse,
+    print: impl FnOnce(AxonResponse) -> Result<()>,
+) -> Result<bool> {
+    if pending != Some(request_id) {
+        debug!("Ignoring stray response {} from {}", request_id, peer);
+        return Ok(false);
+    }
+    print(response)?;
+    Ok(true)
+}
+
+/// Whether a request failed only because the peer negotiated protocol 1.0.0,
+/// which cannot express it
+fn is_downgrade_refusal(error: &request_response::OutboundFailure) -> bool {
+    matches!(error, request_response::OutboundFailure::Io(e) if e.kind() == std::io::ErrorKind::Unsupported)
+}
+
+/// Whether a Leader advertising `models` can take a request for `wanted_model`
+fn advertises(models: &[String], wanted_model: Option<&str>) -> bool {
+    wanted_model.is_none_or(|model| models.iter().any(|m| ollama::same_model(m, model)))
+}
+
+/// Whether a failed capabilities query leaves the peer unknown rather than ruled out
+///
+/// A 1.0.0 Leader can't advertise and a busy one may answer too late; both
+/// are tried anyway. Other failures mean the peer isn't a reachable Leader.
+fn probe_inconclusive(error: &request_response::OutboundFailure) -> bool {
+    is_downgrade_refusal(error) || matches!(error, request_response::OutboundFailure::Timeout)
+}
+
+/// Report connections to static peers as mDNS discoveries, peers that
+/// stopped answering pings as mDNS expiries, and ones that answer again as
+/// fresh discoveries
+///
+/// This lets every mode track peers through its usual discovery handling.
+/// Failed dials to static peers are logged and retried later.
+fn discovery_event(
+    event: SwarmEvent<AxonBehaviourEvent>,
+    static_peers: &mut StaticPeers,
+    ping_failures: &mut PingFailures,
+) -> SwarmEvent<AxonBehaviourEvent> {
+    match &event {
+        SwarmEvent::Behaviour(AxonBehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+            // Older nodes without ping report Unsupported, which says nothing about liveness
+            let ok = !matches!(
+                result,
+                Err(ping::Failure::Timeout | ping::Failure::Other { .. })
+            );
+            match ping_failures.record(*peer, ok) {
+                Some(Liveness::Lost(peer_id)) => {
+                    debug!("💀 Evicting {}: stopped answering pings", peer_id);
+                    let expired = vec![(peer_id, Multiaddr::empty())];
+                    return SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(
+                        expired,
+                    )));
+                }
+                Some(Liveness::Recovered(peer_id)) => {
+                    info!("💚 {} is answering pings again", peer_id);
+                    let discovered = vec![(peer_id, Multiaddr::empty())];
+                    return SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(
+                        mdns::Event::Discovered(discovered),
+                    ));
+                }
+                None => {}
+            }
+        }
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint,
+            num_established,
+            ..
+        } => {
+            let addr = endpoint.get_remote_address();
+            if num_established.get() == 1 {
+                info!("🤝 Connected to {} at {}", peer_id, addr);
+            } else {
+                debug!(
+                    "Another connection to {} at {} ({} open)",
+                    peer_id, addr, num_established
+                );
+            }
+            if let Some(addr) = static_peers.established(*connection_id, *peer_id) {
+                info!("🔗 Connected to static peer {} at {}", peer_id, addr);
+                let discovered = vec![(*peer_id, addr.clone())];
+                return SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(
+                    discovered,
+                )));
+            }
+        }
+        SwarmEvent::OutgoingConnectionError {
+            connection_id,
+            error,
+            ..
+        } => {
+            if let Some((addr, delay)) = static_peers.failed(*connection_id) {
+                warn!(
+                    "⚠️ Could not reach static peer {} (retrying in {:?}): {}",
+                    addr, delay, error
+                );
+            }
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established,
+            cause,
+            ..
+        } if *num_established > 0 => {
+            debug!(
+                "A connection to {} closed ({} still open): {:?}",
+                peer_id, num_established, cause
+            );
+        }
+        SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+            ping_failures.forget(peer_id);
+            match cause {
+                Some(cause) => info!("👋 Disconnected from {}: {}", peer_id, cause),
+                None => info!("👋 Disconnected from {}", peer_id),
+            }
+            if let Some((addr, delay)) = static_peers.closed(peer_id) {
+                warn!(
+                    "🔌 Lost connection to static peer {} at {} (redialling in {:?})",
+                    peer_id, addr, delay
+                );
+            }
+        }
+        _ => {}
+    }
+    event
+}
+
+/// Ping every known Leader so dead ones can be dropped, and every dropped
+/// one so it can rejoin once it answers
+fn send_heartbeats(
+    swarm: &mut Swarm<AxonBehaviour>,
+    leaders: &LeaderPool,
+    heartbeats: &mut Heartbeats,
+) {
+    let peers: Vec<PeerId> = leaders
+        .iter()
+        .chain(heartbeats.dropped())
+        .copied()
+        .collect();
+    for peer_id in peers {
+        let request_id = swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&peer_id, AxonRequest::Ping);
+        heartbeats.sent(request_id, peer_id);
+    }
+}
+
+/// Put a Leader back in the pool after it starts answering again, asking
+/// afresh which models it serves since it may have restarted with others
+fn readmit_leader(
+    swarm: &mut Swarm<AxonBehaviour>,
+    leaders: &mut LeaderPool,
+    peer_models: &mut PeerModels,
+    peer_id: PeerId,
+) {
+    if !leaders.insert(peer_id) {
+        return;
+    }
+    info!("💚 Leader {} is responding again", peer_id);
+    peer_models.forget(&peer_id);
+    let query_id = swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(&peer_id, AxonRequest::Capabilities);
+    peer_models.sent(query_id, peer_id);
+}
+
+/// Send `request` to the next Leader not yet tried in this round
+///
+/// Once every known Leader has been tried the round starts over. Returns
+/// `None` if no Leader is currently known.
+fn send_to_next_leader(
+    swarm: &mut Swarm<AxonBehaviour>,
+    leaders: &mut LeaderPool,
+    tried: &mut HashSet<PeerId>,
+    request: &AxonRequest,
+) -> Option<OutboundRequestId> {
+    let peer_id = match leaders
+        .next_where(|p| !tried.contains(p) && swarm.is_connected(p))
+        .or_else(|| leaders.next_where(|p| !tried.contains(p)))
+    {
+        Some(peer_id) => peer_id,
+        None => {
+            tried.clear();
+            leaders.next(|p| swarm.is_connected(p))?
+        }
+    };
+    tried.insert(peer_id);
+
+    info!("📤 Sending request to Leader {}...", peer_id);
+    let req_id = swarm
+        .behaviour_mut()
+        .request_response
+        .send_request(&peer_id, request.clone());
+    Some(req_id)
+}
+
+/// Outcome of `ask --json`, printed as the only line on stdout
+#[derive(Debug, serde::Serialize)]
+struct AskOutput {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<String>,
+    /// Leader that answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    /// Time from sending the request to the Leader until its answer arrived
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+}
+
+/// Print a Leader's answer from `peer` for `ask --json`, returning a
+/// [`RequestError`] if it failed
+///
+/// Failures are left for the caller to print, so every failure of the
+/// command is reported the same way.
+fn print_json_response(response: AxonResponse, peer: PeerId, latency: Duration) -> Result<()> {
+    match response {
+        AxonResponse::Inference(response) if response.success => {
+            let output = AskOutput {
+                success: true,
+                response: Some(response.response),
+                peer: Some(peer.to_string()),
+                latency_ms: Some(latency.as_millis() as u64),
+                error: None,
+                code: None,
+            };
+            println!("{}", serde_json::to_string(&output)?);
+            Ok(())
+        }
+        AxonResponse::Inference(response) => Err(RequestError {
+            code: response.error_code,
+            message: response.error.unwrap_or_default(),
+        }
+        .into()),
+        other => print_response(other),
+    }
+}
+
+/// Print a Leader's response for the user, returning a [`RequestError`] if it failed
+fn print_response(response: AxonResponse) -> Result<()> {
+    match response {
+        AxonResponse::Inference(response) if response.success => {
+            println!("\n✅ Response from Leader:\n");
+            println!("{}", response.response);
+            if let Some(session_id) = response.session_id {
+                println!("\n🧵 Session: {}", session_id);
+            }
+            if let Some(stats) = response.stats {
+                println!(
+                    "\n📊 {} prompt tokens, {} completion tokens, {:.2}s total, {:.1} tokens/s",
+                    stats.prompt_tokens,
+                    stats.completion_tokens,
+                    stats.total_duration_ms as f64 / 1000.0,
+                    stats.tokens_per_second
+                );
+                if stats.retries > 0 {
+                    println!("🔁 Leader retried Ollama {} time(s)", stats.retries);
+                }
+            }
+        }
+        AxonResponse::Embedding(response) if response.success => {
+            println!("{}", serde_json::to_string(&response.embeddings)?);
+        }
+        AxonResponse::CountTokens(response) if response.success => {
+            let approx = if response.estimated { "~" } else { "" };
+            match response.context_length {
+                Some(context_length) => println!(
+                    "🔢 {}{} tokens of a {} token context window",
+                    approx, response.tokens, context_length
+                ),
+                None => println!("🔢 {}{} tokens", approx, response.tokens),
+            }
+        }
+        AxonResponse::Batch(response) if response.success => {
+            // One JSON line per prompt, in batch file order
+            for result in response.results {
+                println!("{}", serde_json::to_string(&result)?);
+            }
+        }
+        AxonResponse::Chat(ChatResponse {
+            message: Some(message),
+            success: true,
+            ..
+        }) => {
+            println!("\n🤖 {}\n", message.content);
+        }
+        AxonResponse::Inference(InferenceResponse {
+            error, error_code, ..
+        })
+        | AxonResponse::Embedding(EmbeddingResponse {
+            error, error_code, ..
+        })
+        | AxonResponse::Chat(ChatResponse {
+            error, error_code, ..
+        })
+        | AxonResponse::Batch(BatchResponse {
+            error, error_code, ..
+        })
+        | AxonResponse::CountTokens(CountTokensResponse {
+            error, error_code, ..
+        }) => {
+            let error = RequestError {
+                code: error_code,
+                message: error.unwrap_or_default(),
+            };
+            eprintln!("\n❌ Error from Leader: {}", error);
+            return Err(error.into());
+        }
+        AxonResponse::Capabilities(capabilities) => {
+            println!("{}", serde_json::to_string(&capabilities)?);
+        }
+        AxonResponse::Pong { backend_healthy } => {
+            println!("🏓 Pong (backend healthy: {})", backend_healthy);
+        }
+        AxonResponse::Unsupported { protocol } => {
+            let error = RequestError {
+                code: None,
+                message: format!(
+                    "Leader speaks {} and does not support this request type; upgrade the Leader",
+                    protocol
+                ),
+            };
+            eprintln!("\n❌ {}", error);
+            return Err(error.into());
+        }
+    }
+    Ok(())
+}
+
+/// One `ask-batch` result, printed as a JSON line
+#[derive(Debug, serde::Serialize)]
+struct IndexedResult {
+    /// Position of the prompt in the batch file
+    index: usize,
+    #[serde(flatten)]
+    result: InferenceResponse,
+}
+
+/// Send each prompt of a batch file to the discovered Leaders, `concurrency` at
+/// a time, printing the results in file order as they become available
+async fn run_ask_batch(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+    args: AskBatchArgs,
+) -> Result<()> {
+    let prompts = load_indexed_prompts(&args.file)?;
+    info!(
+        "📚 Running {} prompt(s) from {} at concurrency {}",
+        prompts.len(),
+        args.file.display(),
+        args.concurrency
+    );
+
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let wanted_model = args.model.clone();
+    let concurrency = args.concurrency.max(1);
+
+    let mut leaders = LeaderPool::new();
+    let mut probes: HashMap<OutboundRequestId, PeerId> = HashMap::new();
+    let mut probed: HashSet<PeerId> = HashSet::new();
+
+    // Position in `prompts` of each request in flight
+    let mut in_flight: HashMap<OutboundRequestId, usize> = HashMap::new();
+    let mut sent = 0;
+    // Finished results not yet printed, by position; printed in order from `printed`
+    let mut finished: BTreeMap<usize, InferenceResponse> = BTreeMap::new();
+    let mut printed = 0;
+    let mut succeeded = 0;
+
+    let discovery_deadline = Instant::now() + Duration::from_secs(args.discovery_timeout_secs);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    info!("🔍 Discovering Leader nodes...");
+
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    loop {
+        while sent < prompts.len() && in_flight.len() < concurrency {
+            let Some(peer_id) = leaders.next(|p| swarm.is_connected(p)) else {
+                break;
+            };
+            let request = AxonRequest::Inference(InferenceRequest {
+                prompt: prompts[sent].1.clone(),
+                model: wanted_model.clone(),
+                images: Vec::new(),
+                messages: None,
+                priority: None,
+                session_id: None,
+                options: None,
+                format: PromptFormat::default(),
+                request_id: Some(new_request_id()),
+                timeout_secs: None,
+            });
+            let request_id = swarm
+                .behaviour_mut()
+                .request_response
+                .send_request(&peer_id, request);
+            in_flight.insert(request_id, sent);
+            sent += 1;
+        }
+
+        while let Some(result) = finished.remove(&printed) {
+            succeeded += usize::from(result.success);
+            let line = IndexedResult {
+                index: prompts[printed].0,
+                result,
+            };
+            println!("{}", serde_json::to_string(&line)?);
+            printed += 1;
+        }
+        if printed == prompts.len() {
+            info!("✅ {} of {} prompt(s) succeeded", succeeded, prompts.len());
+            return Ok(());
+        }
+        if sent > 0 && leaders.is_empty() && in_flight.is_empty() {
+            anyhow::bail!(
+                "Every Leader disappeared after {} of {} prompt(s)",
+                printed,
+                prompts.len()
+            );
+        }
+
+        let event = tokio::select! {
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+                continue;
+            }
+            event = swarm.select_next_some() => discovery_event(event, &mut static_peers, &mut ping_failures),
+            _ = &mut shutdown => {
+                anyhow::bail!("Interrupted after {} of {} prompt(s)", printed, prompts.len());
+            }
+            _ = tokio::time::sleep_until(discovery_deadline), if leaders.is_empty() && sent == 0 => {
+                anyhow::bail!("No suitable Leader found within {}s", args.discovery_timeout_secs);
+            }
+        };
+
+        let found_leader = match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("👂 Listening on: {}", address);
+                None
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, _addr) in peers {
+                    if probed.insert(peer_id) {
+                        let probe_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, AxonRequest::Capabilities);
+                        probes.insert(probe_id, peer_id);
+                    }
+                }
+                None
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Response {
+                            response,
+                            request_id,
+                        },
+                },
+            )) => {
+                if let Some(position) = in_flight.remove(&request_id) {
+                    let result = match response {
+                        AxonResponse::Inference(result) => result,
+                        other => InferenceResponse {
+                            response: String::new(),
+                            success: false,
+                            error: Some(format!("Unexpected response from {}: {:?}", peer, other)),
+                            error_code: Some(ErrorCode::Internal),
+                            stats: None,
+                            session_id: None,
+                            request_id: None,
+                        },
+                    };
+                    finished.insert(position, result);
+                    None
+                } else if probes.remove(&request_id).is_some() {
+                    match response {
+                        AxonResponse::Capabilities(capabilities) => {
+                            advertises(&capabilities.models, wanted_model.as_deref())
+                                .then_some(peer)
+                        }
+                        // Older Leaders can't advertise, so try them anyway
+                        _ => Some(peer),
+                    }
+                } else {
+                    None
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                },
+            )) => {
+                if let Some(position) = in_flight.remove(&request_id) {
+                    warn!("❌ Request to {} failed: {:?}", peer, error);
+                    let code = match error {
+                        request_response::OutboundFailure::Timeout => ErrorCode::Timeout,
+                        _ => ErrorCode::BackendUnavailable,
+                    };
+                    finished.insert(
+                        position,
+                        InferenceResponse {
+                            response: String::new(),
+                            success: false,
+                            error: Some(format!("Request failed: {:?}", error)),
+                            error_code: Some(code),
+                            stats: None,
+                            session_id: None,
+                            request_id: None,
+                        },
+                    );
+                    None
+                } else if probes.remove(&request_id).is_some() && probe_inconclusive(&error) {
+                    Some(peer)
+                } else {
+                    None
+                }
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer_id, _addr) in peers {
+                    probed.remove(&peer_id);
+                    if leaders.remove(&peer_id) {
+                        warn!("❌ Leader disconnected: {}", peer_id);
+                    }
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(peer_id) = found_leader
+            && leaders.insert(peer_id)
+        {
+            info!("🎯 Found Leader {}", peer_id);
+        }
+    }
+}
+
+/// Send `requests` inference calls across the discovered Leaders and report latency
+async fn run_bench(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+    args: BenchArgs,
+) -> Result<()> {
+    info!(
+        "🚀 Starting benchmark: {} request(s) at concurrency {}",
+        args.requests, args.concurrency
+    );
+
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let request = AxonRequest::Inference(InferenceRequest {
+        prompt: args.prompt,
+        model: args.model,
+        images: Vec::new(),
+        messages: None,
+        priority: None,
+        session_id: None,
+        options: None,
+        format: PromptFormat::default(),
+        request_id: None,
+        timeout_secs: None,
+    });
+    let wanted_model = request.model().map(str::to_string);
+    let concurrency = args.concurrency.max(1);
+
+    let mut leaders = LeaderPool::new();
+    let mut probes: HashMap<OutboundRequestId, PeerId> = HashMap::new();
+    let mut probed: HashSet<PeerId> = HashSet::new();
+
+    let mut in_flight: HashMap<OutboundRequestId, (PeerId, Instant)> = HashMap::new();
+    let mut samples: Vec<Sample> = Vec::with_capacity(args.requests);
+    let mut sent = 0;
+
+    // Firing starts once the first Leader has had company for the warmup period
+    let discovery_deadline = Instant::now() + Duration::from_secs(args.discovery_timeout_secs);
+    let mut start_at: Option<Instant> = None;
+    let mut started: Option<Instant> = None;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    info!("🔍 Discovering Leader nodes...");
+
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    loop {
+        if let Some(started) = started {
+            while sent < args.requests && in_flight.len() < concurrency {
+                let Some(peer_id) = leaders.next(|p| swarm.is_connected(p)) else {
+                    break;
+                };
+                let request_id = swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_request(&peer_id, request.clone());
+                in_flight.insert(request_id, (peer_id, Instant::now()));
+                sent += 1;
+            }
+
+            if samples.len() == args.requests {
+                let report = BenchReport::new(&samples, concurrency, started.elapsed());
+                if args.json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    print!("{}", report);
+                }
+                return Ok(());
+            }
+            if leaders.is_empty() && in_flight.is_empty() {
+                anyhow::bail!(
+                    "Every Leader disappeared after {} of {} request(s)",
+                    samples.len(),
+                    args.requests
+                );
+            }
+        }
+
+        let event = tokio::select! {
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+                continue;
+            }
+            event = swarm.select_next_some() => discovery_event(event, &mut static_peers, &mut ping_failures),
+            _ = &mut shutdown => {
+                anyhow::bail!("Interrupted after {} of {} request(s)", samples.len(), args.requests);
+            }
+            _ = tokio::time::sleep_until(discovery_deadline), if start_at.is_none() => {
+                anyhow::bail!("No suitable Leader found within {}s", args.discovery_timeout_secs);
+            }
+            _ = tokio::time::sleep_until(start_at.unwrap_or_else(Instant::now)), if start_at.is_some() && started.is_none() => {
+                info!("🏁 Benchmarking {} Leader(s)", leaders.len());
+                started = Some(Instant::now());
+                continue;
+            }
+        };
+
+        let found_leader = match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("👂 Listening on: {}", address);
+                None
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, _addr) in peers {
+                    if probed.insert(peer_id) {
+                        let probe_id = swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_request(&peer_id, AxonRequest::Capabilities);
+                        probes.insert(probe_id, peer_id);
+                    }
+                }
+                None
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Response {
+                            response,
+                            request_id,
+                        },
                 },
             )) => {
-                if response.success {
-                    println!("\n✅ Response from Leader:\n");
-                    println!("{}", response.response);
+                if let Some((leader, sent_at)) = in_flight.remove(&request_id) {
+                    let success = matches!(&response, AxonResponse::Inference(r) if r.success);
+                    samples.push(Sample {
+                        leader,
+                        latency: sent_at.elapsed(),
+                        success,
+                    });
+                    None
+                } else if probes.remove(&request_id).is_some() {
+                    match response {
+                        AxonResponse::Capabilities(capabilities) => {
+                            advertises(&capabilities.models, wanted_model.as_deref())
+                                .then_some(peer)
+                        }
+                        // Older Leaders can't advertise, so try them anyway
+                        _ => Some(peer),
+                    }
                 } else {
-                    eprintln!(
-                        "\n❌ Error from Leader: {}",
-                        response.error.unwrap_or_default()
-                    );
+                    None
                 }
-                return Ok(());
             }
             SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
-                request_response::Event::OutboundFailure { error, .. },
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                },
             )) => {
-                eprintln!("❌ Request failed: {:?}", error);
-                return Err(anyhow::anyhow!("Request failed: {:?}", error));
+                if let Some((leader, sent_at)) = in_flight.remove(&request_id) {
+                    debug!("❌ Request to {} failed: {:?}", leader, error);
+                    samples.push(Sample {
+                        leader,
+                        latency: sent_at.elapsed(),
+                        success: false,
+                    });
+                    None
+                } else if probes.remove(&request_id).is_some() && probe_inconclusive(&error) {
+                    Some(peer)
+                } else {
+                    None
+                }
             }
             SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
                 for (peer_id, _addr) in peers {
-                    println!("❌ Leader disconnected: {}", peer_id);
-                    discovered_leaders.remove(&peer_id);
+                    if leaders.remove(&peer_id) {
+                        warn!("❌ Leader disconnected: {}", peer_id);
+                    }
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(peer_id) = found_leader
+            && leaders.insert(peer_id)
+        {
+            info!("🎯 Found Leader {}", peer_id);
+            start_at.get_or_insert_with(|| Instant::now() + Duration::from_secs(args.warmup_secs));
+        }
+    }
+}
+
+/// Print the addresses other nodes can give `--peer` to reach this one
+async fn run_peer_info(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+) -> Result<()> {
+    let local_peer_id = PeerId::from(local_key.public());
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+
+    let mut addresses = Vec::new();
+    let deadline = tokio::time::sleep(LISTEN_ADDR_WAIT);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            event = swarm.select_next_some() => {
+                if let SwarmEvent::NewListenAddr { address, .. } = event {
+                    addresses.push(address);
                 }
             }
+        }
+    }
+
+    if addresses.is_empty() {
+        anyhow::bail!("No listen address came up within {:?}", LISTEN_ADDR_WAIT);
+    }
+    for address in addresses {
+        let address = address.with_p2p(local_peer_id).unwrap_or_else(|a| a);
+        println!("{}", address);
+    }
+    Ok(())
+}
+
+/// Send one administration command to the Leader `peer` and print its answer as JSON
+async fn run_admin(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+    peer: PeerId,
+    request: ControlRequest,
+    discovery_timeout_secs: u64,
+) -> Result<()> {
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let discovery_deadline = Instant::now() + Duration::from_secs(discovery_timeout_secs);
+    let mut pending_request: Option<OutboundRequestId> = None;
+
+    info!("🔍 Looking for Leader {}...", peer);
+
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    loop {
+        let event = tokio::select! {
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+                continue;
+            }
+            event = swarm.select_next_some() => discovery_event(event, &mut static_peers, &mut ping_failures),
+            _ = tokio::time::sleep_until(discovery_deadline), if pending_request.is_none() => {
+                anyhow::bail!("Leader {} not found within {}s", peer, discovery_timeout_secs);
+            }
+        };
+
+        match event {
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers)))
+                if pending_request.is_none()
+                    && peers.iter().any(|(peer_id, _)| *peer_id == peer) =>
+            {
+                info!("📤 Sending {:?} to Leader {}", request, peer);
+                pending_request = Some(
+                    swarm
+                        .behaviour_mut()
+                        .control
+                        .send_request(&peer, request.clone()),
+                );
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Control(
+                request_response::Event::Message {
+                    message: request_response::Message::Response { response, .. },
+                    ..
+                },
+            )) => {
+                println!("{}", serde_json::to_string(&response)?);
+                return match response {
+                    ControlResponse::Rejected { code, message } => Err(RequestError {
+                        code: Some(code),
+                        message,
+                    }
+                    .into()),
+                    ControlResponse::Unsupported { protocol } => Err(RequestError {
+                        code: None,
+                        message: format!("Leader ({}) does not support this command", protocol),
+                    }
+                    .into()),
+                    ControlResponse::Stats(_) | ControlResponse::Draining { .. } => Ok(()),
+                };
+            }
+            SwarmEvent::Behaviour(AxonBehaviourEvent::Control(
+                request_response::Event::OutboundFailure { error, .. },
+            )) => {
+                anyhow::bail!("Admin command failed: {:?}", error);
+            }
             _ => {}
         }
     }
 }
+
+/// Run an interactive chat session, accumulating turns across requests
+async fn run_chat(
+    psk_bytes: [u8; 32],
+    local_key: identity::Keypair,
+    network: NetworkArgs,
+    model: Option<String>,
+    system: Option<String>,
+) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    info!("🚀 Starting Subordinate Chat Mode (Client)");
+
+    let mut swarm = create_swarm(psk_bytes, local_key, &network)?;
+    listen(&mut swarm, &network.listen_addrs())?;
+    let mut ping_failures = PingFailures::new(network.max_ping_failures);
+    let mut static_peers = StaticPeers::new(network.peers);
+
+    let mut leaders = LeaderPool::new();
+    let mut pending_request: Option<OutboundRequestId> = None;
+    let mut messages: Vec<ChatMessage> = system
+        .into_iter()
+        .map(|content| ChatMessage {
+            role: ChatRole::System,
+            content,
+        })
+        .collect();
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    info!("🔍 Discovering Leader nodes...");
+    println!("💬 Type a message and press Enter (Ctrl-D to exit)");
+
+    let mut redial_timer = tokio::time::interval(STATIC_REDIAL_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if pending_request.is_none() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                let line = line.trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+
+                let Some(peer_id) = leaders.next(|p| swarm.is_connected(p)) else {
+                    eprintln!("❌ No Leader discovered yet, try again shortly");
+                    continue;
+                };
+
+                messages.push(ChatMessage {
+                    role: ChatRole::User,
+                    content: line,
+                });
+                let request = AxonRequest::Chat(ChatRequest {
+                    messages: messages.clone(),
+                    model: model.clone(),
+                    options: None,
+                });
+                pending_request = Some(
+                    swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_request(&peer_id, request),
+                );
+            }
+
+            _ = redial_timer.tick(), if !static_peers.is_empty() => {
+                static_peers.dial(&mut swarm);
+            }
+
+            event = swarm.select_next_some() => {
+                match discovery_event(event, &mut static_peers, &mut ping_failures) {
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                        for (peer_id, _addr) in peers {
+                            if leaders.insert(peer_id) {
+                                info!("🎯 Found Leader: {}", peer_id);
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Response { response, request_id },
+                            ..
+                        },
+                    )) if pending_request == Some(request_id) => {
+                        pending_request = None;
+                        match response {
+                            AxonResponse::Chat(ChatResponse {
+                                message: Some(message),
+                                success: true,
+                                ..
+                            }) => {
+                                println!("\n🤖 {}\n", message.content);
+                                messages.push(message);
+                            }
+                            other => {
+                                // Drop the unanswered turn so it can be retried
+                                messages.pop();
+                                // Failures are printed here; the session carries on
+                                print_response(other).ok();
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::RequestResponse(
+                        request_response::Event::OutboundFailure { request_id, error, .. },
+                    )) if pending_request == Some(request_id) => {
+                        pending_request = None;
+                        messages.pop();
+                        warn!("❌ Request failed: {:?}", error);
+                    }
+                    SwarmEvent::Behaviour(AxonBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                        for (peer_id, _addr) in peers {
+                            if leaders.remove(&peer_id) {
+                                warn!("❌ Leader disconnected: {}", peer_id);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::MockBackend;
+    use clap::Parser;
+
+    /// An empty directory under the system temp dir, unique to this test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "axon-{}-{}-{:x}",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Settings of a Leader started with `flags`
+    fn leader_args(flags: &[&str]) -> LeaderArgs {
+        let args =
+            cli::Args::try_parse_from(["axon_cluster", "serve"].iter().chain(flags)).unwrap();
+        match args.mode {
+            Mode::Serve { leader } => leader,
+            _ => unreachable!(),
+        }
+    }
+
+    /// A Leader serving `llama3` from `backend`
+    fn leader(backend: &Arc<MockBackend>) -> LeaderContext {
+        LeaderContext::new(&leader_args(&["--model", "llama3"]), backend.clone()).unwrap()
+    }
+
+    fn ask(prompt: &str) -> AxonRequest {
+        AxonRequest::Inference(InferenceRequest {
+            prompt: prompt.to_string(),
+            model: None,
+            images: Vec::new(),
+            messages: None,
+            priority: None,
+            session_id: None,
+            options: None,
+            format: PromptFormat::default(),
+            request_id: Some("req-1".to_string()),
+            timeout_secs: None,
+        })
+    }
+
+    fn answer(response: AxonResponse) -> InferenceResponse {
+        match response {
+            AxonResponse::Inference(response) => response,
+            other => panic!("expected an inference response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn leader_answers_with_the_backend_reply() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there"));
+        let ctx = leader(&backend);
+
+        let response = answer(
+            dispatch_request(
+                &ctx,
+                PeerId::random(),
+                ask("Hello"),
+                &CancellationToken::new(),
+            )
+            .await,
+        );
+
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.response, "Hi there");
+        assert_eq!(response.request_id.as_deref(), Some("req-1"));
+        assert_eq!(backend.prompts(), ["Hello"]);
+    }
+
+    #[tokio::test]
+    async fn backend_failure_reaches_the_requester() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there"));
+        backend.fail_next(ErrorCode::BackendUnavailable, "connection refused");
+        let ctx = leader(&backend);
+
+        let failed = answer(
+            dispatch_request(
+                &ctx,
+                PeerId::random(),
+                ask("Hello"),
+                &CancellationToken::new(),
+            )
+            .await,
+        );
+        let retried = answer(
+            dispatch_request(
+                &ctx,
+                PeerId::random(),
+                ask("Hello"),
+                &CancellationToken::new(),
+            )
+            .await,
+        );
+
+        assert!(!failed.success);
+        assert_eq!(failed.error_code, Some(ErrorCode::BackendUnavailable));
+        assert!(retried.success);
+    }
+
+    #[tokio::test]
+    async fn cancelled_generation_stops_promptly() {
+        let backend = Arc::new(
+            MockBackend::new(&["llama3"], "Hi there").with_latency(Duration::from_secs(60)),
+        );
+        let ctx = leader(&backend);
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        let started = Instant::now();
+
+        let response =
+            answer(dispatch_request(&ctx, PeerId::random(), ask("Hello"), &cancel).await);
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(ErrorCode::Cancelled));
+        assert_eq!(backend.prompts(), ["Hello"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_model_is_refused_before_generating() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there"));
+        let ctx = leader(&backend);
+        let AxonRequest::Inference(mut request) = ask("Hello") else {
+            unreachable!()
+        };
+        request.model = Some("mistral".to_string());
+
+        let response = answer(
+            dispatch_request(
+                &ctx,
+                PeerId::random(),
+                AxonRequest::Inference(request),
+                &CancellationToken::new(),
+            )
+            .await,
+        );
+
+        assert_eq!(response.error_code, Some(ErrorCode::ModelNotFound));
+        assert!(backend.prompts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prompt_over_the_context_window_is_too_long() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there").with_context_length(8));
+        let ctx = leader(&backend);
+
+        let response = answer(
+            dispatch_request(
+                &ctx,
+                PeerId::random(),
+                ask(&"word ".repeat(20)),
+                &CancellationToken::new(),
+            )
+            .await,
+        );
+
+        assert_eq!(response.error_code, Some(ErrorCode::PromptTooLong));
+        assert!(backend.prompts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_history_counts_towards_the_context_window() {
+        let backend =
+            Arc::new(MockBackend::new(&["llama3"], "Hi, how can I help?").with_context_length(8));
+        let ctx = leader(&backend);
+        let peer = PeerId::random();
+        let turn = |prompt: &str| {
+            let AxonRequest::Inference(mut request) = ask(prompt) else {
+                unreachable!()
+            };
+            request.session_id = Some("chat".to_string());
+            AxonRequest::Inference(request)
+        };
+
+        let first = answer(
+            dispatch_request(&ctx, peer, turn("one two three"), &CancellationToken::new()).await,
+        );
+        let second = answer(
+            dispatch_request(&ctx, peer, turn("four five six"), &CancellationToken::new()).await,
+        );
+
+        assert!(first.success, "{:?}", first.error);
+        assert_eq!(second.error_code, Some(ErrorCode::PromptTooLong));
+    }
+
+    #[tokio::test]
+    async fn embedding_request_returns_one_vector_per_input() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "unused"));
+        let ctx = leader(&backend);
+        let request = AxonRequest::Embedding(EmbeddingRequest {
+            input: vec!["one".to_string(), "three".to_string()],
+            model: Some("llama3".to_string()),
+        });
+
+        let response =
+            handle_request(&ctx, PeerId::random(), request, &CancellationToken::new()).await;
+
+        let AxonResponse::Embedding(response) = response else {
+            panic!("expected an embedding response, got {:?}", response);
+        };
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.embeddings, [vec![3.0], vec![5.0]]);
+        assert_eq!(backend.prompts(), ["one", "three"]);
+    }
+
+    #[tokio::test]
+    async fn chat_and_batch_prompts_over_the_context_window_are_too_long() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there").with_context_length(8));
+        let ctx = leader(&backend);
+        let message = |content: &str| ChatMessage {
+            role: ChatRole::User,
+            content: content.to_string(),
+        };
+        let chat = AxonRequest::Chat(ChatRequest {
+            messages: vec![
+                message("one two three four five"),
+                message("six seven eight nine"),
+            ],
+            model: None,
+            options: None,
+        });
+        let batch = AxonRequest::Batch(BatchRequest {
+            prompts: vec!["short".to_string(), "word ".repeat(20)],
+            model: None,
+            priority: None,
+            options: None,
+        });
+
+        let chat = dispatch_request(&ctx, PeerId::random(), chat, &CancellationToken::new()).await;
+        let AxonResponse::Batch(batch) =
+            dispatch_request(&ctx, PeerId::random(), batch, &CancellationToken::new()).await
+        else {
+            panic!("expected a batch response");
+        };
+
+        assert_eq!(chat.failure_code(), Some(ErrorCode::PromptTooLong));
+        assert!(batch.results[0].success);
+        assert_eq!(batch.results[1].error_code, Some(ErrorCode::PromptTooLong));
+        assert_eq!(backend.prompts(), ["short"]);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_prompt_limit_are_refused() {
+        let backend = Arc::new(MockBackend::new(&["llama3"], "Hi there"));
+        let args = leader_args(&["--model", "llama3", "--max-prompt-chars", "10"]);
+        let ctx = LeaderContext::new(&args, backend.clone()).unwrap();
+        let long = "x".repeat(11);
+
+        for request in [
+            ask(&long),
+            AxonRequest::Embedding(EmbeddingRequest {
+                input: vec!["short".to_string(), long.clone()],
+                model: None,
+            }),
+            AxonRequest::CountTokens(CountTokensRequest {
+                prompt: long.clone(),
+                model: None,
+            }),
+        ] {
+            let response = dispatch_request(
+                &ctx,
+                PeerId::random(),
+                request.clone(),
+                &CancellationToken::new(),
+            )
+            .await;
+            assert_eq!(
+                response.failure_code(),
+                Some(ErrorCode::PromptTooLong),
+                "{:?}",
+                request
+            );
+        }
+        assert!(backend.prompts().is_empty());
+    }
+
+    /// Request ids as a swarm would hand them out
+    fn outbound_ids(count: usize) -> Vec<OutboundRequestId> {
+        let mut behaviour = request_response::Behaviour::with_codec(
+            InferenceCodec::default(),
+            iter::once((StreamProtocol::new(PROTOCOL_NAME), ProtocolSupport::Full)),
+            request_response::Config::default(),
+        );
+        let peer = PeerId::random();
+        (0..count)
+            .map(|_| behaviour.send_request(&peer, AxonRequest::Ping))
+            .collect()
+    }
+
+    #[test]
+    fn only_the_pending_request_is_answered() {
+        let ids = outbound_ids(2);
+        let pong = || AxonResponse::Pong {
+            backend_healthy: true,
+        };
+        let mut printed = 0;
+
+        let stray = take_answer(Some(ids[0]), ids[1], PeerId::random(), pong(), |_| {
+            printed += 1;
+            Ok(())
+        })
+        .unwrap();
+        let unsent = take_answer(None, ids[1], PeerId::random(), pong(), |_| {
+            printed += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(!stray && !unsent);
+        assert_eq!(printed, 0);
+
+        let answered = take_answer(Some(ids[0]), ids[0], PeerId::random(), pong(), |_| {
+            printed += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(answered);
+        assert_eq!(printed, 1);
+    }
+
+    #[test]
+    fn config_print_redacts_every_api_key() {
+        let args = cli::Args::try_parse_from([
+            "axon_cluster",
+            "config",
+            "print",
+            "--backend-api-key",
+            "backend-secret",
+            "--ollama-api-key",
+            "ollama-secret",
+            "--http-api-key",
+            "http-secret",
+        ])
+        .unwrap();
+        let Mode::Config {
+            command: ConfigCommand::Print { leader },
+        } = &args.mode
+        else {
+            unreachable!()
+        };
+
+        let printed = config_toml(&args, leader).unwrap();
+
+        assert!(!printed.contains("secret"), "{}", printed);
+        for key in ["backend_api_key", "ollama_api_key", "http_api_key"] {
+            assert!(
+                printed.contains(&format!("{} = \"<redacted>\"", key)),
+                "{}",
+                printed
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn generated_swarm_key_parses() {
+        let dir = scratch_dir("keygen");
+        let path = dir.join("swarm.key");
+
+        write_psk(&path, false).unwrap();
+        let key = parse_psk(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_ne!(key, [0u8; 32]);
+        #[cfg(unix)]
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn swarm_key_is_only_replaced_with_force() {
+        let dir = scratch_dir("keygen-force");
+        let path = dir.join("swarm.key");
+        write_psk(&path, false).unwrap();
+        let original = fs::read_to_string(&path).unwrap();
+
+        let error = write_psk(&path, false).unwrap_err().to_string();
+        assert!(error.contains("pass --force"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        write_psk(&path, true).unwrap();
+        let replaced = fs::read_to_string(&path).unwrap();
+
+        assert_ne!(replaced, original);
+        parse_psk(&replaced).unwrap();
+        #[cfg(unix)]
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    const KEY_HEX: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn psk_error(contents: &str) -> String {
+        parse_psk(contents).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn swarm_key_parses_despite_whitespace_case_and_bom() {
+        let contents = format!(
+            "\u{feff}  {}\r\n\r\n{}\r\n{}  \n",
+            PSK_HEADER,
+            PSK_ENCODING,
+            KEY_HEX.to_uppercase()
+        );
+
+        let key = parse_psk(&contents).unwrap();
+
+        assert_eq!(hex::encode(key), KEY_HEX);
+    }
+
+    #[test]
+    fn swarm_key_with_a_bad_header_is_refused() {
+        let error = psk_error(&format!(
+            "/key/swarm/psk/2.0.0/\n{}\n{}\n",
+            PSK_ENCODING, KEY_HEX
+        ));
+
+        assert!(
+            error.starts_with("line 1 should be '/key/swarm/psk/1.0.0/'"),
+            "{}",
+            error
+        );
+        assert_eq!(psk_error(""), "the file is empty");
+    }
+
+    #[test]
+    fn swarm_key_with_another_encoding_is_refused() {
+        let error = psk_error(&format!("{}\n/base64/\n{}\n", PSK_HEADER, KEY_HEX));
+
+        assert!(
+            error.starts_with("line 2 should be '/base16/'"),
+            "{}",
+            error
+        );
+        assert!(error.ends_with("but is '/base64/'"), "{}", error);
+        assert_eq!(
+            psk_error(&format!("{}\n", PSK_HEADER)),
+            "'/base16/' is missing after the header"
+        );
+    }
+
+    #[test]
+    fn swarm_key_of_the_wrong_length_is_refused() {
+        let short = psk_error(&format!(
+            "{}\n{}\n{}\n",
+            PSK_HEADER,
+            PSK_ENCODING,
+            &KEY_HEX[..62]
+        ));
+        let long = psk_error(&format!(
+            "{}\n{}\n{}00\n",
+            PSK_HEADER, PSK_ENCODING, KEY_HEX
+        ));
+        let odd = psk_error(&format!(
+            "{}\n{}\n{}\n",
+            PSK_HEADER,
+            PSK_ENCODING,
+            &KEY_HEX[..63]
+        ));
+
+        assert_eq!(
+            short,
+            "the key on line 3 is 62 hex digits long, expected 64 (32 bytes)"
+        );
+        assert!(long.contains("is 66 hex digits long"), "{}", long);
+        assert!(odd.contains("is 63 hex digits long"), "{}", odd);
+        assert_eq!(
+            psk_error(&format!("{}\n{}\n", PSK_HEADER, PSK_ENCODING)),
+            "the key is missing after '/base16/'"
+        );
+    }
+
+    #[test]
+    fn swarm_key_with_non_hex_digits_is_refused() {
+        let key = KEY_HEX.replacen('a', "g", 1);
+        let error = psk_error(&format!("{}\n{}\n{}\n", PSK_HEADER, PSK_ENCODING, key));
+
+        assert_eq!(
+            error,
+            "the key on line 3 contains 'g', which is not a hex digit"
+        );
+    }
+
+    #[test]
+    fn swarm_key_with_trailing_text_is_refused() {
+        let error = psk_error(&format!(
+            "{}\n{}\n{}\nextra\n",
+            PSK_HEADER, PSK_ENCODING, KEY_HEX
+        ));
+
+        assert_eq!(error, "unexpected text on line 4, after the key");
+    }
+
+    #[test]
+    fn identity_round_trips_through_its_file() {
+        let dir = scratch_dir("identity");
+        let path = dir.join("nested").join("identity.key");
+
+        let created = load_identity(&path).unwrap();
+        let loaded = load_identity(&path).unwrap();
+
+        assert_eq!(created.public(), loaded.public());
+        #[cfg(unix)]
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn identity_is_not_saved_over_an_existing_file() {
+        let dir = scratch_dir("identity-exists");
+        let path = dir.join("identity.key");
+        fs::write(&path, b"keep me").unwrap();
+
+        let keypair = identity::Keypair::generate_ed25519();
+        assert!(save_identity(&path, &keypair).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"keep me");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! Prometheus metrics a Leader exposes at `/metrics`
+
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{Histogram, exponential_buckets},
+    },
+    registry::Registry,
+};
+use std::time::Duration;
+
+use crate::protocol::ErrorCode;
+
+/// Content type of [`Metrics::encode`]'s output
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ErrorLabels {
+    code: String,
+}
+
+/// Request counts, errors, connected peers and inference latency
+pub struct Metrics {
+    registry: Registry,
+    requests: Counter,
+    errors: Family<ErrorLabels, Counter>,
+    peers_connected: Gauge,
+    inference_duration: Histogram,
+    cache_hits: Counter,
+    cache_misses: Counter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let requests = Counter::default();
+        let errors = Family::<ErrorLabels, Counter>::default();
+        let peers_connected = Gauge::default();
+        // 50ms to roughly 3.5 minutes
+        let inference_duration = Histogram::new(exponential_buckets(0.05, 2.0, 13));
+        let cache_hits = Counter::default();
+        let cache_misses = Counter::default();
+
+        let mut registry = Registry::default();
+        registry.register(
+            "axon_requests",
+            "Requests served with the backend",
+            requests.clone(),
+        );
+        registry.register(
+            "axon_errors",
+            "Served requests that failed, by error code",
+            errors.clone(),
+        );
+        registry.register(
+            "axon_peers_connected",
+            "Peers with an open connection",
+            peers_connected.clone(),
+        );
+        registry.register(
+            "axon_inference_duration_seconds",
+            "Time taken to serve a request with the backend",
+            inference_duration.clone(),
+        );
+        registry.register(
+            "axon_cache_hits",
+            "Requests answered from the response cache",
+            cache_hits.clone(),
+        );
+        registry.register(
+            "axon_cache_misses",
+            "Cacheable requests not found in the response cache",
+            cache_misses.clone(),
+        );
+
+        Self {
+            registry,
+            requests,
+            errors,
+            peers_connected,
+            inference_duration,
+            cache_hits,
+            cache_misses,
+        }
+    }
+
+    /// Count a served request, failed with `failure` if set, that took `duration`
+    pub fn record_request(&self, failure: Option<ErrorCode>, duration: Duration) {
+        self.requests.inc();
+        if let Some(code) = failure {
+            self.errors
+                .get_or_create(&ErrorLabels {
+                    code: code.to_string(),
+                })
+                .inc();
+        }
+        self.inference_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Count a response cache lookup
+    pub fn record_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.cache_hits.inc();
+        } else {
+            self.cache_misses.inc();
+        }
+    }
+
+    pub fn set_peers_connected(&self, peers: usize) {
+        self.peers_connected.set(peers as i64);
+    }
+
+    /// Every metric, in the OpenMetrics text format Prometheus scrapes
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        // Writing to a String can't fail
+        encode(&mut text, &self.registry).ok();
+        text
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,62 +1,1224 @@
 //! Ollama API integration for AI inference
 
 use anyhow::Result;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher, RandomState},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::protocol::{ChatMessage, ErrorCode, InferenceStats};
 
 /// Ollama API request payload
 #[derive(Debug, Serialize)]
 struct OllamaRequest {
     model: String,
     prompt: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<KeepAlive>,
+    #[serde(flatten)]
+    format: PromptFormat,
     stream: bool,
 }
 
+/// How a generate call frames the prompt for the model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PromptFormat {
+    /// System prompt, replacing the one from the model's Modelfile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Prompt template, replacing the model's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Send the prompt exactly as given, without any template
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw: bool,
+    /// Make the model answer in JSON, optionally following a schema
+    #[serde(default, rename = "format", skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+}
+
+/// Structure a generation's output must have
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OutputFormat {
+    /// Any JSON value, written as `"json"`
+    Json(JsonOutput),
+    /// JSON following this schema
+    Schema(serde_json::Map<String, serde_json::Value>),
+}
+
+/// The `"json"` output format
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JsonOutput {
+    #[serde(rename = "json")]
+    Json,
+}
+
+/// How long Ollama keeps a model loaded after a call
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum KeepAlive {
+    /// Seconds; negative keeps the model loaded indefinitely
+    Seconds(i64),
+    /// A duration such as "30m" or "2h"
+    Duration(String),
+}
+
+impl std::str::FromStr for KeepAlive {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Ok(seconds) = value.parse() {
+            return Ok(KeepAlive::Seconds(seconds));
+        }
+        anyhow::ensure!(
+            value.ends_with(['s', 'm', 'h']) && value.starts_with(|c: char| c.is_ascii_digit()),
+            "Invalid keep-alive '{}', expected seconds or a duration like \"30m\"",
+            value
+        );
+        Ok(KeepAlive::Duration(value.to_string()))
+    }
+}
+
+impl std::fmt::Display for KeepAlive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeepAlive::Seconds(seconds) if *seconds < 0 => f.write_str("forever"),
+            KeepAlive::Seconds(seconds) => write!(f, "{}s", seconds),
+            KeepAlive::Duration(duration) => f.write_str(duration),
+        }
+    }
+}
+
+/// Model parameters passed in the `options` object of generate and chat calls
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OllamaOptions {
+    /// Context window size in tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Sequences that end generation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Any other option, forwarded to Ollama untouched
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl OllamaOptions {
+    /// Combine with `overrides`, whose settings win
+    pub fn merge(mut self, overrides: OllamaOptions) -> Self {
+        self.num_ctx = overrides.num_ctx.or(self.num_ctx);
+        self.temperature = overrides.temperature.or(self.temperature);
+        self.repeat_penalty = overrides.repeat_penalty.or(self.repeat_penalty);
+        self.seed = overrides.seed.or(self.seed);
+        self.stop = overrides.stop.or(self.stop);
+        self.extra.extend(overrides.extra);
+        self
+    }
+}
+
 /// Ollama API response payload
 #[derive(Debug, Deserialize)]
 struct OllamaResponse {
     response: String,
     #[allow(dead_code)]
     done: bool,
+    #[serde(flatten)]
+    timings: OllamaTimings,
+}
+
+/// Token and timing counters reported by generate and chat calls
+#[derive(Debug, Deserialize)]
+struct OllamaTimings {
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+    /// Nanoseconds spent on the whole request
+    total_duration: Option<u64>,
+    /// Nanoseconds spent generating the completion tokens
+    eval_duration: Option<u64>,
+}
+
+impl OllamaTimings {
+    /// Derive statistics from Ollama's counters, if it reported them
+    fn stats(&self, retries: u32) -> Option<InferenceStats> {
+        let completion_tokens = self.eval_count?;
+        let tokens_per_second = match self.eval_duration {
+            Some(ns) if ns > 0 => completion_tokens as f64 / (ns as f64 / 1e9),
+            _ => 0.0,
+        };
+
+        Some(InferenceStats {
+            prompt_tokens: self.prompt_eval_count.unwrap_or(0),
+            completion_tokens,
+            total_duration_ms: self.total_duration.unwrap_or(0) / 1_000_000,
+            tokens_per_second,
+            retries,
+        })
+    }
+}
+
+/// Text produced by a generate call, with Ollama's statistics
+#[derive(Debug)]
+pub struct Generation {
+    pub response: String,
+    pub stats: Option<InferenceStats>,
+}
+
+/// One NDJSON line of a streamed generate call
+#[derive(Debug, Deserialize)]
+struct OllamaStreamLine {
+    #[serde(default)]
+    response: String,
+    /// Chat streams carry each token in a message instead of `response`
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    /// Set instead of a token when generation fails part-way
+    error: Option<String>,
+    #[serde(flatten)]
+    timings: OllamaTimings,
+}
+
+/// A piece of a streamed generation
+#[derive(Debug)]
+pub enum GenerationChunk {
+    /// Text produced since the previous chunk
+    Token(String),
+    /// Generation finished; always the last chunk of a successful stream
+    Done { stats: Option<InferenceStats> },
+}
+
+/// Chunks buffered between the HTTP reader and the consumer
+pub(crate) const STREAM_BUFFER: usize = 64;
+
+/// Ollama pull request payload
+#[derive(Debug, Serialize)]
+struct OllamaPullRequest {
+    model: String,
+    stream: bool,
+}
+
+/// One NDJSON progress record of a pull
+#[derive(Debug, Deserialize)]
+struct OllamaPullLine {
+    #[serde(default)]
+    status: String,
+    digest: Option<String>,
+    total: Option<u64>,
+    completed: Option<u64>,
+    /// Set instead of a status when the pull fails part-way
+    error: Option<String>,
+}
+
+/// Progress of a model pull, e.g. "pulling manifest" or one layer's download
+#[derive(Debug, Clone)]
+pub struct PullProgress {
+    pub status: String,
+    /// Layer being downloaded, if any
+    pub digest: Option<String>,
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+}
+
+impl PullProgress {
+    /// Percentage of the current layer downloaded, if Ollama reported sizes
+    pub fn percent(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) if total > 0 => {
+                Some(completed as f64 * 100.0 / total as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Ollama chat request payload
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<KeepAlive>,
+    #[serde(rename = "format", skip_serializing_if = "Option::is_none")]
+    output: Option<OutputFormat>,
+    stream: bool,
+}
+
+/// Ollama chat response payload
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: ChatMessage,
+    #[serde(flatten)]
+    timings: OllamaTimings,
+}
+
+/// Assistant reply produced by a chat call, with Ollama's statistics
+#[derive(Debug)]
+pub struct ChatReply {
+    pub message: ChatMessage,
+    pub stats: Option<InferenceStats>,
+}
+
+/// Ollama version response payload
+#[derive(Debug, Deserialize)]
+struct OllamaVersionResponse {
+    version: String,
+}
+
+/// Ollama model list response payload
+#[derive(Debug, Deserialize)]
+struct OllamaTagsResponse {
+    models: Vec<ModelInfo>,
+}
+
+/// A model available locally in Ollama
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub name: String,
+    /// Size on disk in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// When the model was last pulled or changed, as reported by Ollama (RFC 3339)
+    #[serde(default)]
+    pub modified_at: String,
+}
+
+impl ModelInfo {
+    /// Whether this is `model`, treating an untagged name as `<name>:latest`
+    pub fn is(&self, model: &str) -> bool {
+        self.name == model || self.name.strip_suffix(":latest") == Some(model)
+    }
+}
+
+/// Whether `a` and `b` name the same model, treating an untagged name as `<name>:latest`
+pub fn same_model(a: &str, b: &str) -> bool {
+    a == b || a.strip_suffix(":latest") == Some(b) || b.strip_suffix(":latest") == Some(a)
+}
+
+/// Ollama running models response payload
+#[derive(Debug, Deserialize)]
+struct OllamaPsResponse {
+    models: Vec<RunningModel>,
+}
+
+/// A model Ollama currently has loaded in memory
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunningModel {
+    pub name: String,
+    /// Memory used in bytes, in RAM and VRAM together
+    #[serde(default)]
+    pub size: u64,
+    /// Part of `size` held in GPU memory
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When Ollama will unload the model unless it is used again (RFC 3339)
+    #[serde(default)]
+    pub expires_at: String,
+}
+
+/// Ollama embeddings request payload
+#[derive(Debug, Serialize)]
+struct OllamaEmbeddingRequest {
+    model: String,
+    prompt: String,
+}
+
+/// Ollama embeddings response payload
+#[derive(Debug, Deserialize)]
+struct OllamaEmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+/// Ollama tokenize request payload
+#[derive(Debug, Serialize)]
+struct OllamaTokenizeRequest {
+    model: String,
+    content: String,
+}
+
+/// Ollama tokenize response payload
+#[derive(Debug, Deserialize)]
+struct OllamaTokenizeResponse {
+    tokens: Vec<u64>,
+}
+
+/// Ollama model details request payload
+#[derive(Debug, Serialize)]
+struct OllamaShowRequest {
+    model: String,
+}
+
+/// Ollama model details response payload
+#[derive(Debug, Deserialize)]
+struct OllamaShowResponse {
+    #[serde(default)]
+    details: OllamaShowDetails,
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+}
+
+/// The `details` object of a model details response
+#[derive(Debug, Default, Deserialize)]
+struct OllamaShowDetails {
+    family: Option<String>,
+    parameter_size: Option<String>,
+    quantization_level: Option<String>,
+}
+
+/// What Ollama reports about a model's architecture and size
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelDetails {
+    /// Model family, e.g. "llama"
+    pub family: Option<String>,
+    /// Parameter count, e.g. "7B"
+    pub parameter_size: Option<String>,
+    /// Quantization, e.g. "Q4_0"
+    pub quantization_level: Option<String>,
+    /// Maximum tokens the model accepts
+    pub context_length: Option<u64>,
+}
+
+/// Non-success HTTP status returned by the Ollama API
+#[derive(Debug)]
+pub struct OllamaApiError {
+    pub status: reqwest::StatusCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for OllamaApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Ollama API error ({}): {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for OllamaApiError {}
+
+impl OllamaApiError {
+    /// Whether Ollama, or a proxy in front of it, refused our credentials
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self.status.as_u16(), 401 | 403)
+    }
+}
+
+/// A failure a backend has already classified
+#[derive(Debug)]
+pub struct BackendError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// An Ollama call that ran out of time
+#[derive(Debug)]
+pub struct OllamaTimeout {
+    /// Which limit was hit: "connect", "request" or "read"
+    pub stage: &'static str,
+    pub after: Duration,
+}
+
+impl std::fmt::Display for OllamaTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Ollama {} timed out after {:?}", self.stage, self.after)
+    }
+}
+
+impl std::error::Error for OllamaTimeout {}
+
+/// A call abandoned through its cancellation token
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Request cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Run `call` unless `cancel` fires first, in which case fail with [`Cancelled`]
+///
+/// The unfinished call is dropped, closing its HTTP connection, which is
+/// what makes Ollama stop generating.
+pub async fn cancellable<T>(
+    cancel: &CancellationToken,
+    call: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(Cancelled.into()),
+        result = call => result,
+    }
+}
+
+/// Turn a non-success response into an [`OllamaApiError`]
+async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let message = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unknown error".to_string());
+    Err(OllamaApiError { status, message }.into())
+}
+
+/// Classify an error from [`OllamaClient`] or another backend for reporting to Subordinates
+pub fn error_code(err: &anyhow::Error) -> ErrorCode {
+    if let Some(backend_error) = err.downcast_ref::<BackendError>() {
+        return backend_error.code;
+    }
+
+    if err.downcast_ref::<OllamaTimeout>().is_some() {
+        return ErrorCode::Timeout;
+    }
+
+    if err.downcast_ref::<Cancelled>().is_some() {
+        return ErrorCode::Cancelled;
+    }
+
+    if let Some(api_error) = err.downcast_ref::<OllamaApiError>() {
+        return match api_error.status.as_u16() {
+            404 => ErrorCode::ModelNotFound,
+            400 | 413 | 422 => ErrorCode::InvalidRequest,
+            408 | 504 => ErrorCode::Timeout,
+            429 => ErrorCode::RateLimited,
+            500..=599 => ErrorCode::BackendUnavailable,
+            _ => ErrorCode::Internal,
+        };
+    }
+
+    if let Some(http_error) = err.downcast_ref::<reqwest::Error>() {
+        if http_error.is_timeout() {
+            return ErrorCode::Timeout;
+        }
+        if http_error.is_connect() || http_error.is_request() {
+            return ErrorCode::BackendUnavailable;
+        }
+    }
+
+    ErrorCode::Internal
+}
+
+/// Whether a failed call may succeed if simply sent again
+///
+/// Connection failures and 5xx statuses are transient; timeouts and 4xx
+/// statuses such as an unknown model are not.
+fn is_transient(err: &anyhow::Error) -> bool {
+    if let Some(api_error) = err.downcast_ref::<OllamaApiError>() {
+        return api_error.status.is_server_error();
+    }
+    err.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| !e.is_timeout() && (e.is_connect() || e.is_request()))
+}
+
+/// Delay before retry number `retry`: the base doubled per retry, plus up to 50% jitter
+fn backoff_delay(base: Duration, retry: u32) -> Duration {
+    let delay = base.saturating_mul(1 << retry.saturating_sub(1).min(10));
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+    delay + delay.mul_f64(jitter as f64 / 2000.0)
+}
+
+/// How long a health probe may take before Ollama is considered down
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeouts for calls to Ollama
+#[derive(Debug, Clone, Copy)]
+pub struct OllamaConfig {
+    /// Establishing a connection
+    pub connect_timeout: Duration,
+    /// A whole non-streaming call, from sending the request to reading the reply
+    pub request_timeout: Duration,
+    /// Waiting for the next part of a response body, including each chunk of a stream
+    pub read_timeout: Duration,
+    /// Tries per call, including the first, before a transient failure is returned
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_backoff: Duration,
 }
 
 /// Client for interacting with the Ollama API
+#[derive(Clone)]
 pub struct OllamaClient {
     base_url: String,
     client: reqwest::Client,
+    config: OllamaConfig,
+    /// Keep-alive sent with calls to each model; others use Ollama's default
+    keep_alive: HashMap<String, KeepAlive>,
 }
 
 impl OllamaClient {
-    /// Create a new Ollama client
-    pub fn new(base_url: String) -> Self {
-        Self {
+    /// Create a new Ollama client, sending `headers` with every call
+    pub fn new(base_url: String, config: OllamaConfig, headers: HeaderMap) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
             base_url,
-            client: reqwest::Client::new(),
+            client,
+            config,
+            keep_alive: HashMap::new(),
+        })
+    }
+
+    /// Ask Ollama to keep each model loaded for the given time after a call
+    pub fn with_keep_alive(mut self, keep_alive: HashMap<String, KeepAlive>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Models with a configured keep-alive
+    pub fn keep_alive_models(&self) -> impl Iterator<Item = &String> {
+        self.keep_alive.keys()
+    }
+
+    /// Keep-alive configured for `model`, treating an untagged name as `<name>:latest`
+    fn keep_alive_for(&self, model: &str) -> Option<KeepAlive> {
+        self.keep_alive
+            .get(model)
+            .or_else(|| self.keep_alive.get(model.strip_suffix(":latest")?))
+            .cloned()
+    }
+
+    /// Load `model` into memory without generating anything, applying its keep-alive
+    pub async fn load_model(&self, model: String) -> Result<()> {
+        let url = format!("{}/api/generate", self.base_url);
+
+        let request = OllamaRequest {
+            keep_alive: self.keep_alive_for(&model),
+            model,
+            prompt: String::new(),
+            images: Vec::new(),
+            options: None,
+            format: PromptFormat::default(),
+            stream: false,
+        };
+
+        self.send(self.client.post(&url).json(&request), false)
+            .await?;
+        Ok(())
+    }
+
+    /// Send a request, retrying transient failures; returns the response and
+    /// the number of retries it took
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+        streaming: bool,
+    ) -> Result<(reqwest::Response, u32)> {
+        let mut retries = 0;
+        loop {
+            let last = retries + 1 >= self.config.max_attempts;
+            let attempt = match request.try_clone() {
+                Some(attempt) if !last => attempt,
+                _ => return Ok((self.send_once(request, streaming).await?, retries)),
+            };
+
+            match self.send_once(attempt, streaming).await {
+                Ok(response) => return Ok((response, retries)),
+                Err(e) if is_transient(&e) => {
+                    retries += 1;
+                    let delay = backoff_delay(self.config.retry_backoff, retries);
+                    warn!(
+                        "🔁 Ollama call failed ({}), retrying in {:?} (attempt {}/{})",
+                        e,
+                        delay,
+                        retries + 1,
+                        self.config.max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Send a request once, failing on timeouts and non-success statuses
+    ///
+    /// Streaming calls are only bounded by the connect and read timeouts, since
+    /// they legitimately run for a long time.
+    async fn send_once(
+        &self,
+        request: reqwest::RequestBuilder,
+        streaming: bool,
+    ) -> Result<reqwest::Response> {
+        let response = if streaming {
+            tokio::time::timeout(self.config.read_timeout, request.send())
+                .await
+                .map_err(|_| OllamaTimeout {
+                    stage: "read",
+                    after: self.config.read_timeout,
+                })?
+        } else {
+            request.timeout(self.config.request_timeout).send().await
+        };
+
+        let response = response.map_err(|e| self.timeout_error(e))?;
+        ensure_success(response).await
+    }
+
+    /// Read a JSON reply, bounded by the read timeout
+    async fn read_json<T: serde::de::DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<T> {
+        match tokio::time::timeout(self.config.read_timeout, response.json()).await {
+            Ok(result) => result.map_err(|e| self.timeout_error(e)),
+            Err(_) => Err(OllamaTimeout {
+                stage: "read",
+                after: self.config.read_timeout,
+            }
+            .into()),
         }
     }
 
-    /// Send a prompt to Ollama and get the response
-    pub async fn generate(&self, prompt: String, model: String) -> Result<String> {
+    /// Replace reqwest's timeout errors with an [`OllamaTimeout`]
+    fn timeout_error(&self, err: reqwest::Error) -> anyhow::Error {
+        if !err.is_timeout() {
+            return err.into();
+        }
+        let (stage, after) = if err.is_connect() {
+            ("connect", self.config.connect_timeout)
+        } else {
+            ("request", self.config.request_timeout)
+        };
+        OllamaTimeout { stage, after }.into()
+    }
+
+    /// Check that Ollama is reachable and answering requests, returning its version
+    pub async fn health(&self) -> Result<String> {
+        let url = format!("{}/api/version", self.base_url);
+
+        let response = self.client.get(&url).timeout(HEALTH_TIMEOUT).send().await?;
+        let response = ensure_success(response).await?;
+        let version: OllamaVersionResponse = response.json().await?;
+
+        Ok(version.version)
+    }
+
+    /// Base URL of the Ollama API
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// List the models available locally
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let url = format!("{}/api/tags", self.base_url);
+
+        let (response, _) = self.send(self.client.get(&url), false).await?;
+
+        let tags: OllamaTagsResponse = self.read_json(response).await?;
+
+        Ok(tags.models)
+    }
+
+    /// List the models currently loaded in memory
+    pub async fn ps(&self) -> Result<Vec<RunningModel>> {
+        let url = format!("{}/api/ps", self.base_url);
+
+        let (response, _) = self.send(self.client.get(&url), false).await?;
+
+        let running: OllamaPsResponse = self.read_json(response).await?;
+
+        Ok(running.models)
+    }
+
+    /// Send a prompt, with optional base64-encoded images, to Ollama and get the response
+    ///
+    /// Fails with [`Cancelled`] as soon as `cancel` fires, abandoning the call.
+    pub async fn generate(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<Generation> {
         let url = format!("{}/api/generate", self.base_url);
 
         let request = OllamaRequest {
+            keep_alive: self.keep_alive_for(&model),
             model,
             prompt,
+            images,
+            options,
+            format,
             stream: false,
         };
 
-        let response = self.client.post(&url).json(&request).send().await?;
+        let (ollama_response, retries) = cancellable(cancel, async {
+            let (response, retries) = self
+                .send(self.client.post(&url).json(&request), false)
+                .await?;
+            let ollama_response: OllamaResponse = self.read_json(response).await?;
+            Ok((ollama_response, retries))
+        })
+        .await?;
+        let stats = ollama_response.timings.stats(retries);
+
+        Ok(Generation {
+            response: ollama_response.response,
+            stats,
+        })
+    }
+
+    /// Like [`generate`](Self::generate), but yield tokens as Ollama produces them
+    ///
+    /// The receiver ends after a [`GenerationChunk::Done`], or after an `Err`
+    /// if the body is malformed, reports an error, closes before finishing,
+    /// or `cancel` fires.
+    pub async fn generate_stream(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        let url = format!("{}/api/generate", self.base_url);
+
+        let request = OllamaRequest {
+            keep_alive: self.keep_alive_for(&model),
+            model,
+            prompt,
+            images,
+            options,
+            format,
+            stream: true,
+        };
+
+        let (response, retries) = cancellable(
+            cancel,
+            self.send(self.client.post(&url).json(&request), true),
+        )
+        .await?;
+        let reader = NdjsonReader::new(response, self.config.read_timeout);
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cancellable(&cancel, read_stream(reader, retries, &tx)).await {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Download `model` from the registry, calling `on_progress` for each update
+    ///
+    /// Fails if the name is invalid, or if the registry becomes unreachable and
+    /// Ollama gives up before reporting success.
+    pub async fn pull_model(
+        &self,
+        model: String,
+        mut on_progress: impl FnMut(&PullProgress),
+    ) -> Result<()> {
+        let url = format!("{}/api/pull", self.base_url);
+
+        let request = OllamaPullRequest {
+            model: model.clone(),
+            stream: true,
+        };
+
+        let (response, _) = self
+            .send(self.client.post(&url).json(&request), true)
+            .await?;
+
+        let mut reader = NdjsonReader::new(response, self.config.read_timeout);
+        while let Some(line) = reader.next::<OllamaPullLine>().await? {
+            if let Some(error) = line.error {
+                anyhow::bail!("Pulling '{}' failed: {}", model, error);
+            }
+            if line.status == "success" {
+                return Ok(());
+            }
+            on_progress(&PullProgress {
+                status: line.status,
+                digest: line.digest,
+                completed: line.completed,
+                total: line.total,
+            });
+        }
+
+        anyhow::bail!("Pulling '{}' stopped before Ollama reported success", model)
+    }
+
+    /// Send a conversation to Ollama and get the assistant's reply
+    ///
+    /// A leading system message sets the assistant's behaviour; an empty
+    /// conversation is refused without calling Ollama. Fails with
+    /// [`Cancelled`] as soon as `cancel` fires.
+    pub async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<ChatReply> {
+        anyhow::ensure!(!messages.is_empty(), "Chat requires at least one message");
+
+        let url = format!("{}/api/chat", self.base_url);
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
+        let request = OllamaChatRequest {
+            keep_alive: self.keep_alive_for(&model),
+            model,
+            messages,
+            options,
+            output,
+            stream: false,
+        };
+
+        let (chat_response, retries) = cancellable(cancel, async {
+            let (response, retries) = self
+                .send(self.client.post(&url).json(&request), false)
+                .await?;
+            let chat_response: OllamaChatResponse = self.read_json(response).await?;
+            Ok((chat_response, retries))
+        })
+        .await?;
+        let stats = chat_response.timings.stats(retries);
+
+        Ok(ChatReply {
+            message: chat_response.message,
+            stats,
+        })
+    }
+
+    /// Like [`chat`](Self::chat), but yield the reply's tokens as Ollama produces them
+    ///
+    /// The receiver ends like that of [`generate_stream`](Self::generate_stream).
+    pub async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        anyhow::ensure!(!messages.is_empty(), "Chat requires at least one message");
+
+        let url = format!("{}/api/chat", self.base_url);
+
+        let request = OllamaChatRequest {
+            keep_alive: self.keep_alive_for(&model),
+            model,
+            messages,
+            options,
+            output,
+            stream: true,
+        };
+
+        let (response, retries) = cancellable(
+            cancel,
+            self.send(self.client.post(&url).json(&request), true),
+        )
+        .await?;
+        let reader = NdjsonReader::new(response, self.config.read_timeout);
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cancellable(&cancel, read_stream(reader, retries, &tx)).await {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Count the tokens `content` takes for `model`
+    ///
+    /// Older Ollama versions don't provide a tokenize endpoint; callers should
+    /// be ready to fall back to an estimate.
+    pub async fn tokenize(&self, content: String, model: String) -> Result<u64> {
+        let url = format!("{}/api/tokenize", self.base_url);
+
+        let request = OllamaTokenizeRequest { model, content };
+
+        let (response, _) = self
+            .send(self.client.post(&url).json(&request), false)
+            .await?;
+
+        let tokenize_response: OllamaTokenizeResponse = self.read_json(response).await?;
+
+        Ok(tokenize_response.tokens.len() as u64)
+    }
+
+    /// Look up the family, size, quantization and context window of `model`
+    ///
+    /// A model Ollama doesn't have fails with a 404 [`OllamaApiError`].
+    pub async fn show_model(&self, model: String) -> Result<ModelDetails> {
+        let url = format!("{}/api/show", self.base_url);
+
+        let request = OllamaShowRequest { model };
+
+        let (response, _) = self
+            .send(self.client.post(&url).json(&request), false)
+            .await?;
+
+        // Keyed by architecture, e.g. "llama.context_length"
+        let show_response: OllamaShowResponse = self.read_json(response).await?;
+        let context_length = show_response
+            .model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64());
+
+        Ok(ModelDetails {
+            family: show_response.details.family,
+            parameter_size: show_response.details.parameter_size,
+            quantization_level: show_response.details.quantization_level,
+            context_length,
+        })
+    }
+
+    /// Compute the embedding vector for a single input
+    pub async fn embed(&self, input: String, model: String) -> Result<Vec<f32>> {
+        let url = format!("{}/api/embeddings", self.base_url);
+
+        let request = OllamaEmbeddingRequest {
+            model,
+            prompt: input,
+        };
+
+        let (response, _) = self
+            .send(self.client.post(&url).json(&request), false)
+            .await?;
+
+        let embedding_response: OllamaEmbeddingResponse = self.read_json(response).await?;
+
+        Ok(embedding_response.embedding)
+    }
+}
+
+/// Reads newline-delimited records from a streamed response body
+pub(crate) struct NdjsonReader {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    /// Longest wait for the next chunk of the body
+    read_timeout: Duration,
+}
+
+impl NdjsonReader {
+    pub(crate) fn new(response: reqwest::Response, read_timeout: Duration) -> Self {
+        Self {
+            response,
+            buffer: Vec::new(),
+            read_timeout,
+        }
+    }
+
+    /// Next record, or `None` once the body ends
+    ///
+    /// A line that isn't valid JSON for `T` is an error rather than skipped.
+    async fn next<T: serde::de::DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        let Some(line) = self.next_line().await? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&line)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Malformed line in Ollama stream: {}", e))
+    }
+
+    /// Next non-blank line, including its line ending, or `None` once the body ends
+    pub(crate) async fn next_line(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                return Ok(Some(line));
+            }
+
+            let chunk = tokio::time::timeout(self.read_timeout, self.response.chunk())
                 .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            anyhow::bail!("Ollama API error ({}): {}", status, error_text);
+                .map_err(|_| OllamaTimeout {
+                    stage: "read",
+                    after: self.read_timeout,
+                })??;
+            match chunk {
+                Some(bytes) => self.buffer.extend_from_slice(&bytes),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Forward each NDJSON line of a streamed generate body as a chunk
+///
+/// Returns once the `done` record has been sent or the consumer has gone away.
+async fn read_stream(
+    mut reader: NdjsonReader,
+    retries: u32,
+    tx: &mpsc::Sender<Result<GenerationChunk>>,
+) -> Result<()> {
+    while let Some(line) = reader.next::<OllamaStreamLine>().await? {
+        if let Some(error) = line.error {
+            anyhow::bail!("Ollama stream failed: {}", error);
+        }
+
+        let chunk = if line.done {
+            GenerationChunk::Done {
+                stats: line.timings.stats(retries),
+            }
+        } else {
+            GenerationChunk::Token(line.message.map_or(line.response, |m| m.content))
+        };
+        let finished = matches!(chunk, GenerationChunk::Done { .. });
+        if tx.send(Ok(chunk)).await.is_err() || finished {
+            return Ok(());
         }
+    }
+
+    anyhow::bail!("Ollama stream ended before generation finished")
+}
 
-        let ollama_response: OllamaResponse = response.json().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
 
-        Ok(ollama_response.response)
+    const GENERATED: &str = r#"{"response":"Hi there","done":true,"eval_count":2}"#;
+
+    /// Serve one canned `(status, body)` reply per connection, in order,
+    /// counting the requests received
+    async fn ollama(replies: Vec<(u16, &'static str)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in replies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let reply = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn client(url: String) -> OllamaClient {
+        let config = OllamaConfig {
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(10),
+        };
+        OllamaClient::new(url, config, HeaderMap::new()).unwrap()
+    }
+
+    async fn generate(client: &OllamaClient) -> Result<Generation> {
+        client
+            .generate(
+                "Hello".to_string(),
+                "llama3".to_string(),
+                Vec::new(),
+                None,
+                PromptFormat::default(),
+                &CancellationToken::new(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn server_error_is_retried() {
+        let (url, requests) = ollama(vec![(503, r#"{"error":"busy"}"#), (200, GENERATED)]).await;
+
+        let generation = generate(&client(url)).await.unwrap();
+
+        assert_eq!(generation.response, "Hi there");
+        assert_eq!(generation.stats.unwrap().retries, 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn client_error_is_not_retried() {
+        let (url, requests) = ollama(vec![(400, r#"{"error":"bad"}"#), (200, GENERATED)]).await;
+
+        let err = generate(&client(url)).await.unwrap_err();
+
+        assert_eq!(error_code(&err), ErrorCode::InvalidRequest);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn server_error_is_returned_after_the_last_attempt() {
+        let busy = (503, r#"{"error":"busy"}"#);
+        let (url, requests) = ollama(vec![busy; 3]).await;
+
+        let err = generate(&client(url)).await.unwrap_err();
+
+        assert_eq!(error_code(&err), ErrorCode::BackendUnavailable);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backoff_doubles_with_up_to_half_again_of_jitter() {
+        let base = Duration::from_millis(100);
+        for (retry, delay) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = Duration::from_millis(delay);
+            let actual = backoff_delay(base, retry);
+            assert!(
+                actual >= delay && actual <= delay.mul_f64(1.5),
+                "{:?}",
+                actual
+            );
+        }
     }
 }
@@ -0,0 +1,564 @@
+//! OpenAI-compatible chat completions API, as served by llama.cpp, vLLM or LM Studio
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    backend::InferenceBackend,
+    ollama::{
+        BackendError, ChatReply, Generation, GenerationChunk, ModelDetails, ModelInfo,
+        NdjsonReader, OllamaConfig, OllamaOptions, OllamaTimeout, OutputFormat, PromptFormat,
+        PullProgress, RunningModel, STREAM_BUFFER, cancellable,
+    },
+    protocol::{ChatMessage, ChatRole, ErrorCode, InferenceStats},
+};
+
+/// How long a health probe may take before the server is considered down
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Chat completions request payload
+#[derive(Debug, Serialize)]
+struct CompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    stream: bool,
+}
+
+impl CompletionRequest {
+    /// Map Ollama's options onto the request fields the API has equivalents for
+    ///
+    /// `num_predict` becomes `max_tokens`; options without an equivalent are dropped.
+    /// A JSON `output` format becomes the matching `response_format`.
+    fn new(
+        model: String,
+        messages: Vec<ChatMessage>,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        stream: bool,
+    ) -> Self {
+        let options = options.unwrap_or_default();
+        let response_format = output.map(|output| match output {
+            OutputFormat::Json(_) => json!({ "type": "json_object" }),
+            OutputFormat::Schema(schema) => json!({
+                "type": "json_schema",
+                "json_schema": { "name": "output", "schema": schema },
+            }),
+        });
+        Self {
+            model,
+            messages,
+            temperature: options.temperature,
+            seed: options.seed,
+            stop: options.stop,
+            max_tokens: options.extra.get("num_predict").and_then(|v| v.as_u64()),
+            response_format,
+            stream,
+        }
+    }
+}
+
+/// Chat completions response payload
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChoice {
+    message: CompletionMessage,
+}
+
+/// A reply message, or the `delta` of a streamed one
+#[derive(Debug, Default, Deserialize)]
+struct CompletionMessage {
+    /// Absent or null for replies without text, such as tool calls
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// One server-sent event of a streamed completion
+#[derive(Debug, Deserialize)]
+struct CompletionStreamEvent {
+    #[serde(default)]
+    choices: Vec<CompletionStreamChoice>,
+    usage: Option<Usage>,
+    /// Set instead of choices when generation fails part-way
+    error: Option<ApiErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionStreamChoice {
+    #[serde(default)]
+    delta: CompletionMessage,
+}
+
+/// Token counts reported with a completion
+#[derive(Debug, Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl Usage {
+    /// Derive statistics from the token counts and the measured duration
+    fn stats(&self, elapsed: Duration) -> InferenceStats {
+        let seconds = elapsed.as_secs_f64();
+        InferenceStats {
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            total_duration_ms: elapsed.as_millis() as u64,
+            tokens_per_second: if seconds > 0.0 {
+                self.completion_tokens as f64 / seconds
+            } else {
+                0.0
+            },
+            retries: 0,
+        }
+    }
+}
+
+/// Model list response payload
+#[derive(Debug, Deserialize)]
+struct ModelsResponse {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+/// Embeddings request payload
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest {
+    model: String,
+    input: String,
+}
+
+/// Embeddings response payload
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingEntry {
+    embedding: Vec<f32>,
+}
+
+/// Error response payload, e.g. `{"error": {"message": "...", "code": "model_not_found"}}`
+#[derive(Debug, Deserialize)]
+struct ApiErrorResponse {
+    error: ApiErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorDetail {
+    message: String,
+    /// A string such as "model_not_found", or the HTTP status on some servers
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+}
+
+impl ApiErrorDetail {
+    /// Classify the error by its code, falling back to the HTTP status
+    fn into_error(self, status: Option<reqwest::StatusCode>) -> BackendError {
+        let by_code = match self.code.as_ref().and_then(|code| code.as_str()) {
+            Some("model_not_found") => Some(ErrorCode::ModelNotFound),
+            Some("context_length_exceeded") => Some(ErrorCode::InvalidRequest),
+            Some("rate_limit_exceeded") => Some(ErrorCode::RateLimited),
+            _ => None,
+        };
+        let status = status.or_else(|| {
+            let code = self.code.as_ref()?.as_u64()?;
+            reqwest::StatusCode::from_u16(u16::try_from(code).ok()?).ok()
+        });
+        let code = by_code.unwrap_or_else(|| match status.map(|s| s.as_u16()) {
+            Some(404) => ErrorCode::ModelNotFound,
+            Some(400 | 413 | 422) => ErrorCode::InvalidRequest,
+            Some(408 | 504) => ErrorCode::Timeout,
+            Some(429) => ErrorCode::RateLimited,
+            Some(500..=599) => ErrorCode::BackendUnavailable,
+            _ => ErrorCode::Internal,
+        });
+
+        BackendError {
+            code,
+            message: match status {
+                Some(status) => {
+                    format!("OpenAI-compatible API error ({}): {}", status, self.message)
+                }
+                None => format!("OpenAI-compatible API error: {}", self.message),
+            },
+        }
+    }
+}
+
+/// Turn a non-success response into a classified [`BackendError`]
+async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let detail = serde_json::from_str::<ApiErrorResponse>(&body)
+        .map(|response| response.error)
+        .unwrap_or_else(|_| ApiErrorDetail {
+            message: if body.is_empty() {
+                "Unknown error".to_string()
+            } else {
+                body
+            },
+            code: None,
+        });
+    Err(detail.into_error(Some(status)).into())
+}
+
+/// Refuse what the chat completions API can't express
+fn check_supported(images: &[String], format: &PromptFormat) -> Result<()> {
+    let unsupported = if !images.is_empty() {
+        "Images"
+    } else if format.raw {
+        "Raw prompts"
+    } else if format.template.is_some() {
+        "Prompt templates"
+    } else {
+        return Ok(());
+    };
+    Err(BackendError {
+        code: ErrorCode::InvalidRequest,
+        message: format!(
+            "{} aren't supported by the OpenAI-compatible backend",
+            unsupported
+        ),
+    }
+    .into())
+}
+
+/// The conversation sent for a single prompt
+fn prompt_messages(prompt: String, system: Option<String>) -> Vec<ChatMessage> {
+    let system = system.map(|content| ChatMessage {
+        role: ChatRole::System,
+        content,
+    });
+    system
+        .into_iter()
+        .chain([ChatMessage {
+            role: ChatRole::User,
+            content: prompt,
+        }])
+        .collect()
+}
+
+/// Backend for servers speaking the OpenAI chat completions API
+pub struct OpenAiCompatBackend {
+    /// API root including the version, e.g. "http://127.0.0.1:8080/v1"
+    base_url: String,
+    /// Sent as a bearer token when set
+    api_key: Option<String>,
+    client: reqwest::Client,
+    config: OllamaConfig,
+}
+
+impl OpenAiCompatBackend {
+    /// Create a backend for the API at `base_url`, using the timeouts of `config`
+    pub fn new(base_url: String, api_key: Option<String>, config: OllamaConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .build()?;
+
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            client,
+            config,
+        })
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.client.get(format!("{}{}", self.base_url, path)))
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.authorize(self.client.post(format!("{}{}", self.base_url, path)))
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    /// Send a non-streaming request and read its JSON reply within the request timeout
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T> {
+        let response = request.timeout(self.config.request_timeout).send().await?;
+        Ok(ensure_success(response).await?.json().await?)
+    }
+
+    /// Run a non-streaming chat completion
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+    ) -> Result<(String, Option<InferenceStats>)> {
+        let request = CompletionRequest::new(model, messages, options, output, false);
+
+        let started = Instant::now();
+        let response: CompletionResponse = self
+            .call(self.post("/chat/completions").json(&request))
+            .await?;
+        let stats = response.usage.map(|usage| usage.stats(started.elapsed()));
+
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Completion response has no choices"))?;
+        Ok((choice.message.content.unwrap_or_default(), stats))
+    }
+
+    /// Run a streaming chat completion, yielding tokens as they arrive until
+    /// `cancel` fires
+    async fn stream_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        let request = CompletionRequest::new(model, messages, options, output, true);
+
+        let started = Instant::now();
+        let read_timeout = self.config.read_timeout;
+        let response = cancellable(cancel, async {
+            let response = tokio::time::timeout(
+                read_timeout,
+                self.post("/chat/completions").json(&request).send(),
+            )
+            .await
+            .map_err(|_| OllamaTimeout {
+                stage: "read",
+                after: read_timeout,
+            })??;
+            ensure_success(response).await
+        })
+        .await?;
+        let reader = NdjsonReader::new(response, read_timeout);
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cancellable(&cancel, read_events(reader, started, &tx)).await {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for OpenAiCompatBackend {
+    fn endpoint(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn health(&self) -> Result<String> {
+        let response = self.get("/models").timeout(HEALTH_TIMEOUT).send().await?;
+        ensure_success(response).await?;
+        Ok("OpenAI-compatible server".to_string())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let models: ModelsResponse = self.call(self.get("/models")).await?;
+        Ok(models
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                name: model.id,
+                size: 0,
+                modified_at: String::new(),
+            })
+            .collect())
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        anyhow::bail!("The OpenAI-compatible API doesn't report loaded models")
+    }
+
+    async fn show_model(&self, model: String) -> Result<ModelDetails> {
+        // The API has no metadata beyond the model list
+        if self.list_models().await?.iter().any(|m| m.is(&model)) {
+            return Ok(ModelDetails::default());
+        }
+        Err(BackendError {
+            code: ErrorCode::ModelNotFound,
+            message: format!("Model '{}' not found", model),
+        }
+        .into())
+    }
+
+    async fn load_model(&self, _model: String) -> Result<()> {
+        // Models are loaded by the server itself
+        Ok(())
+    }
+
+    async fn pull_model(
+        &self,
+        _model: String,
+        _on_progress: Box<dyn for<'p> FnMut(&'p PullProgress) + Send>,
+    ) -> Result<()> {
+        anyhow::bail!("The OpenAI-compatible API can't download models")
+    }
+
+    async fn generate(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<Generation> {
+        check_supported(&images, &format)?;
+
+        let messages = prompt_messages(prompt, format.system);
+        let (response, stats) = cancellable(
+            cancel,
+            self.complete(messages, model, options, format.output),
+        )
+        .await?;
+        Ok(Generation { response, stats })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: String,
+        model: String,
+        images: Vec<String>,
+        options: Option<OllamaOptions>,
+        format: PromptFormat,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        check_supported(&images, &format)?;
+
+        let messages = prompt_messages(prompt, format.system);
+        self.stream_completion(messages, model, options, format.output, cancel)
+            .await
+    }
+
+    async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<ChatReply> {
+        let (content, stats) =
+            cancellable(cancel, self.complete(messages, model, options, output)).await?;
+        Ok(ChatReply {
+            message: ChatMessage {
+                role: ChatRole::Assistant,
+                content,
+            },
+            stats,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: String,
+        options: Option<OllamaOptions>,
+        output: Option<OutputFormat>,
+        cancel: &CancellationToken,
+    ) -> Result<mpsc::Receiver<Result<GenerationChunk>>> {
+        self.stream_completion(messages, model, options, output, cancel)
+            .await
+    }
+
+    async fn embed(&self, input: String, model: String) -> Result<Vec<f32>> {
+        let request = EmbeddingRequest { model, input };
+        let response: EmbeddingResponse =
+            self.call(self.post("/embeddings").json(&request)).await?;
+
+        response
+            .data
+            .into_iter()
+            .next()
+            .map(|entry| entry.embedding)
+            .ok_or_else(|| anyhow::anyhow!("Embedding response has no data"))
+    }
+
+    async fn tokenize(&self, _content: String, _model: String) -> Result<u64> {
+        anyhow::bail!("The OpenAI-compatible API has no tokenize endpoint")
+    }
+}
+
+/// Forward each server-sent event of a streamed completion as a chunk
+///
+/// Returns once `[DONE]` has been seen or the consumer has gone away.
+async fn read_events(
+    mut reader: NdjsonReader,
+    started: Instant,
+    tx: &mpsc::Sender<Result<GenerationChunk>>,
+) -> Result<()> {
+    let mut usage = None;
+    while let Some(line) = reader.next_line().await? {
+        let line = std::str::from_utf8(&line)?.trim();
+        // Comments and fields other than data, such as "event:", carry no tokens
+        let Some(data) = line.strip_prefix("data:") else {
+            continue;
+        };
+        let data = data.trim_start();
+
+        if data == "[DONE]" {
+            let stats = usage.map(|usage: Usage| usage.stats(started.elapsed()));
+            let _ = tx.send(Ok(GenerationChunk::Done { stats })).await;
+            return Ok(());
+        }
+
+        let event: CompletionStreamEvent = serde_json::from_str(data)
+            .map_err(|e| anyhow::anyhow!("Malformed event in completion stream: {}", e))?;
+        if let Some(error) = event.error {
+            return Err(error.into_error(None).into());
+        }
+        usage = event.usage.or(usage);
+
+        let token: String = event
+            .choices
+            .into_iter()
+            .filter_map(|choice| choice.delta.content)
+            .collect();
+        if !token.is_empty() && tx.send(Ok(GenerationChunk::Token(token))).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("Completion stream ended before [DONE]")
+}
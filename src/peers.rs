@@ -0,0 +1,671 @@
+//! Tracking of discovered Leader peers and request distribution
+
+use anyhow::Result;
+use libp2p::{
+    Multiaddr, PeerId, Swarm,
+    request_response::OutboundRequestId,
+    swarm::{ConnectionId, NetworkBehaviour, dial_opts::DialOpts},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::ollama::same_model;
+
+/// How often known Leaders are pinged
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Consecutive failed heartbeats after which a Leader is dropped
+pub const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// How often static peers are checked for a due redial
+pub const STATIC_REDIAL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before redialling a static peer after its first failure or a lost connection
+const STATIC_REDIAL_MIN_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Longest delay between redials of an unreachable static peer
+const STATIC_REDIAL_MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Round-robin selector over the set of known Leader peers
+#[derive(Debug, Default)]
+pub struct LeaderPool {
+    peers: Vec<PeerId>,
+    cursor: usize,
+}
+
+impl LeaderPool {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a peer, returning `true` if it was not already known
+    pub fn insert(&mut self, peer_id: PeerId) -> bool {
+        if self.peers.contains(&peer_id) {
+            return false;
+        }
+        self.peers.push(peer_id);
+        true
+    }
+
+    /// Remove a peer, returning `true` if it was known
+    pub fn remove(&mut self, peer_id: &PeerId) -> bool {
+        let Some(index) = self.peers.iter().position(|p| p == peer_id) else {
+            return false;
+        };
+        self.peers.remove(index);
+
+        // Keep the cursor pointing at the peer that would have been next
+        if index < self.cursor {
+            self.cursor -= 1;
+        }
+        if self.cursor >= self.peers.len() {
+            self.cursor = 0;
+        }
+        true
+    }
+
+    /// Number of known peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Iterate over the known peers in rotation order
+    pub fn iter(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.iter()
+    }
+
+    /// Whether no peers are known
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Pick the next peer in rotation and advance the cursor
+    ///
+    /// Peers for which `is_connected` returns `false` are skipped. If none of
+    /// the known peers are connected, the next peer in rotation is returned so
+    /// that sending a request to it triggers a dial.
+    pub fn next(&mut self, is_connected: impl Fn(&PeerId) -> bool) -> Option<PeerId> {
+        self.next_where(is_connected)
+            .or_else(|| self.next_where(|_| true))
+    }
+
+    /// Pick the next peer in rotation for which `accept` returns `true`
+    pub fn next_where(&mut self, accept: impl Fn(&PeerId) -> bool) -> Option<PeerId> {
+        let len = self.peers.len();
+        let index = (0..len)
+            .map(|i| (self.cursor + i) % len)
+            .find(|&index| accept(&self.peers[index]))?;
+
+        self.cursor = (index + 1) % len;
+        Some(self.peers[index])
+    }
+}
+
+/// A change in a peer's liveness, as reported by [`Heartbeats::record`] and
+/// [`PingFailures::record`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    /// Failed too many checks in a row and should be forgotten
+    Lost(PeerId),
+    /// Answered again after being dropped, so it can rejoin the pool
+    Recovered(PeerId),
+}
+
+/// Liveness tracking for Leaders based on Ping/Pong heartbeats
+///
+/// Leaders dropped for missing heartbeats are still pinged, so one that comes
+/// back is noticed.
+#[derive(Debug, Default)]
+pub struct Heartbeats {
+    pending: HashMap<OutboundRequestId, PeerId>,
+    misses: HashMap<PeerId, u32>,
+    dropped: HashSet<PeerId>,
+}
+
+impl Heartbeats {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember that a ping was sent to `peer_id`
+    pub fn sent(&mut self, request_id: OutboundRequestId, peer_id: PeerId) {
+        self.pending.insert(request_id, peer_id);
+    }
+
+    /// Whether `request_id` is an outstanding ping
+    pub fn is_heartbeat(&self, request_id: &OutboundRequestId) -> bool {
+        self.pending.contains_key(request_id)
+    }
+
+    /// Peers dropped for missing heartbeats, which should still be pinged
+    pub fn dropped(&self) -> impl Iterator<Item = &PeerId> {
+        self.dropped.iter()
+    }
+
+    /// Record the outcome of a ping, returning a change in the peer's liveness
+    ///
+    /// A Leader counts as alive only if it answered and its backend is healthy.
+    pub fn record(&mut self, request_id: &OutboundRequestId, alive: bool) -> Option<Liveness> {
+        let peer_id = self.pending.remove(request_id)?;
+
+        if alive {
+            self.misses.remove(&peer_id);
+            return self
+                .dropped
+                .remove(&peer_id)
+                .then_some(Liveness::Recovered(peer_id));
+        }
+        if self.dropped.contains(&peer_id) {
+            return None;
+        }
+
+        let misses = self.misses.entry(peer_id).or_insert(0);
+        *misses += 1;
+        if *misses >= MAX_MISSED_HEARTBEATS {
+            self.misses.remove(&peer_id);
+            self.dropped.insert(peer_id);
+            return Some(Liveness::Lost(peer_id));
+        }
+        None
+    }
+
+    /// Forget all state for a peer that is no longer known
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.misses.remove(peer_id);
+        self.dropped.remove(peer_id);
+        self.pending.retain(|_, p| p != peer_id);
+    }
+}
+
+/// Models each Leader advertised in answer to a capabilities query
+///
+/// Leaders that never answered are unknown, and are tried for any model.
+#[derive(Debug, Default)]
+pub struct PeerModels {
+    pending: HashMap<OutboundRequestId, PeerId>,
+    advertised: HashMap<PeerId, Vec<String>>,
+}
+
+impl PeerModels {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember that a capabilities query was sent to `peer_id`
+    pub fn sent(&mut self, request_id: OutboundRequestId, peer_id: PeerId) {
+        self.pending.insert(request_id, peer_id);
+    }
+
+    /// Whether `request_id` is an outstanding capabilities query
+    pub fn is_query(&self, request_id: &OutboundRequestId) -> bool {
+        self.pending.contains_key(request_id)
+    }
+
+    /// Record the answer to a capabilities query, returning the peer it was sent to
+    ///
+    /// `None` means the peer didn't advertise its models, leaving it unknown.
+    pub fn record(
+        &mut self,
+        request_id: &OutboundRequestId,
+        models: Option<Vec<String>>,
+    ) -> Option<PeerId> {
+        let peer_id = self.pending.remove(request_id)?;
+        match models {
+            Some(models) => self.advertised.insert(peer_id, models),
+            None => self.advertised.remove(&peer_id),
+        };
+        Some(peer_id)
+    }
+
+    /// The models `peer_id` advertised, if it answered
+    pub fn advertised(&self, peer_id: &PeerId) -> Option<&[String]> {
+        self.advertised.get(peer_id).map(Vec::as_slice)
+    }
+
+    /// Whether `peer_id` may serve `model`: it advertised it, or is unknown
+    pub fn may_serve(&self, peer_id: &PeerId, model: &str) -> bool {
+        self.advertised
+            .get(peer_id)
+            .is_none_or(|models| models.iter().any(|m| same_model(m, model)))
+    }
+
+    /// Forget all state for a peer that is no longer known
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.advertised.remove(peer_id);
+        self.pending.retain(|_, p| p != peer_id);
+    }
+}
+
+/// What a node knows about a peer it has discovered or is connected to
+#[derive(Debug, Clone, Default)]
+pub struct SeenPeer {
+    pub addresses: Vec<Multiaddr>,
+    /// When discovery first reported the peer, if it still does
+    pub discovered_at: Option<SystemTime>,
+}
+
+/// Every peer a node knows about, for listing to operators
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    peers: HashMap<PeerId, SeenPeer>,
+}
+
+impl PeerTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that discovery reported `peer_id` at `address`
+    pub fn discovered(&mut self, peer_id: PeerId, address: Multiaddr) {
+        let peer = self.add_address(peer_id, address);
+        peer.discovered_at.get_or_insert_with(SystemTime::now);
+    }
+
+    /// Record a connection with `peer_id` at `address`
+    pub fn connected(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.add_address(peer_id, address);
+    }
+
+    /// Record that discovery no longer reports `peer_id`, forgetting it
+    /// unless it is still `connected`
+    pub fn expired(&mut self, peer_id: &PeerId, connected: bool) {
+        if !connected {
+            self.peers.remove(peer_id);
+        } else if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.discovered_at = None;
+        }
+    }
+
+    /// Record that the last connection with `peer_id` closed, forgetting it
+    /// unless discovery still reports it
+    pub fn disconnected(&mut self, peer_id: &PeerId) {
+        if self
+            .peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.discovered_at.is_none())
+        {
+            self.peers.remove(peer_id);
+        }
+    }
+
+    /// Iterate over the known peers
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &SeenPeer)> {
+        self.peers.iter()
+    }
+
+    fn add_address(&mut self, peer_id: PeerId, address: Multiaddr) -> &mut SeenPeer {
+        let peer = self.peers.entry(peer_id).or_default();
+        if !address.is_empty() && !peer.addresses.contains(&address) {
+            peer.addresses.push(address);
+        }
+        peer
+    }
+}
+
+/// PeerIds permitted to send requests to a Leader
+#[derive(Debug, Clone, Default)]
+pub struct PeerAllowlist {
+    peers: HashSet<PeerId>,
+}
+
+impl PeerAllowlist {
+    /// Load an allowlist file of base58 PeerIds
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            anyhow::anyhow!("Failed to read peer allowlist '{}': {}", path.display(), e)
+        })?;
+        Self::parse(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid peer allowlist '{}': {}", path.display(), e))
+    }
+
+    /// Parse one PeerId per line, ignoring blank lines and `#` comments
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut peers = HashSet::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let peer_id = line.parse::<PeerId>().map_err(|e| {
+                anyhow::anyhow!("line {}: '{}' is not a PeerId: {}", index + 1, line, e)
+            })?;
+            peers.insert(peer_id);
+        }
+        Ok(Self { peers })
+    }
+
+    /// Whether `peer_id` may send requests
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(peer_id)
+    }
+
+    /// Number of allowed peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Whether no peer is allowed
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+/// Consecutive keep-alive ping failures per peer
+///
+/// A peer declared dead stays connected and keeps being pinged, so one that
+/// starts answering again is reported as recovered.
+#[derive(Debug)]
+pub struct PingFailures {
+    failures: HashMap<PeerId, u32>,
+    evicted: HashSet<PeerId>,
+    max_failures: u32,
+}
+
+impl PingFailures {
+    /// Track failures, declaring a peer dead after `max_failures` in a row
+    pub fn new(max_failures: u32) -> Self {
+        Self {
+            failures: HashMap::new(),
+            evicted: HashSet::new(),
+            max_failures: max_failures.max(1),
+        }
+    }
+
+    /// Record a ping outcome, returning a change in the peer's liveness
+    pub fn record(&mut self, peer_id: PeerId, ok: bool) -> Option<Liveness> {
+        if ok {
+            self.failures.remove(&peer_id);
+            return self
+                .evicted
+                .remove(&peer_id)
+                .then_some(Liveness::Recovered(peer_id));
+        }
+        if self.evicted.contains(&peer_id) {
+            return None;
+        }
+
+        let failures = self.failures.entry(peer_id).or_insert(0);
+        *failures += 1;
+        if *failures >= self.max_failures {
+            self.failures.remove(&peer_id);
+            self.evicted.insert(peer_id);
+            return Some(Liveness::Lost(peer_id));
+        }
+        None
+    }
+
+    /// Forget a peer whose last connection closed
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.failures.remove(peer_id);
+        self.evicted.remove(peer_id);
+    }
+}
+
+/// Reconnection state of one static peer address
+#[derive(Debug)]
+struct StaticPeer {
+    addr: Multiaddr,
+    /// Peer last reached at this address
+    peer_id: Option<PeerId>,
+    /// Consecutive failed dials since the last connection
+    failures: u32,
+    /// Earliest time the address may be dialled again
+    next_dial: Instant,
+}
+
+/// Peers configured by address, for networks where mDNS can't reach them
+///
+/// Each address is dialled at startup. Failed dials are retried with
+/// exponential backoff up to [`STATIC_REDIAL_MAX_BACKOFF`], and a lost
+/// connection is redialled shortly after, so clusters recover from restarts.
+#[derive(Debug, Default)]
+pub struct StaticPeers {
+    peers: Vec<StaticPeer>,
+    /// Dials in progress, by connection
+    dialing: HashMap<ConnectionId, usize>,
+}
+
+impl StaticPeers {
+    /// Track the given addresses
+    pub fn new(addrs: Vec<Multiaddr>) -> Self {
+        let now = Instant::now();
+        let peers = addrs
+            .into_iter()
+            .map(|addr| StaticPeer {
+                addr,
+                peer_id: None,
+                failures: 0,
+                next_dial: now,
+            })
+            .collect();
+        Self {
+            peers,
+            dialing: HashMap::new(),
+        }
+    }
+
+    /// Whether no static peers are configured
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Dial every address that is due and isn't connected or already being dialled
+    pub fn dial<B: NetworkBehaviour>(&mut self, swarm: &mut Swarm<B>) {
+        let now = Instant::now();
+        for (index, peer) in self.peers.iter_mut().enumerate() {
+            let busy = self.dialing.values().any(|&i| i == index)
+                || peer
+                    .peer_id
+                    .is_some_and(|peer_id| swarm.is_connected(&peer_id));
+            if busy || now < peer.next_dial {
+                continue;
+            }
+
+            let opts = DialOpts::unknown_peer_id()
+                .address(peer.addr.clone())
+                .build();
+            let connection_id = opts.connection_id();
+            match swarm.dial(opts) {
+                Ok(()) => {
+                    self.dialing.insert(connection_id, index);
+                }
+                Err(e) => {
+                    let delay = peer.backoff(now);
+                    tracing::warn!(
+                        "⚠️ Could not dial static peer {} (retrying in {:?}): {}",
+                        peer.addr,
+                        delay,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Note an established connection, returning its address if it was one of our dials
+    pub fn established(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+    ) -> Option<&Multiaddr> {
+        let index = self.dialing.remove(&connection_id)?;
+        let peer = &mut self.peers[index];
+        peer.peer_id = Some(peer_id);
+        peer.failures = 0;
+        Some(&peer.addr)
+    }
+
+    /// Note a failed dial, returning its address and the delay before the
+    /// next attempt if it was one of ours
+    pub fn failed(&mut self, connection_id: ConnectionId) -> Option<(&Multiaddr, Duration)> {
+        let index = self.dialing.remove(&connection_id)?;
+        let peer = &mut self.peers[index];
+        let delay = peer.backoff(Instant::now());
+        Some((&peer.addr, delay))
+    }
+
+    /// Note that the last connection to `peer_id` closed, returning its
+    /// address and the delay before it is redialled if it is a static peer
+    pub fn closed(&mut self, peer_id: &PeerId) -> Option<(&Multiaddr, Duration)> {
+        let peer = self
+            .peers
+            .iter_mut()
+            .find(|peer| peer.peer_id.as_ref() == Some(peer_id))?;
+        peer.next_dial = Instant::now() + STATIC_REDIAL_MIN_BACKOFF;
+        Some((&peer.addr, STATIC_REDIAL_MIN_BACKOFF))
+    }
+}
+
+impl StaticPeer {
+    /// Count a failure and push back the next dial, returning the delay
+    fn backoff(&mut self, now: Instant) -> Duration {
+        self.failures += 1;
+        let delay = STATIC_REDIAL_MIN_BACKOFF
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(STATIC_REDIAL_MAX_BACKOFF);
+        self.next_dial = now + delay;
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{AxonRequest, InferenceCodec, PROTOCOL_NAME};
+    use libp2p::{
+        StreamProtocol,
+        request_response::{self, ProtocolSupport},
+    };
+    use std::iter;
+
+    /// Request ids as a swarm would hand them out for pings to `peer_id`
+    fn ping_ids(peer_id: PeerId, count: usize) -> Vec<OutboundRequestId> {
+        let mut behaviour = request_response::Behaviour::with_codec(
+            InferenceCodec::default(),
+            iter::once((StreamProtocol::new(PROTOCOL_NAME), ProtocolSupport::Full)),
+            request_response::Config::default(),
+        );
+        (0..count)
+            .map(|_| behaviour.send_request(&peer_id, AxonRequest::Ping))
+            .collect()
+    }
+
+    #[test]
+    fn leader_is_lost_after_missed_heartbeats_and_recovers() {
+        let leader = PeerId::random();
+        let ids = ping_ids(leader, MAX_MISSED_HEARTBEATS as usize + 2);
+        let mut heartbeats = Heartbeats::new();
+        for id in &ids {
+            heartbeats.sent(*id, leader);
+        }
+
+        let (missed, rest) = ids.split_at(MAX_MISSED_HEARTBEATS as usize);
+        let (last, earlier) = missed.split_last().unwrap();
+        for id in earlier {
+            assert_eq!(heartbeats.record(id, false), None);
+        }
+        assert_eq!(heartbeats.record(last, false), Some(Liveness::Lost(leader)));
+        assert_eq!(heartbeats.dropped().collect::<Vec<_>>(), [&leader]);
+
+        // Still down: no second report
+        assert_eq!(heartbeats.record(&rest[0], false), None);
+
+        assert_eq!(
+            heartbeats.record(&rest[1], true),
+            Some(Liveness::Recovered(leader))
+        );
+        assert_eq!(heartbeats.dropped().count(), 0);
+    }
+
+    #[test]
+    fn a_good_heartbeat_resets_the_misses() {
+        let leader = PeerId::random();
+        let streak = MAX_MISSED_HEARTBEATS as usize - 1;
+        let mut ids = ping_ids(leader, 2 * streak + 1).into_iter();
+        let mut heartbeats = Heartbeats::new();
+        let mut ping = |alive| {
+            let id = ids.next().unwrap();
+            heartbeats.sent(id, leader);
+            heartbeats.record(&id, alive)
+        };
+
+        for _ in 0..streak {
+            assert_eq!(ping(false), None);
+        }
+        assert_eq!(ping(true), None);
+        for _ in 0..streak {
+            assert_eq!(ping(false), None);
+        }
+    }
+
+    #[test]
+    fn forgotten_leader_is_no_longer_pinged() {
+        let leader = PeerId::random();
+        let ids = ping_ids(leader, MAX_MISSED_HEARTBEATS as usize);
+        let mut heartbeats = Heartbeats::new();
+        for id in &ids {
+            heartbeats.sent(*id, leader);
+            heartbeats.record(id, false);
+        }
+        assert_eq!(heartbeats.dropped().count(), 1);
+
+        heartbeats.forget(&leader);
+        assert_eq!(heartbeats.dropped().count(), 0);
+    }
+
+    #[test]
+    fn allowlist_accepts_only_listed_peers() {
+        let listed = PeerId::random();
+        let stranger = PeerId::random();
+        let allowlist =
+            PeerAllowlist::parse(&format!("# trusted Subordinates\n\n{}  # laptop\n", listed))
+                .unwrap();
+
+        assert_eq!(allowlist.len(), 1);
+        assert!(allowlist.contains(&listed));
+        assert!(!allowlist.contains(&stranger));
+    }
+
+    #[test]
+    fn allowlist_names_the_line_of_a_bad_peer_id() {
+        let error = PeerAllowlist::parse(&format!("{}\nnot-a-peer\n", PeerId::random()))
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            error.starts_with("line 2: 'not-a-peer' is not a PeerId"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn peer_evicted_for_ping_failures_recovers_on_a_good_ping() {
+        let peer = PeerId::random();
+        let mut pings = PingFailures::new(2);
+
+        assert_eq!(pings.record(peer, false), None);
+        assert_eq!(pings.record(peer, false), Some(Liveness::Lost(peer)));
+        assert_eq!(pings.record(peer, false), None);
+        assert_eq!(pings.record(peer, true), Some(Liveness::Recovered(peer)));
+        assert_eq!(pings.record(peer, true), None);
+    }
+
+    #[test]
+    fn disconnected_peer_is_not_reported_as_recovered() {
+        let peer = PeerId::random();
+        let mut pings = PingFailures::new(1);
+
+        assert_eq!(pings.record(peer, false), Some(Liveness::Lost(peer)));
+        pings.forget(&peer);
+        assert_eq!(pings.record(peer, true), None);
+    }
+}
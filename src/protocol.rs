@@ -3,114 +3,1267 @@
 use async_trait::async_trait;
 use libp2p::{StreamProtocol, request_response::Codec};
 use serde::{Deserialize, Serialize};
-use std::io;
+use std::{io, time::Duration};
 
-/// Request sent from Subordinate to Leader
+use crate::ollama::{OllamaOptions, PromptFormat};
+
+/// Protocol name and version negotiated over libp2p
+pub const PROTOCOL_NAME: &str = "/axon/inference/1.1.0";
+
+/// Original protocol: uncompressed frames carrying plain inference messages
+pub const LEGACY_PROTOCOL_NAME: &str = "/axon/inference/1.0.0";
+
+/// Every protocol version this node speaks, most preferred first
+pub const SUPPORTED_PROTOCOLS: [&str; 2] = [PROTOCOL_NAME, LEGACY_PROTOCOL_NAME];
+
+/// Any request a Subordinate can send to a Leader
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AxonRequest {
+    Inference(InferenceRequest),
+    Embedding(EmbeddingRequest),
+    Chat(ChatRequest),
+    Batch(BatchRequest),
+    CountTokens(CountTokensRequest),
+    /// Ask a Leader which models it serves and how busy it is
+    Capabilities,
+    /// Cheap liveness check
+    Ping,
+    /// A request type this node does not know, e.g. from a newer peer
+    #[serde(other)]
+    Unsupported,
+}
+
+/// Any response a Leader can send back to a Subordinate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AxonResponse {
+    Inference(InferenceResponse),
+    Embedding(EmbeddingResponse),
+    Chat(ChatResponse),
+    Batch(BatchResponse),
+    CountTokens(CountTokensResponse),
+    Capabilities(CapabilitiesResponse),
+    /// Answer to a ping, reporting whether the Leader's backend is reachable
+    Pong {
+        backend_healthy: bool,
+    },
+    /// The Leader did not recognise the request type
+    Unsupported {
+        protocol: String,
+    },
+}
+
+impl AxonRequest {
+    /// The model explicitly requested, if any
+    pub fn model(&self) -> Option<&str> {
+        match self {
+            AxonRequest::Inference(request) => request.model.as_deref(),
+            AxonRequest::Embedding(request) => request.model.as_deref(),
+            AxonRequest::Chat(request) => request.model.as_deref(),
+            AxonRequest::Batch(request) => request.model.as_deref(),
+            AxonRequest::CountTokens(request) => request.model.as_deref(),
+            AxonRequest::Capabilities | AxonRequest::Ping | AxonRequest::Unsupported => None,
+        }
+    }
+
+    /// Length in characters of the longest prompt the request asks to run
+    ///
+    /// A conversation counts as one prompt, and a batch or set of embedding
+    /// inputs as its longest one. Requests without a prompt count as 0.
+    pub fn prompt_chars(&self) -> usize {
+        let longest = |prompts: &[String]| {
+            prompts
+                .iter()
+                .map(|prompt| prompt.chars().count())
+                .max()
+                .unwrap_or(0)
+        };
+        let conversation = |messages: &[ChatMessage]| {
+            messages
+                .iter()
+                .map(|message| message.content.chars().count())
+                .sum::<usize>()
+        };
+        match self {
+            AxonRequest::Inference(request) => {
+                request.prompt.chars().count() + request.messages.as_deref().map_or(0, conversation)
+            }
+            AxonRequest::Chat(request) => conversation(&request.messages),
+            AxonRequest::Batch(request) => longest(&request.prompts),
+            AxonRequest::Embedding(request) => longest(&request.input),
+            AxonRequest::CountTokens(request) => request.prompt.chars().count(),
+            AxonRequest::Capabilities | AxonRequest::Ping | AxonRequest::Unsupported => 0,
+        }
+    }
+
+    /// How long the requester waits for the answer, if it said
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            AxonRequest::Inference(request) => request.timeout_secs.map(Duration::from_secs),
+            _ => None,
+        }
+    }
+
+    /// Requested scheduling priority, 0 unless an inference request sets one
+    pub fn priority(&self) -> u8 {
+        match self {
+            AxonRequest::Inference(request) => request.priority.unwrap_or(0),
+            AxonRequest::Batch(request) => request.priority.unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+impl AxonResponse {
+    /// A failed response of the kind matching `request`
+    pub fn failure(request: &AxonRequest, code: ErrorCode, message: String) -> Self {
+        match request {
+            AxonRequest::Embedding(_) => AxonResponse::Embedding(EmbeddingResponse {
+                embeddings: Vec::new(),
+                success: false,
+                error: Some(message),
+                error_code: Some(code),
+            }),
+            AxonRequest::Chat(_) => AxonResponse::Chat(ChatResponse {
+                message: None,
+                success: false,
+                error: Some(message),
+                error_code: Some(code),
+            }),
+            AxonRequest::Batch(_) => AxonResponse::Batch(BatchResponse {
+                results: Vec::new(),
+                success: false,
+                error: Some(message),
+                error_code: Some(code),
+            }),
+            AxonRequest::CountTokens(_) => AxonResponse::CountTokens(CountTokensResponse {
+                tokens: 0,
+                estimated: false,
+                context_length: None,
+                success: false,
+                error: Some(message),
+                error_code: Some(code),
+            }),
+            AxonRequest::Inference(_)
+            | AxonRequest::Capabilities
+            | AxonRequest::Ping
+            | AxonRequest::Unsupported => AxonResponse::Inference(InferenceResponse {
+                response: String::new(),
+                success: false,
+                error: Some(message),
+                error_code: Some(code),
+                stats: None,
+                session_id: None,
+                request_id: match request {
+                    AxonRequest::Inference(request) => request.request_id.clone(),
+                    _ => None,
+                },
+            }),
+        }
+    }
+
+    /// Why the request failed, or `None` if it succeeded
+    pub fn failure_code(&self) -> Option<ErrorCode> {
+        let (success, code) = match self {
+            AxonResponse::Inference(response) => (response.success, response.error_code),
+            AxonResponse::Embedding(response) => (response.success, response.error_code),
+            AxonResponse::Chat(response) => (response.success, response.error_code),
+            AxonResponse::Batch(response) => (response.success, response.error_code),
+            AxonResponse::CountTokens(response) => (response.success, response.error_code),
+            AxonResponse::Capabilities(_) | AxonResponse::Pong { .. } => (true, None),
+            AxonResponse::Unsupported { .. } => (false, Some(ErrorCode::InvalidRequest)),
+        };
+        (!success).then(|| code.unwrap_or(ErrorCode::Internal))
+    }
+}
+
+/// Text generation request sent from Subordinate to Leader
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceRequest {
     pub prompt: String,
     pub model: Option<String>,
+    /// Base64-encoded images for vision models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// Prior conversation turns; when present the Leader uses Ollama's chat API
+    /// and `prompt`, if non-empty, is appended as the final user turn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<ChatMessage>>,
+    /// Scheduling priority on the Leader, higher is served first (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// Conversation the Leader keeps history for; earlier turns are prepended
+    /// and this exchange is added to them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Model parameters, applied over the Leader's defaults for the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+    /// System prompt, template and raw mode; a missing system prompt gets the Leader's default
+    #[serde(flatten)]
+    pub format: PromptFormat,
+    /// Chosen by the Subordinate so both ends log the exchange under the same id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Seconds the requester waits for the answer; the Leader gives up on the
+    /// request once they have passed since it arrived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
 }
 
-/// Response sent from Leader to Subordinate
+/// Text generation response sent from Leader to Subordinate
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceResponse {
     pub response: String,
     pub success: bool,
     pub error: Option<String>,
+    /// Category of `error`, absent from older Leaders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+    /// Token counts and timings, absent from older Leaders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<InferenceStats>,
+    /// Session the exchange belongs to, echoed from the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Echoed from the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Request as sent by 1.0.0 nodes
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyInferenceRequest {
+    prompt: String,
+    model: Option<String>,
+}
+
+/// Response as sent by 1.0.0 nodes
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyInferenceResponse {
+    response: String,
+    success: bool,
+    error: Option<String>,
+}
+
+impl TryFrom<AxonRequest> for LegacyInferenceRequest {
+    type Error = io::Error;
+
+    /// Downgrade a request for a 1.0.0 peer, refusing anything it can't express
+    ///
+    /// The priority, request id and timeout are only hints and are dropped.
+    fn try_from(request: AxonRequest) -> io::Result<Self> {
+        match request {
+            AxonRequest::Inference(request)
+                if request.images.is_empty()
+                    && request.messages.is_none()
+                    && request.session_id.is_none()
+                    && request.options.is_none()
+                    && request.format == PromptFormat::default() =>
+            {
+                Ok(Self {
+                    prompt: request.prompt,
+                    model: request.model,
+                })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Request needs {}; peer only speaks {}",
+                    PROTOCOL_NAME, LEGACY_PROTOCOL_NAME
+                ),
+            )),
+        }
+    }
+}
+
+impl From<LegacyInferenceRequest> for AxonRequest {
+    fn from(request: LegacyInferenceRequest) -> Self {
+        AxonRequest::Inference(InferenceRequest {
+            prompt: request.prompt,
+            model: request.model,
+            images: Vec::new(),
+            messages: None,
+            priority: None,
+            session_id: None,
+            options: None,
+            format: PromptFormat::default(),
+            request_id: None,
+            timeout_secs: None,
+        })
+    }
+}
+
+impl From<AxonResponse> for LegacyInferenceResponse {
+    fn from(response: AxonResponse) -> Self {
+        match response {
+            AxonResponse::Inference(response) => Self {
+                response: response.response,
+                success: response.success,
+                error: response.error,
+            },
+            // 1.0.0 peers only send inference requests, so this is never expected
+            _ => Self {
+                response: String::new(),
+                success: false,
+                error: Some("Response can't be expressed in protocol 1.0.0".to_string()),
+            },
+        }
+    }
 }
 
+impl From<LegacyInferenceResponse> for AxonResponse {
+    fn from(response: LegacyInferenceResponse) -> Self {
+        AxonResponse::Inference(InferenceResponse {
+            response: response.response,
+            success: response.success,
+            error: response.error,
+            error_code: None,
+            stats: None,
+            session_id: None,
+            request_id: None,
+        })
+    }
+}
+
+/// Whether `protocol` is the original 1.0.0 wire format
+fn is_legacy(protocol: &StreamProtocol) -> bool {
+    protocol.as_ref() == LEGACY_PROTOCOL_NAME
+}
+
+/// Decode a JSON payload
+fn decode<M: serde::de::DeserializeOwned>(buffer: &[u8]) -> io::Result<M> {
+    serde_json::from_slice(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Encode a JSON payload
+fn encode<M: Serialize>(message: &M) -> io::Result<Vec<u8>> {
+    serde_json::to_vec(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Machine-readable category for a failed request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    ModelNotFound,
+    BackendUnavailable,
+    Timeout,
+    RateLimited,
+    InvalidRequest,
+    Unauthorized,
+    /// The model's answer doesn't have the requested format
+    InvalidOutput,
+    /// The prompt is longer than the Leader accepts
+    PromptTooLong,
+    /// The requester went away before the answer was ready
+    Cancelled,
+    Internal,
+}
+
+impl ErrorCode {
+    /// Whether the same request may succeed if sent again later
+    pub fn is_retriable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::BackendUnavailable | ErrorCode::Timeout | ErrorCode::RateLimited
+        )
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorCode::ModelNotFound => "model_not_found",
+            ErrorCode::BackendUnavailable => "backend_unavailable",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::InvalidOutput => "invalid_output",
+            ErrorCode::PromptTooLong => "prompt_too_long",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::Internal => "internal",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Token counts and timing statistics for a single generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferenceStats {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_duration_ms: u64,
+    pub tokens_per_second: f64,
+    /// Ollama calls retried after transient failures, absent from older Leaders
+    #[serde(default)]
+    pub retries: u32,
+}
+
+/// Embedding request: one vector is produced per input string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    pub input: Vec<String>,
+    pub model: Option<String>,
+}
+
+/// Embedding response, with `embeddings` in the same order as the request input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Category of `error`, absent from older Leaders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Author of a chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// A single turn in a conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+/// Chat request carrying the full conversation so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub model: Option<String>,
+    /// Model parameters, applied over the Leader's defaults for the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+}
+
+/// Chat response carrying the assistant's reply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatResponse {
+    pub message: Option<ChatMessage>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Category of `error`, absent from older Leaders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Several prompts generated one after another by the same Leader
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub prompts: Vec<String>,
+    pub model: Option<String>,
+    /// Scheduling priority on the Leader, higher is served first (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// Model parameters for every prompt, applied over the Leader's defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+}
+
+/// Per-prompt results of a batch, in request order
+///
+/// `success` covers the batch as a whole; each result carries its own
+/// success and error, so one failed prompt doesn't fail the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResponse {
+    pub results: Vec<InferenceResponse>,
+    pub success: bool,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Ask how many tokens a prompt takes for a model, without generating
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountTokensRequest {
+    pub prompt: String,
+    pub model: Option<String>,
+}
+
+/// Token count for a prompt and the model's context window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountTokensResponse {
+    pub tokens: u64,
+    /// Whether `tokens` is a rough estimate because the backend can't tokenize
+    pub estimated: bool,
+    /// Maximum tokens the model accepts, if known
+    pub context_length: Option<u64>,
+    pub success: bool,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+}
+
+/// What a Leader advertises about itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilitiesResponse {
+    pub models: Vec<String>,
+    pub queue_depth: usize,
+    pub protocol: String,
+    /// Longest prompt accepted, in characters; `None` from older Leaders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_prompt_chars: Option<usize>,
+    /// What the backend has loaded, if it can tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_load: Option<BackendLoad>,
+}
+
+/// Summary of the models a Leader's backend has loaded in memory
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackendLoad {
+    pub loaded_models: Vec<String>,
+    /// Memory used by the loaded models in bytes, in RAM and VRAM together
+    pub memory_bytes: u64,
+    /// Part of `memory_bytes` held in GPU memory
+    pub vram_bytes: u64,
+}
+
+/// Default upper bound on the size of a single framed message (16 MiB)
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default payload size above which frames are zstd-compressed (4 KiB)
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// Frame flag: payload is raw JSON
+const FLAG_NONE: u8 = 0x00;
+
+/// Frame flag: payload is zstd-compressed JSON
+const FLAG_ZSTD: u8 = 0x01;
+
+/// zstd compression level used for outgoing frames
+const ZSTD_LEVEL: i32 = 3;
+
 /// Codec for encoding/decoding inference messages
+///
+/// Each frame is a 4-byte big-endian payload length, a flag byte and the payload.
 #[derive(Debug, Clone)]
-pub struct InferenceCodec;
+pub struct InferenceCodec {
+    max_message_size: usize,
+    compression_threshold: Option<usize>,
+}
+
+impl InferenceCodec {
+    /// Create a codec that rejects frames larger than `max_message_size` bytes
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+        }
+    }
+
+    /// Compress outgoing payloads larger than `threshold` bytes, or never if `None`
+    ///
+    /// Incoming compressed frames are always accepted regardless of this setting.
+    pub fn with_compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Read a frame and return its decoded JSON payload
+    ///
+    /// Legacy frames have no flag byte and are never compressed.
+    async fn read_frame<T>(&self, io: &mut T, legacy: bool) -> io::Result<Vec<u8>>
+    where
+        T: futures::AsyncRead + Unpin + Send,
+    {
+        let mut length_bytes = [0u8; 4];
+        read_part(io, &mut length_bytes, "length prefix").await?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+
+        let mut flag = FLAG_NONE;
+        if !legacy {
+            let mut flag_byte = [0u8; 1];
+            read_part(io, &mut flag_byte, "flag byte").await?;
+            flag = flag_byte[0];
+        }
+
+        // Refuse to allocate before we know the peer is within bounds
+        if length > self.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Message length {} exceeds maximum of {} bytes",
+                    length, self.max_message_size
+                ),
+            ));
+        }
+
+        let mut buffer = vec![0u8; length];
+        read_part(io, &mut buffer, "payload").await?;
+
+        match flag {
+            FLAG_NONE => Ok(buffer),
+            FLAG_ZSTD => decompress(&buffer, self.max_message_size),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown frame flag: {:#04x}", other),
+            )),
+        }
+    }
+
+    /// Write a JSON payload as a single frame, compressing it if it is large enough
+    ///
+    /// Legacy frames have no flag byte and are never compressed.
+    async fn write_frame<T>(&self, io: &mut T, data: Vec<u8>, legacy: bool) -> io::Result<()>
+    where
+        T: futures::AsyncWrite + Unpin + Send,
+    {
+        use futures::AsyncWriteExt;
+
+        let (flag, payload) = match self.compression_threshold {
+            Some(threshold) if !legacy && data.len() > threshold => {
+                (FLAG_ZSTD, zstd::bulk::compress(&data, ZSTD_LEVEL)?)
+            }
+            _ => (FLAG_NONE, data),
+        };
+
+        let length = u32::try_from(payload.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        io.write_all(&length.to_be_bytes()).await?;
+        if !legacy {
+            io.write_all(&[flag]).await?;
+        }
+        io.write_all(&payload).await?;
+        io.close().await?;
+
+        Ok(())
+    }
+}
+
+/// Decompress a zstd payload, refusing output larger than `limit` bytes
+///
+/// The output grows as the stream is decoded rather than being allocated at
+/// the limit up front, so a small frame costs little memory however large
+/// the limit is.
+fn decompress(payload: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let invalid = |e: io::Error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to decompress frame: {}", e),
+        )
+    };
+    let decoder = zstd::stream::read::Decoder::with_buffer(payload).map_err(invalid)?;
+    let mut data = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(invalid)?;
+    if data.len() > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Decompressed message exceeds maximum of {} bytes", limit),
+        ));
+    }
+    Ok(data)
+}
+
+/// Fill `buf` with the next `part` of a frame
+///
+/// A stream that ends early fails with [`io::ErrorKind::UnexpectedEof`] saying
+/// how much of the part arrived, so a dropped connection isn't mistaken for
+/// a malformed message.
+async fn read_part<T>(io: &mut T, buf: &mut [u8], part: &str) -> io::Result<()>
+where
+    T: futures::AsyncRead + Unpin + Send,
+{
+    use futures::AsyncReadExt;
+
+    let mut filled = 0;
+    while filled < buf.len() {
+        match io.read(&mut buf[filled..]).await {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "stream closed before full message read: got {} of {} bytes of the {}",
+                        filled,
+                        buf.len(),
+                        part
+                    ),
+                ));
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl Default for InferenceCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
 
 #[async_trait]
 impl Codec for InferenceCodec {
     type Protocol = StreamProtocol;
-    type Request = InferenceRequest;
-    type Response = InferenceResponse;
+    type Request = AxonRequest;
+    type Response = AxonResponse;
 
     async fn read_request<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Request>
     where
         T: futures::AsyncRead + Unpin + Send,
     {
-        use futures::AsyncReadExt;
-
-        let mut length_bytes = [0u8; 4];
-        io.read_exact(&mut length_bytes).await?;
-        let length = u32::from_be_bytes(length_bytes) as usize;
-
-        let mut buffer = vec![0u8; length];
-        io.read_exact(&mut buffer).await?;
+        let legacy = is_legacy(protocol);
+        let buffer = self.read_frame(io, legacy).await?;
 
-        serde_json::from_slice(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        if legacy {
+            return decode::<LegacyInferenceRequest>(&buffer).map(Into::into);
+        }
+        decode(&buffer)
     }
 
     async fn read_response<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
         T: futures::AsyncRead + Unpin + Send,
     {
-        use futures::AsyncReadExt;
+        let legacy = is_legacy(protocol);
+        let buffer = self.read_frame(io, legacy).await?;
 
-        let mut length_bytes = [0u8; 4];
-        io.read_exact(&mut length_bytes).await?;
-        let length = u32::from_be_bytes(length_bytes) as usize;
-
-        let mut buffer = vec![0u8; length];
-        io.read_exact(&mut buffer).await?;
-
-        serde_json::from_slice(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        if legacy {
+            return decode::<LegacyInferenceResponse>(&buffer).map(Into::into);
+        }
+        decode(&buffer)
     }
 
     async fn write_request<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         req: Self::Request,
     ) -> io::Result<()>
     where
         T: futures::AsyncWrite + Unpin + Send,
     {
-        use futures::AsyncWriteExt;
+        let legacy = is_legacy(protocol);
+        let data = if legacy {
+            encode(&LegacyInferenceRequest::try_from(req)?)?
+        } else {
+            encode(&req)?
+        };
 
-        let data =
-            serde_json::to_vec(&req).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        let length = data.len() as u32;
-        io.write_all(&length.to_be_bytes()).await?;
-        io.write_all(&data).await?;
-        io.close().await?;
-
-        Ok(())
+        self.write_frame(io, data, legacy).await
     }
 
     async fn write_response<T>(
         &mut self,
-        _protocol: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         res: Self::Response,
     ) -> io::Result<()>
     where
         T: futures::AsyncWrite + Unpin + Send,
     {
-        use futures::AsyncWriteExt;
+        let legacy = is_legacy(protocol);
+        let data = if legacy {
+            encode(&LegacyInferenceResponse::from(res))?
+        } else {
+            encode(&res)?
+        };
 
-        let data =
-            serde_json::to_vec(&res).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write_frame(io, data, legacy).await
+    }
+}
 
-        let length = data.len() as u32;
-        io.write_all(&length.to_be_bytes()).await?;
-        io.write_all(&data).await?;
-        io.close().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
 
-        Ok(())
+    fn current() -> StreamProtocol {
+        StreamProtocol::new(PROTOCOL_NAME)
+    }
+
+    fn legacy() -> StreamProtocol {
+        StreamProtocol::new(LEGACY_PROTOCOL_NAME)
+    }
+
+    #[tokio::test]
+    async fn oversized_length_prefix_is_refused() {
+        let mut codec = InferenceCodec::new(1024);
+        let mut frame = 4096u32.to_be_bytes().to_vec();
+        frame.push(FLAG_NONE);
+
+        let error = codec
+            .read_request(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("exceeds maximum of 1024 bytes"));
+    }
+
+    #[tokio::test]
+    async fn oversized_legacy_length_prefix_is_refused() {
+        let mut codec = InferenceCodec::new(1024);
+        let frame = u32::MAX.to_be_bytes().to_vec();
+
+        let error = codec
+            .read_response(&legacy(), &mut Cursor::new(frame))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn frame_at_the_limit_is_read() {
+        let frame = frame_of(AxonRequest::Ping).await;
+        let payload_len = frame.len() - 5;
+
+        let decoded = InferenceCodec::new(payload_len)
+            .read_request(&current(), &mut Cursor::new(frame.clone()))
+            .await
+            .unwrap();
+        let error = InferenceCodec::new(payload_len - 1)
+            .read_request(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(decoded, AxonRequest::Ping));
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn compressed_frame_expanding_past_the_limit_is_refused() {
+        let payload = zstd::bulk::compress(&[b' '; 4096], ZSTD_LEVEL).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.push(FLAG_ZSTD);
+        frame.extend(payload);
+
+        let error = InferenceCodec::new(1024)
+            .read_request(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("exceeds maximum of 1024 bytes"));
+    }
+
+    fn inference(prompt: &str) -> InferenceRequest {
+        InferenceRequest {
+            prompt: prompt.to_string(),
+            model: Some("llama3".to_string()),
+            images: Vec::new(),
+            messages: None,
+            priority: None,
+            session_id: None,
+            options: None,
+            format: PromptFormat::default(),
+            request_id: None,
+            timeout_secs: None,
+        }
+    }
+
+    /// A frame as a 1.0.0 node writes it: length prefix, then plain JSON
+    fn legacy_frame(json: serde_json::Value) -> Vec<u8> {
+        let payload = serde_json::to_vec(&json).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend(payload);
+        frame
+    }
+
+    /// The JSON of a frame written for a 1.0.0 node
+    fn legacy_payload(frame: &[u8]) -> serde_json::Value {
+        let (length, payload) = frame.split_at(4);
+        assert_eq!(
+            u32::from_be_bytes(length.try_into().unwrap()) as usize,
+            payload.len()
+        );
+        serde_json::from_slice(payload).unwrap()
+    }
+
+    async fn written_request(
+        protocol: &StreamProtocol,
+        request: AxonRequest,
+    ) -> io::Result<Vec<u8>> {
+        let mut buffer = Cursor::new(Vec::new());
+        InferenceCodec::default()
+            .write_request(protocol, &mut buffer, request)
+            .await?;
+        Ok(buffer.into_inner())
+    }
+
+    #[tokio::test]
+    async fn request_is_downgraded_for_a_legacy_peer() {
+        let mut request = inference("Hello");
+        request.priority = Some(7);
+        request.request_id = Some("req-1".to_string());
+        request.timeout_secs = Some(30);
+
+        let frame = written_request(&legacy(), AxonRequest::Inference(request))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            legacy_payload(&frame),
+            serde_json::json!({ "prompt": "Hello", "model": "llama3" })
+        );
+    }
+
+    #[tokio::test]
+    async fn request_from_a_legacy_peer_is_upgraded() {
+        let frame = legacy_frame(serde_json::json!({ "prompt": "Hello", "model": null }));
+
+        let request = InferenceCodec::default()
+            .read_request(&legacy(), &mut Cursor::new(frame))
+            .await
+            .unwrap();
+
+        let AxonRequest::Inference(request) = request else {
+            panic!("expected an inference request, got {:?}", request);
+        };
+        assert_eq!(request.prompt, "Hello");
+        assert_eq!(request.model, None);
+        assert!(request.images.is_empty());
+        assert!(request.messages.is_none());
+        assert_eq!(request.priority, None);
+        assert_eq!(request.format, PromptFormat::default());
+    }
+
+    #[tokio::test]
+    async fn requests_a_legacy_peer_cannot_express_are_refused() {
+        let message = ChatMessage {
+            role: ChatRole::User,
+            content: "Hi".to_string(),
+        };
+        let with = |change: &dyn Fn(&mut InferenceRequest)| {
+            let mut request = inference("Hello");
+            change(&mut request);
+            AxonRequest::Inference(request)
+        };
+        let mut refused = vec![
+            with(&|r| r.images = vec!["aGk=".to_string()]),
+            with(&|r| r.messages = Some(vec![message.clone()])),
+            with(&|r| r.session_id = Some("chat".to_string())),
+            with(&|r| r.options = Some(OllamaOptions::default())),
+            with(&|r| r.format.system = Some("Be brief".to_string())),
+            with(&|r| r.format.raw = true),
+        ];
+        refused.push(AxonRequest::Ping);
+        refused.push(AxonRequest::Capabilities);
+
+        for request in refused {
+            let error = written_request(&legacy(), request.clone())
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::Unsupported, "{:?}", request);
+        }
+    }
+
+    #[tokio::test]
+    async fn response_is_downgraded_for_a_legacy_peer() {
+        let response = AxonResponse::Inference(InferenceResponse {
+            response: "Hi there".to_string(),
+            success: true,
+            error: None,
+            error_code: None,
+            stats: None,
+            session_id: Some("chat".to_string()),
+            request_id: Some("req-1".to_string()),
+        });
+        let mut buffer = Cursor::new(Vec::new());
+        InferenceCodec::default()
+            .write_response(&legacy(), &mut buffer, response)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            legacy_payload(&buffer.into_inner()),
+            serde_json::json!({ "response": "Hi there", "success": true, "error": null })
+        );
+    }
+
+    #[tokio::test]
+    async fn response_from_a_legacy_peer_is_upgraded() {
+        let frame = legacy_frame(serde_json::json!({
+            "response": "",
+            "success": false,
+            "error": "model not found",
+        }));
+
+        let response = InferenceCodec::default()
+            .read_response(&legacy(), &mut Cursor::new(frame))
+            .await
+            .unwrap();
+
+        let AxonResponse::Inference(response) = response else {
+            panic!("expected an inference response, got {:?}", response);
+        };
+        assert!(!response.success);
+        assert_eq!(response.error.as_deref(), Some("model not found"));
+        assert_eq!(response.error_code, None);
+    }
+
+    #[tokio::test]
+    async fn current_protocol_keeps_every_field() {
+        let mut request = inference(&"long prompt ".repeat(1000));
+        request.priority = Some(7);
+        request.session_id = Some("chat".to_string());
+        request.timeout_secs = Some(30);
+
+        let frame = written_request(&current(), AxonRequest::Inference(request))
+            .await
+            .unwrap();
+        assert_eq!(frame[4], FLAG_ZSTD);
+
+        let decoded = InferenceCodec::default()
+            .read_request(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap();
+
+        let AxonRequest::Inference(decoded) = decoded else {
+            panic!("expected an inference request, got {:?}", decoded);
+        };
+        assert_eq!(decoded.prompt.len(), 12 * 1000);
+        assert_eq!(decoded.priority, Some(7));
+        assert_eq!(decoded.session_id.as_deref(), Some("chat"));
+        assert_eq!(decoded.timeout_secs, Some(30));
+    }
+
+    async fn round_trip_request(
+        writer: &mut InferenceCodec,
+        reader: &mut InferenceCodec,
+        request: AxonRequest,
+    ) -> (u8, AxonRequest) {
+        let mut buffer = Cursor::new(Vec::new());
+        writer
+            .write_request(&current(), &mut buffer, request)
+            .await
+            .unwrap();
+        let frame = buffer.into_inner();
+        let decoded = reader
+            .read_request(&current(), &mut Cursor::new(frame.clone()))
+            .await
+            .unwrap();
+        (frame[4], decoded)
+    }
+
+    fn prompt_of(request: &AxonRequest) -> &str {
+        match request {
+            AxonRequest::Inference(request) => &request.prompt,
+            other => panic!("expected an inference request, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn payload_under_the_threshold_is_sent_uncompressed() {
+        let mut codec = InferenceCodec::default();
+        let (flag, decoded) = round_trip_request(
+            &mut codec.clone(),
+            &mut codec,
+            AxonRequest::Inference(inference("Hello")),
+        )
+        .await;
+
+        assert_eq!(flag, FLAG_NONE);
+        assert_eq!(prompt_of(&decoded), "Hello");
+    }
+
+    #[tokio::test]
+    async fn request_over_the_threshold_is_compressed_and_restored() {
+        let prompt = "a rather repetitive prompt ".repeat(500);
+        let mut codec = InferenceCodec::default();
+        let (flag, decoded) = round_trip_request(
+            &mut codec.clone(),
+            &mut codec,
+            AxonRequest::Inference(inference(&prompt)),
+        )
+        .await;
+
+        assert_eq!(flag, FLAG_ZSTD);
+        assert_eq!(prompt_of(&decoded), prompt);
+    }
+
+    #[tokio::test]
+    async fn response_over_the_threshold_is_compressed_and_restored() {
+        let text = "a rather repetitive answer ".repeat(500);
+        let response = AxonResponse::Inference(InferenceResponse {
+            response: text.clone(),
+            success: true,
+            error: None,
+            error_code: None,
+            stats: None,
+            session_id: None,
+            request_id: Some("req-1".to_string()),
+        });
+        let mut codec = InferenceCodec::default();
+        let mut buffer = Cursor::new(Vec::new());
+        codec
+            .write_response(&current(), &mut buffer, response)
+            .await
+            .unwrap();
+        let frame = buffer.into_inner();
+        assert_eq!(frame[4], FLAG_ZSTD);
+        assert!(frame.len() < text.len());
+
+        let decoded = codec
+            .read_response(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap();
+
+        let AxonResponse::Inference(decoded) = decoded else {
+            panic!("expected an inference response, got {:?}", decoded);
+        };
+        assert_eq!(decoded.response, text);
+        assert_eq!(decoded.request_id.as_deref(), Some("req-1"));
+    }
+
+    #[tokio::test]
+    async fn writer_without_compression_is_read_by_a_compressing_reader() {
+        let prompt = "a rather repetitive prompt ".repeat(500);
+        let (flag, decoded) = round_trip_request(
+            &mut InferenceCodec::default().with_compression_threshold(None),
+            &mut InferenceCodec::default(),
+            AxonRequest::Inference(inference(&prompt)),
+        )
+        .await;
+
+        assert_eq!(flag, FLAG_NONE);
+        assert_eq!(prompt_of(&decoded), prompt);
+    }
+
+    #[tokio::test]
+    async fn corrupted_zstd_frame_is_invalid_data() {
+        let mut frame = written_request(
+            &current(),
+            AxonRequest::Inference(inference(&"prompt ".repeat(1000))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(frame[4], FLAG_ZSTD);
+        // Overwrite the zstd magic number
+        frame[5..9].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let error = InferenceCodec::default()
+            .read_request(&current(), &mut Cursor::new(frame))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Failed to decompress frame"));
+    }
+
+    #[test]
+    fn prompt_chars_covers_every_request_with_text() {
+        let embedding = AxonRequest::Embedding(EmbeddingRequest {
+            input: vec!["short".to_string(), "the longest één".to_string()],
+            model: None,
+        });
+        let count = AxonRequest::CountTokens(CountTokensRequest {
+            prompt: "count me".to_string(),
+            model: None,
+        });
+        let batch = AxonRequest::Batch(BatchRequest {
+            prompts: vec!["a".to_string(), "abc".to_string()],
+            model: None,
+            priority: None,
+            options: None,
+        });
+
+        assert_eq!(embedding.prompt_chars(), 15);
+        assert_eq!(count.prompt_chars(), 8);
+        assert_eq!(batch.prompt_chars(), 3);
+        assert_eq!(AxonRequest::Ping.prompt_chars(), 0);
+    }
+
+    /// A whole frame for `request` on the current protocol, uncompressed
+    async fn frame_of(request: AxonRequest) -> Vec<u8> {
+        let mut buffer = Cursor::new(Vec::new());
+        InferenceCodec::default()
+            .with_compression_threshold(None)
+            .write_request(&current(), &mut buffer, request)
+            .await
+            .unwrap();
+        buffer.into_inner()
+    }
+
+    async fn read_truncated(frame: &[u8]) -> io::Error {
+        InferenceCodec::default()
+            .read_request(&current(), &mut Cursor::new(frame.to_vec()))
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_unexpected_eof() {
+        let frame = frame_of(AxonRequest::Ping).await;
+
+        for (cut, part) in [(2, "length prefix"), (4, "flag byte"), (8, "payload")] {
+            let error = read_truncated(&frame[..cut]).await;
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof, "{}", cut);
+            assert!(error.to_string().contains(part), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn frame_cut_inside_a_multibyte_character_is_an_unexpected_eof() {
+        let frame = frame_of(AxonRequest::Inference(inference("naïve café 日本語"))).await;
+        let text_start = frame.windows(3).position(|w| w == "日".as_bytes()).unwrap();
+
+        // Stop one and two bytes into the three-byte character
+        for cut in [text_start + 1, text_start + 2] {
+            assert!(std::str::from_utf8(&frame[5..cut]).is_err());
+            let error = read_truncated(&frame[..cut]).await;
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+            assert!(
+                error.to_string().contains(&format!(
+                    "got {} of {} bytes of the payload",
+                    cut - 5,
+                    frame.len() - 5
+                )),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn frame_arriving_a_byte_at_a_time_is_read_whole() {
+        /// Hands out one byte per read, like a slow connection
+        struct Trickle(Cursor<Vec<u8>>);
+
+        impl futures::AsyncRead for Trickle {
+            fn poll_read(
+                mut self: std::pin::Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+                buf: &mut [u8],
+            ) -> std::task::Poll<io::Result<usize>> {
+                let end = buf.len().min(1);
+                std::pin::Pin::new(&mut self.0).poll_read(cx, &mut buf[..end])
+            }
+        }
+
+        let frame = frame_of(AxonRequest::Inference(inference("日本語"))).await;
+        let request = InferenceCodec::default()
+            .read_request(&current(), &mut Trickle(Cursor::new(frame)))
+            .await
+            .unwrap();
+
+        let AxonRequest::Inference(request) = request else {
+            panic!("expected an inference request, got {:?}", request);
+        };
+        assert_eq!(request.prompt, "日本語");
     }
 }
@@ -0,0 +1,161 @@
+//! Per-client rate limiting for the web API
+
+use axum::http::HeaderMap;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Clients tracked before idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Token bucket per client IP, refilled at a steady rate up to the burst size
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    trust_proxy: bool,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allow each client `per_minute` requests a minute, and up to `burst` at once
+    ///
+    /// With `trust_proxy`, clients are told apart by the address a reverse
+    /// proxy adds to `X-Forwarded-For` instead of the connecting address.
+    pub fn new(per_minute: u32, burst: u32, trust_proxy: bool) -> Self {
+        Self {
+            per_second: f64::from(per_minute) / 60.0,
+            burst: f64::from(burst.max(1)),
+            trust_proxy,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The address to rate limit a request from `peer` under
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trust_proxy {
+            return peer.ip();
+        }
+        // The proxy appends the address it saw, so earlier entries may be forged
+        headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .next_back()
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(peer.ip())
+    }
+
+    /// Take a token for `client`, or return how long until one is available
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    /// [`check`](Self::check) as if the time were `now`
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // A bucket that has refilled is the same as a new one
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.per_second,
+        ))
+    }
+
+    /// Tokens in `bucket` at `now`
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn burst_is_honoured_then_refused_until_a_token_refills() {
+        let limiter = RateLimiter::new(60, 3, false);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(CLIENT, start), Ok(()));
+        }
+        assert_eq!(limiter.check_at(CLIENT, start), Err(Duration::from_secs(1)));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check_at(CLIENT, later),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.check_at(CLIENT, start + Duration::from_secs(1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn idle_client_gets_no_more_than_the_burst() {
+        let limiter = RateLimiter::new(60, 2, false);
+        let start = Instant::now();
+        limiter.check_at(CLIENT, start).unwrap();
+
+        let much_later = start + Duration::from_secs(3600);
+        assert_eq!(limiter.check_at(CLIENT, much_later), Ok(()));
+        assert_eq!(limiter.check_at(CLIENT, much_later), Ok(()));
+        assert!(limiter.check_at(CLIENT, much_later).is_err());
+    }
+
+    #[test]
+    fn clients_have_separate_buckets() {
+        let limiter = RateLimiter::new(60, 1, false);
+        let other = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at(CLIENT, now), Ok(()));
+        assert!(limiter.check_at(CLIENT, now).is_err());
+        assert_eq!(limiter.check_at(other, now), Ok(()));
+    }
+
+    #[test]
+    fn forwarded_address_is_used_only_behind_a_trusted_proxy() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 40000));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.9, 192.0.2.1".parse().unwrap());
+
+        assert_eq!(
+            RateLimiter::new(60, 1, false).client_ip(peer, &headers),
+            peer.ip()
+        );
+        assert_eq!(
+            RateLimiter::new(60, 1, true).client_ip(peer, &headers),
+            CLIENT
+        );
+    }
+}
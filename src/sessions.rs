@@ -0,0 +1,279 @@
+//! Conversation history kept by a Leader for Subordinates and web chats
+//! using sessions
+
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::protocol::ChatMessage;
+
+/// Conversation turns per session, scoped to the peer that created them
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: HashMap<(PeerId, String), Session>,
+    ttl: Duration,
+    max_sessions: usize,
+}
+
+#[derive(Debug)]
+struct Session {
+    messages: Vec<ChatMessage>,
+    last_used: Instant,
+}
+
+impl SessionStore {
+    /// Create an empty store
+    ///
+    /// Sessions idle for longer than `ttl` are forgotten, and once more than
+    /// `max_sessions` exist the least recently used is evicted.
+    pub fn new(ttl: Duration, max_sessions: usize) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            ttl,
+            max_sessions: max_sessions.max(1),
+        }
+    }
+
+    /// Turns recorded so far in a session, empty for a new or expired one
+    pub fn history(&mut self, peer_id: PeerId, session_id: &str) -> Vec<ChatMessage> {
+        self.evict_expired();
+        self.sessions
+            .get(&(peer_id, session_id.to_string()))
+            .map(|session| session.messages.clone())
+            .unwrap_or_default()
+    }
+
+    /// Append turns to a session, creating it if needed
+    pub fn record(
+        &mut self,
+        peer_id: PeerId,
+        session_id: &str,
+        turns: impl IntoIterator<Item = ChatMessage>,
+    ) {
+        let session = self
+            .sessions
+            .entry((peer_id, session_id.to_string()))
+            .or_insert_with(|| Session {
+                messages: Vec::new(),
+                last_used: Instant::now(),
+            });
+        session.messages.extend(turns);
+        session.last_used = Instant::now();
+
+        while self.sessions.len() > self.max_sessions {
+            let Some(oldest) = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.sessions.remove(&oldest);
+        }
+    }
+
+    /// Number of live sessions
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Whether no sessions are stored
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    fn evict_expired(&mut self) {
+        let ttl = self.ttl;
+        self.sessions
+            .retain(|_, session| session.last_used.elapsed() < ttl);
+    }
+}
+
+/// Conversation turns of web chat sessions, keyed by a server-chosen id
+///
+/// Each session has its own lock, held while a reply is generated, so
+/// concurrent messages to one session are answered in turn and the history
+/// stays in order.
+#[derive(Debug)]
+pub struct ChatSessions {
+    sessions: Mutex<HashMap<String, ChatEntry>>,
+    ttl: Duration,
+    max_sessions: usize,
+    max_messages: usize,
+}
+
+#[derive(Debug)]
+struct ChatEntry {
+    messages: Arc<tokio::sync::Mutex<Vec<ChatMessage>>>,
+    last_used: Instant,
+}
+
+impl ChatSessions {
+    /// Create an empty store
+    ///
+    /// Sessions idle for longer than `ttl` are forgotten, once more than
+    /// `max_sessions` exist the least recently used is evicted, and each keeps
+    /// at most its `max_messages` latest turns.
+    pub fn new(ttl: Duration, max_sessions: usize, max_messages: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+            max_sessions: max_sessions.max(1),
+            max_messages: max_messages.max(2),
+        }
+    }
+
+    /// Start a new, empty session, returning its id and turns
+    pub fn create(&self) -> (String, Arc<tokio::sync::Mutex<Vec<ChatMessage>>>) {
+        let id = new_session_id();
+        let messages = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict_expired(&mut sessions);
+        sessions.insert(
+            id.clone(),
+            ChatEntry {
+                messages: messages.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        while sessions.len() > self.max_sessions {
+            let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            sessions.remove(&oldest);
+        }
+        (id, messages)
+    }
+
+    /// Turns of a live session, marking it used, or `None` if it doesn't
+    /// exist or has expired
+    pub fn get(&self, id: &str) -> Option<Arc<tokio::sync::Mutex<Vec<ChatMessage>>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict_expired(&mut sessions);
+        let entry = sessions.get_mut(id)?;
+        entry.last_used = Instant::now();
+        Some(entry.messages.clone())
+    }
+
+    /// Forget a session, returning whether it existed
+    pub fn remove(&self, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict_expired(&mut sessions);
+        sessions.remove(id).is_some()
+    }
+
+    /// Drop the oldest turns of `messages` beyond the per-session limit
+    pub fn trim(&self, messages: &mut Vec<ChatMessage>) {
+        let excess = messages.len().saturating_sub(self.max_messages);
+        messages.drain(..excess);
+    }
+
+    fn evict_expired(&self, sessions: &mut HashMap<String, ChatEntry>) {
+        let ttl = self.ttl;
+        sessions.retain(|_, entry| entry.last_used.elapsed() < ttl);
+    }
+}
+
+/// A random 128-bit session id in hex
+fn new_session_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ChatRole;
+    use std::thread::sleep;
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    /// Long enough for sessions created either side of it to have distinct ages
+    const TICK: Duration = Duration::from_millis(5);
+
+    fn turn(content: &str) -> ChatMessage {
+        ChatMessage {
+            role: ChatRole::User,
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn chat_session_expires_after_the_ttl() {
+        let chats = ChatSessions::new(TTL, 8, 8);
+        let (id, _) = chats.create();
+        assert!(chats.get(&id).is_some());
+
+        sleep(TTL * 2);
+
+        assert!(chats.get(&id).is_none());
+        assert!(!chats.remove(&id));
+    }
+
+    #[test]
+    fn using_a_chat_session_keeps_it_alive() {
+        let chats = ChatSessions::new(TTL, 8, 8);
+        let (id, _) = chats.create();
+
+        for _ in 0..4 {
+            sleep(TTL / 4);
+            assert!(chats.get(&id).is_some());
+        }
+    }
+
+    #[test]
+    fn least_recently_used_chat_session_is_evicted() {
+        let chats = ChatSessions::new(Duration::from_secs(60), 2, 8);
+        let (first, _) = chats.create();
+        sleep(TICK);
+        let (second, _) = chats.create();
+        sleep(TICK);
+        chats.get(&first);
+        sleep(TICK);
+        let (third, _) = chats.create();
+
+        assert!(chats.get(&first).is_some());
+        assert!(chats.get(&second).is_none());
+        assert!(chats.get(&third).is_some());
+    }
+
+    #[test]
+    fn trim_keeps_the_latest_turns() {
+        let chats = ChatSessions::new(TTL, 8, 3);
+        let mut messages: Vec<_> = ["a", "b", "c", "d", "e"].into_iter().map(turn).collect();
+
+        chats.trim(&mut messages);
+
+        let kept: Vec<_> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(kept, ["c", "d", "e"]);
+    }
+
+    #[test]
+    fn peer_sessions_expire_and_are_evicted() {
+        let peer = PeerId::random();
+        let mut store = SessionStore::new(TTL, 2);
+        store.record(peer, "a", [turn("one")]);
+        sleep(TICK);
+        store.record(peer, "b", [turn("two")]);
+        sleep(TICK);
+        store.record(peer, "c", [turn("three")]);
+
+        assert_eq!(store.len(), 2);
+        assert!(store.history(peer, "a").is_empty());
+        assert_eq!(store.history(peer, "c").len(), 1);
+        assert!(store.history(PeerId::random(), "c").is_empty());
+
+        sleep(TTL * 2);
+        assert!(store.history(peer, "c").is_empty());
+        assert!(store.is_empty());
+    }
+}
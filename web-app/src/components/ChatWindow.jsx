@@ -3,7 +3,12 @@ import StatusIndicator from './StatusIndicator';
 import MessageBubble from './MessageBubble';
 import InputArea from './InputArea';
 
-const API_BASE = 'http://localhost:3000';
+// Empty when the page is served by the backend itself; set VITE_API_BASE to
+// reach another one. The dev server proxies /api to localhost:3000.
+const API_BASE = import.meta.env.VITE_API_BASE ?? '';
+
+// Sent as a bearer token when the backend is started with --http-api-key
+const API_KEY = import.meta.env.VITE_AXON_API_KEY;
 
 export default function ChatWindow() {
   const [messages, setMessages] = useState([]);
@@ -57,6 +62,7 @@ export default function ChatWindow() {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          ...(API_KEY && { Authorization: `Bearer ${API_KEY}` }),
         },
         body: JSON.stringify({ prompt: text }),
       });